@@ -9,7 +9,7 @@ extern crate test;
 use test::Bencher;
 
 extern crate dir_signature;
-use dir_signature::v1::{Entry, Parser};
+use dir_signature::v1::{Entry, EntryRef, Parser};
 use dir_signature::v1::merge::FileMergeBuilder;
 
 #[bench]
@@ -28,7 +28,7 @@ fn bench_parser_iterator_ubuntu(bencher: &mut Bencher) {
         let mut signature_parser = Parser::new(reader).unwrap();
         for entry in signature_parser.iter() {
             match entry.unwrap() {
-                Entry::Dir(_) => {},
+                Entry::Dir(..) => {},
                 Entry::File{..} => {},
                 Entry::Link(..) => {},
             }
@@ -36,6 +36,26 @@ fn bench_parser_iterator_ubuntu(bencher: &mut Bencher) {
     });
 }
 
+#[bench]
+fn bench_parser_ref_iterator_ubuntu(bencher: &mut Bencher) {
+    let sig_path = get_ubuntu_signature_path();
+    warmup_signature_file(sig_path);
+
+    bencher.iter(|| {
+        let idx_file = File::open(sig_path).unwrap();
+        let reader = BufReader::new(idx_file);
+        let mut signature_parser = Parser::new(reader).unwrap();
+        let mut entry_iter = signature_parser.iter();
+        while let Some(entry) = entry_iter.next_ref() {
+            match entry.unwrap() {
+                EntryRef::Dir(..) => {},
+                EntryRef::File{..} => {},
+                EntryRef::Link{..} => {},
+            }
+        }
+    });
+}
+
 #[bench]
 fn bench_merged_iterator(bencher: &mut Bencher) {
     let sig_path = get_ubuntu_signature_path();
@@ -53,7 +73,7 @@ fn bench_merged_iterator(bencher: &mut Bencher) {
             for (_, entry) in entries {
                 let entry = entry.unwrap();
                 match entry {
-                    Entry::Dir(_) => {},
+                    Entry::Dir(..) => {},
                     Entry::File{..} => {},
                     Entry::Link(..) => {},
                 }
@@ -81,7 +101,7 @@ fn warmup_signature_file(path: &Path) -> (usize, usize, usize) {
     let mut signature_parser = Parser::new(reader).unwrap();
     for entry in signature_parser.iter() {
         match entry.unwrap() {
-            Entry::Dir(_) => num_dirs += 1,
+            Entry::Dir(..) => num_dirs += 1,
             Entry::File{..} => num_files += 1,
             Entry::Link(..) => num_links += 1,
         }
@@ -6,11 +6,14 @@ DIR=${1:-.}
 : ${HASH_CMD:=${HASH}sum}
 : ${HASH_BYTES:=$((HASH_BITS / 8 * 2))}
 : ${BLOCK_SIZE:=32768}
-
-echo "DIRSIGNATURE.v1 $HASH/$HASH_BITS block_size=$BLOCK_SIZE"
+case $HASH in
+    sha256) : ${HASH_NAME:=sha256} ;;
+    *) : ${HASH_NAME:=$HASH/$HASH_BITS} ;;
+esac
 
 exec 3>&1
 final_hash=$({
+    echo "DIRSIGNATURE.v1 $HASH_NAME block_size=$BLOCK_SIZE"
     cd $DIR
     find ./ -type d | tr '/' '\0' | LC_ALL=C sort | tr '\0' '/' | while read dir; do
         echo "/${dir#./}"
@@ -3,16 +3,23 @@ extern crate argparse;
 extern crate env_logger;
 extern crate dir_signature;
 extern crate num_cpus;
+extern crate rustc_serialize;
 
-use std::io::{self, Write};
+use std::collections::BTreeMap;
+use std::io::{self, Write, BufRead, BufReader};
 use std::env;
-use std::fs::File;
+use std::fs::{File, OpenOptions, remove_file};
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
 use std::process::exit;
 
-use argparse::{ArgumentParser, List, ParseOption, Store, StoreTrue, StoreFalse};
+use argparse::{ArgumentParser, List, Collect, Parse, ParseOption, Store};
+use argparse::{StoreTrue, StoreFalse, StoreConst};
 
-use dir_signature::{v1, ScannerConfig, HashType};
+use dir_signature::{v1, ScannerConfig, HashType, Chunking, Compression};
+use dir_signature::v1::diff::{Change, Diff};
+use dir_signature::sign::{SecretKey, Signature, SignError, TrustedKeys};
+use rustc_serialize::json::Json;
 
 
 pub fn run() -> i32 {
@@ -21,15 +28,565 @@ pub fn run() -> i32 {
     }
     env_logger::init().unwrap();
 
+    let mut args = env::args().collect::<Vec<_>>();
+    // subcommand is recognized only as the first argument, so a directory
+    // having the same name can be scanned with `index -- verify`
+    match args.get(1).map(|x| &x[..]) {
+        Some("verify") | Some("diff") | Some("lint") |
+        Some("export-json") | Some("import-json") |
+        Some("keygen") | Some("sign") | Some("verify-signature") => {
+            let cmd = args.remove(1);
+            args[0] = format!("{} {}", args[0], cmd);
+            match &cmd[..] {
+                "verify" => verify(args),
+                "lint" => lint(args),
+                "export-json" => export_json(args),
+                "import-json" => import_json(args),
+                "keygen" => keygen(args),
+                "sign" => sign(args),
+                "verify-signature" => verify_signature(args),
+                _ => diff(args),
+            }
+        }
+        _ => scan(args),
+    }
+}
+
+fn open_index(path: &Path) -> Result<v1::Parser<BufReader<File>>, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Can't open index {:?}: {}", path, e))?;
+    v1::Parser::new(BufReader::new(file))
+        .map_err(|e| format!("Can't parse index {:?}: {}", path, e))
+}
+
+fn diff(args: Vec<String>) -> i32 {
+    let mut old = PathBuf::new();
+    let mut new = PathBuf::new();
+    let mut json = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Show changes between two index files. Exits with 0 if indexes
+            are equal, with 1 if there are differences and with 2 on error.
+        ");
+        ap.refer(&mut old)
+            .add_argument("OLD", Parse, "The old index file")
+            .required();
+        ap.refer(&mut new)
+            .add_argument("NEW", Parse, "The new index file")
+            .required();
+        ap.refer(&mut json)
+            .add_option(&["--json"], StoreTrue,
+                "Print changes as JSON objects, one per line");
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(0) => return 0,
+            Err(_) => return 2,
+        }
+    }
+    let diff = open_index(&old)
+        .and_then(|old| open_index(&new).map(|new| (old, new)))
+        .and_then(|(old, new)| Diff::new(old, new).map_err(|e| e.to_string()));
+    let mut diff = match diff {
+        Ok(diff) => diff,
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Error: {}", e).ok();
+            return 2;
+        }
+    };
+    let out = io::stdout();
+    let mut out = out.lock();
+    let mut changed = false;
+    for change in diff.iter() {
+        let change = match change {
+            Ok(change) => change,
+            Err(e) => {
+                writeln!(&mut io::stderr(), "Error: {}", e).ok();
+                return 2;
+            }
+        };
+        changed = true;
+        if json {
+            writeln!(&mut out, "{}", change_json(&change)).ok();
+        } else {
+            writeln!(&mut out, "{}", change_text(&change)).ok();
+        }
+    }
+    if changed { 1 } else { 0 }
+}
+
+fn change_text(change: &Change) -> String {
+    match *change {
+        Change::Added(ref entry) => {
+            format!("+ {}", entry.path().display())
+        }
+        Change::Removed(ref entry) => {
+            format!("- {}", entry.path().display())
+        }
+        Change::Modified { ref new, ref blocks, .. } if blocks.len() > 0 => {
+            format!("M {} (blocks {})", new.path().display(),
+                blocks.iter().map(|b| b.to_string())
+                .collect::<Vec<_>>().join(", "))
+        }
+        Change::Modified { ref new, .. } => {
+            format!("M {}", new.path().display())
+        }
+    }
+}
+
+fn entry_json(entry: &v1::Entry) -> BTreeMap<String, Json> {
+    let mut obj = BTreeMap::new();
+    match *entry {
+        v1::Entry::Dir(..) => {
+            obj.insert("type".to_string(), Json::String("dir".into()));
+        }
+        v1::Entry::File { exe, size, .. } => {
+            obj.insert("type".to_string(), Json::String("file".into()));
+            obj.insert("exe".to_string(), Json::Boolean(exe));
+            obj.insert("size".to_string(), Json::U64(size));
+        }
+        v1::Entry::Link(_, ref dest, _) => {
+            obj.insert("type".to_string(), Json::String("symlink".into()));
+            obj.insert("target".to_string(),
+                Json::String(dest.to_string_lossy().into_owned()));
+        }
+    }
+    return obj;
+}
+
+fn change_json(change: &Change) -> Json {
+    let mut obj = match *change {
+        Change::Added(ref entry) => {
+            let mut obj = entry_json(entry);
+            obj.insert("change".to_string(), Json::String("added".into()));
+            obj
+        }
+        Change::Removed(ref entry) => {
+            let mut obj = entry_json(entry);
+            obj.insert("change".to_string(), Json::String("removed".into()));
+            obj
+        }
+        Change::Modified { ref old, ref new, ref blocks } => {
+            let mut obj = BTreeMap::new();
+            obj.insert("change".to_string(),
+                Json::String("modified".into()));
+            obj.insert("old".to_string(), Json::Object(entry_json(old)));
+            obj.insert("new".to_string(), Json::Object(entry_json(new)));
+            obj.insert("blocks".to_string(), Json::Array(
+                blocks.iter().map(|&b| Json::U64(b as u64)).collect()));
+            obj
+        }
+    };
+    obj.insert("path".to_string(),
+        Json::String(change.path().to_string_lossy().into_owned()));
+    Json::Object(obj)
+}
+
+fn verify(args: Vec<String>) -> i32 {
+    let mut index = PathBuf::new();
+    let mut dir = PathBuf::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Check that directory matches the index. Exits with 0 if it does,
+            with 1 if there are differences and with 2 on error.
+        ");
+        ap.refer(&mut index)
+            .add_argument("INDEX", Parse, "The index file to check against")
+            .required();
+        ap.refer(&mut dir)
+            .add_argument("DIR", Parse, "The directory to check")
+            .required();
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(0) => return 0,
+            Err(_) => return 2,
+        }
+    }
+    let file = match File::open(&index) {
+        Ok(f) => f,
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Can't open index: {}", e).ok();
+            return 2;
+        }
+    };
+    let report = v1::Parser::new(BufReader::new(file))
+        .map_err(|e| e.to_string())
+        .and_then(|mut p| v1::verify(&mut p, &dir).map_err(|e| e.to_string()));
+    match report {
+        Ok(ref report) if report.is_clean() => 0,
+        Ok(report) => {
+            let out = io::stdout();
+            let mut out = out.lock();
+            for problem in report.problems() {
+                writeln!(&mut out, "{}", problem).ok();
+            }
+            1
+        }
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Error: {}", e).ok();
+            2
+        }
+    }
+}
+
+fn lint(args: Vec<String>) -> i32 {
+    let mut indexes = Vec::<PathBuf>::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Check that index files are sorted, have no duplicate entries
+            and escape names canonically. Exits with 0 if they do,
+            with 1 if there are violations and with 2 on error.
+        ");
+        ap.refer(&mut indexes)
+            .add_argument("INDEX", Collect, "Index files to check")
+            .required();
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(0) => return 0,
+            Err(_) => return 2,
+        }
+    }
+    let out = io::stdout();
+    let mut out = out.lock();
+    let mut result = 0;
+    for path in &indexes {
+        let mut parser = match open_index(path) {
+            Ok(parser) => parser,
+            Err(e) => {
+                writeln!(&mut io::stderr(), "{}", e).ok();
+                return 2;
+            }
+        };
+        parser.strict();
+        for entry in parser.iter() {
+            match entry {
+                Ok(_) => {}
+                Err(v1::ParseError::Parse(ref err, row_num))
+                if err.is_violation() => {
+                    writeln!(&mut out, "{}:{}: {}",
+                        path.display(), row_num, err).ok();
+                    result = 1;
+                }
+                Err(e) => {
+                    writeln!(&mut io::stderr(), "Error in {:?}: {}",
+                        path, e).ok();
+                    return 2;
+                }
+            }
+        }
+    }
+    result
+}
+
+fn export_json(args: Vec<String>) -> i32 {
+    let mut index = PathBuf::new();
+    let mut output = None::<PathBuf>;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Convert the index to JSON Lines, one object per entry.
+            Use `index import-json` to convert it back.
+        ");
+        ap.refer(&mut index)
+            .add_argument("INDEX", Parse, "The index file to convert")
+            .required();
+        ap.refer(&mut output)
+            .add_option(&["-o", "--output"], ParseOption,
+                "The file to write JSON to (default stdout)")
+            .metavar("PATH");
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(x) => return x,
+        }
+    }
+    let result = open_index(&index).and_then(|mut parser| {
+        match output {
+            Some(ref path) => File::create(path)
+                .map_err(|e| format!("Can't create {:?}: {}", path, e))
+                .and_then(|f| {
+                    let mut out = io::BufWriter::new(f);
+                    v1::json::export(&mut parser, &mut out)
+                        .map_err(|e| e.to_string())
+                }),
+            None => {
+                let out = io::stdout();
+                let mut out = out.lock();
+                v1::json::export(&mut parser, &mut out)
+                    .map_err(|e| e.to_string())
+            }
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Error: {}", e).ok();
+            1
+        }
+    }
+}
+
+fn import_json(args: Vec<String>) -> i32 {
+    let mut input = PathBuf::new();
+    let mut output = None::<PathBuf>;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Convert JSON Lines written by `index export-json` back to
+            the index. Fails if the footer doesn't match the contents.
+        ");
+        ap.refer(&mut input)
+            .add_argument("JSON", Parse, "The JSON Lines file to convert")
+            .required();
+        ap.refer(&mut output)
+            .add_option(&["-o", "--write-index"], ParseOption,
+                "The file to write index to (default stdout)")
+            .metavar("PATH");
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(x) => return x,
+        }
+    }
+    let result = File::open(&input)
+        .map_err(|e| format!("Can't open {:?}: {}", input, e))
+        .and_then(|f| {
+            let input = BufReader::new(f);
+            match output {
+                Some(ref path) => File::create(path)
+                    .map_err(|e| format!("Can't create {:?}: {}", path, e))
+                    .and_then(|f| {
+                        let mut out = io::BufWriter::new(f);
+                        v1::json::import(input, &mut out).map_err(|e| {
+                            // don't leave a partial index behind
+                            remove_file(path).ok();
+                            e.to_string()
+                        })
+                    }),
+                None => {
+                    let out = io::stdout();
+                    let mut out = out.lock();
+                    v1::json::import(input, &mut out)
+                        .map_err(|e| e.to_string())
+                }
+            }
+        });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Error: {}", e).ok();
+            1
+        }
+    }
+}
+
+fn keygen(args: Vec<String>) -> i32 {
+    let mut path = PathBuf::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Generate a secret key for signing indexes and print its public
+            key. The public key should be added to trusted keys of the
+            machines which check signatures.
+        ");
+        ap.refer(&mut path)
+            .add_argument("SECRET_KEY", Parse,
+                "The file to write the secret key to, must not exist")
+            .required();
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(x) => return x,
+        }
+    }
+    let key = SecretKey::generate()
+        .map_err(|e| format!("Can't generate key: {}", e))
+        .and_then(|key| {
+            OpenOptions::new().write(true).create_new(true).mode(0o600)
+                .open(&path)
+                .and_then(|mut f| key.write(&mut f))
+                .map_err(|e| format!("Can't write {:?}: {}", path, e))
+                .map(|()| key)
+        });
+    match key {
+        Ok(key) => {
+            println!("{}", key.public_key());
+            0
+        }
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Error: {}", e).ok();
+            1
+        }
+    }
+}
+
+fn sign(args: Vec<String>) -> i32 {
+    let mut index = PathBuf::new();
+    let mut key = PathBuf::new();
+    let mut output = None::<PathBuf>;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Write a detached signature of the index.
+        ");
+        ap.refer(&mut index)
+            .add_argument("INDEX", Parse, "The index file to sign")
+            .required();
+        ap.refer(&mut key)
+            .add_option(&["-k", "--key"], Parse,
+                "The secret key generated by `index keygen`")
+            .metavar("PATH")
+            .required();
+        ap.refer(&mut output)
+            .add_option(&["-o", "--output"], ParseOption,
+                "The file to write signature to (default INDEX.sig)")
+            .metavar("PATH");
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(x) => return x,
+        }
+    }
+    let output = output.unwrap_or_else(|| signature_path(&index));
+    let result = File::open(&key)
+        .map_err(|e| format!("Can't open key {:?}: {}", key, e))
+        .and_then(|f| SecretKey::read(f)
+            .map_err(|e| format!("Can't read key {:?}: {}", key, e)))
+        .and_then(|key| File::open(&index)
+            .map_err(|e| format!("Can't open index {:?}: {}", index, e))
+            .and_then(|mut f| key.sign(&mut f)
+                .map_err(|e| format!("Can't sign {:?}: {}", index, e))))
+        .and_then(|signature| File::create(&output)
+            .and_then(|mut f| signature.write(&mut f))
+            .map_err(|e| format!("Can't write {:?}: {}", output, e)));
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Error: {}", e).ok();
+            1
+        }
+    }
+}
+
+fn verify_signature(args: Vec<String>) -> i32 {
+    let mut index = PathBuf::new();
+    let mut signature = None::<PathBuf>;
+    let mut key_files = Vec::<PathBuf>::new();
+    let mut keys = Vec::<String>::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("
+            Check that the index is signed by one of the trusted keys.
+            Exits with 0 if it is, with 1 if the signature is invalid or
+            made by untrusted key and with 2 on error.
+        ");
+        ap.refer(&mut index)
+            .add_argument("INDEX", Parse, "The index file to check")
+            .required();
+        ap.refer(&mut signature)
+            .add_option(&["-s", "--signature"], ParseOption,
+                "The signature file (default INDEX.sig)")
+            .metavar("PATH");
+        ap.refer(&mut key_files)
+            .add_option(&["--trusted-keys"], Collect,
+                "Read trusted public keys from FILE, one per line.
+                 Can be specified multiple times")
+            .metavar("FILE");
+        ap.refer(&mut keys)
+            .add_option(&["--trusted-key"], Collect,
+                "Trust the hex encoded public KEY.
+                 Can be specified multiple times")
+            .metavar("KEY");
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            Ok(()) => {}
+            Err(0) => return 0,
+            Err(_) => return 2,
+        }
+    }
+    let mut trusted = TrustedKeys::new();
+    for path in &key_files {
+        let res = File::open(path)
+            .map_err(SignError::Io)
+            .and_then(|f| trusted.read(BufReader::new(f)).map(|_| ()));
+        if let Err(e) = res {
+            writeln!(&mut io::stderr(), "Can't read {:?}: {}", path, e).ok();
+            return 2;
+        }
+    }
+    for key in &keys {
+        match key.parse() {
+            Ok(key) => { trusted.add(key); }
+            Err(e) => {
+                writeln!(&mut io::stderr(), "Error: {}", e).ok();
+                return 2;
+            }
+        }
+    }
+    if trusted.is_empty() {
+        writeln!(&mut io::stderr(), "Error: no trusted keys specified").ok();
+        return 2;
+    }
+    let sig_path = signature.unwrap_or_else(|| signature_path(&index));
+    let sig = File::open(&sig_path).map_err(SignError::Io)
+        .and_then(Signature::read);
+    let sig = match sig {
+        Ok(sig) => sig,
+        Err(e) => {
+            writeln!(&mut io::stderr(),
+                "Can't read signature {:?}: {}", sig_path, e).ok();
+            return 2;
+        }
+    };
+    let result = File::open(&index).map_err(SignError::Io)
+        .and_then(|mut f| trusted.verify(&mut f, &sig));
+    match result {
+        Ok(()) => 0,
+        Err(e @ SignError::Mismatch) | Err(e @ SignError::UntrustedKey(_)) |
+        Err(e @ SignError::InvalidIndex(_)) => {
+            writeln!(&mut io::stderr(), "Bad signature: {}", e).ok();
+            1
+        }
+        Err(e) => {
+            writeln!(&mut io::stderr(), "Error: {}", e).ok();
+            2
+        }
+    }
+}
+
+fn signature_path(index: &Path) -> PathBuf {
+    let mut name = index.as_os_str().to_os_string();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+fn scan(args: Vec<String>) -> i32 {
     let mut index = None::<PathBuf>;
     let mut threads = num_cpus::get();
     let mut dirs = Vec::<String>::new();
     let mut hash_type = HashType::Sha512_256;
+    let mut block_size = 32768u64;
+    let mut cdc = false;
+    let mut file_hash = false;
+    let mut dir_hash = false;
+    let mut xattrs = false;
+    let mut compression = Compression::None;
+    let mut lookup = None::<PathBuf>;
     let mut progress = true;
+    let mut exclude = Vec::<String>::new();
+    let mut exclude_from = Vec::<PathBuf>::new();
+    let mut ignore_file = None::<String>;
+    let mut cache_index = None::<PathBuf>;
+    let mut stat_cache = None::<PathBuf>;
+    let mut header = Vec::<String>::new();
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("
             Scan directories of files. And produce consisten hash of them.
+            Use `index verify INDEX DIR` to check a directory against
+            the index and `index diff OLD NEW` to compare two indexes.
+            Use `index export-json INDEX` and `index import-json JSON`
+            to convert the index to JSON Lines and back. Use `index keygen`,
+            `index sign` and `index verify-signature` to sign indexes.
+            Subcommand must be the first argument. To scan a directory
+            named like a subcommand use `index -- DIR`.
         ");
         ap.refer(&mut dirs)
             .add_argument("[PREFIX:]DIR", List, "
@@ -49,14 +606,79 @@ pub fn run() -> i32 {
         ap.refer(&mut hash_type)
             .add_option(&["--hash"], Store,
                 "Use specified hasher.
-                 Options: `sha512/256` (default), `blake2b/256`.")
+                 Options: `sha512/256` (default), `blake2b/256`,
+                 `blake3/256`, `sha256`.")
             .metavar("HASH");
+        ap.refer(&mut block_size)
+            .add_option(&["--block-size"], Store,
+                "Size of the blocks files are hashed by. Must be a power
+                 of two from 512 bytes to 64 MiB (default 32768)")
+            .metavar("BYTES");
+        ap.refer(&mut cdc)
+            .add_option(&["--cdc"], StoreTrue,
+                "Split files into content-defined chunks (FastCDC) instead
+                 of fixed size blocks. Chunks average `--block-size` bytes.
+                 Makes syncing files with inserted data efficient");
+        ap.refer(&mut file_hash)
+            .add_option(&["--file-hash"], StoreTrue,
+                "Also write a hash of the whole contents of every file");
+        ap.refer(&mut dir_hash)
+            .add_option(&["--dir-hash"], StoreTrue,
+                "Write a hash of the whole subtree on every directory line.
+                 Makes comparing indexes faster but writes the index to
+                 a temporary file first");
+        ap.refer(&mut xattrs)
+            .add_option(&["--xattrs"], StoreTrue,
+                "Record extended attributes (including ACLs and file
+                 capabilities) of every file, directory and symlink");
+        ap.refer(&mut compression)
+            .add_option(&["--gzip"], StoreConst(Compression::Gzip),
+                "Compress the index with gzip")
+            .add_option(&["--zstd"], StoreConst(Compression::Zstd),
+                "Compress the index with zstd. Compressed indexes are
+                 detected automatically when read");
+        ap.refer(&mut lookup)
+            .add_option(&["--write-lookup"], ParseOption,
+                "Also write a lookup file for finding entries of the index
+                 by path without reading the whole index. Can't be used
+                 with compression")
+            .metavar("PATH");
         ap.refer(&mut threads)
             .add_option(&["-t", "--threads"], Store,
                 "Number of threads to use for scanning (defaults to a number
                 of CPUs (cores) on the machine")
             .metavar("NUM");
-        match ap.parse_args() {
+        ap.refer(&mut exclude)
+            .add_option(&["--exclude"], Collect,
+                "Exclude files matching gitignore-style PATTERN. Patterns
+                 starting with `!` re-include files excluded by previous
+                 patterns. Can be specified multiple times")
+            .metavar("PATTERN");
+        ap.refer(&mut exclude_from)
+            .add_option(&["--exclude-from"], Collect,
+                "Read exclude patterns from FILE, in gitignore format")
+            .metavar("FILE");
+        ap.refer(&mut ignore_file)
+            .add_option(&["--ignore-file"], ParseOption,
+                "Read exclude patterns from files named NAME in every
+                 scanned directory (e.g. `.dirsignore`)")
+            .metavar("NAME");
+        ap.refer(&mut cache_index)
+            .add_option(&["--cache-index"], ParseOption,
+                "Reuse hashes of unchanged files from the previous index
+                 at PATH. Requires `--stat-cache`")
+            .metavar("PATH");
+        ap.refer(&mut stat_cache)
+            .add_option(&["--stat-cache"], ParseOption,
+                "Read and update metadata of the files used to find out
+                 which files are unchanged since the previous index")
+            .metavar("PATH");
+        ap.refer(&mut header)
+            .add_option(&["--header"], Collect,
+                "Add custom KEY=VALUE pair to the header of the index.
+                 Can be specified multiple times")
+            .metavar("KEY=VALUE");
+        match ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
             Ok(()) => {}
             Err(x) => return x,
         }
@@ -65,15 +687,92 @@ pub fn run() -> i32 {
     let mut cfg = ScannerConfig::new();
     cfg.threads(threads);
     cfg.hash(hash_type);
+    if let Err(e) = cfg.block_size(block_size) {
+        writeln!(&mut io::stderr(), "{}", e).ok();
+        return 1;
+    }
+    if cdc {
+        cfg.chunking(Chunking::FastCdc);
+    }
+    if file_hash {
+        cfg.file_hash();
+    }
+    if dir_hash {
+        cfg.dir_hash();
+    }
+    if xattrs {
+        cfg.xattrs();
+    }
+    cfg.compression(compression);
+    if let Some(ref path) = lookup {
+        if compression != Compression::None {
+            writeln!(&mut io::stderr(),
+                "Lookup file can't be used with a compressed index").ok();
+            return 1;
+        }
+        cfg.lookup_file(path);
+    }
     if progress {
         cfg.print_progress();
     }
-    if dirs.len() > 1 {
-        warn!("Using more than one source dir is not recommended as it's \
-               not implemented properly yet");
+    for path in &exclude_from {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                writeln!(&mut io::stderr(),
+                    "Can't open {:?}: {}", path, e).ok();
+                return 1;
+            }
+        };
+        for line in BufReader::new(file).lines() {
+            match line {
+                Ok(line) => { cfg.exclude(line); }
+                Err(e) => {
+                    writeln!(&mut io::stderr(),
+                        "Can't read {:?}: {}", path, e).ok();
+                    return 1;
+                }
+            }
+        }
+    }
+    for pattern in &exclude {
+        cfg.exclude(pattern);
+    }
+    if let Some(ref name) = ignore_file {
+        cfg.ignore_file(name);
+    }
+    for pair in &header {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => { cfg.header_key(key, value); }
+            _ => {
+                writeln!(&mut io::stderr(),
+                    "Header pair must be in KEY=VALUE format: {:?}", pair
+                ).ok();
+                return 1;
+            }
+        }
+    }
+    match (cache_index, stat_cache) {
+        (Some(cache_index), Some(stat_cache)) => {
+            if index.as_ref() == Some(&cache_index) {
+                writeln!(&mut io::stderr(),
+                    "Previous index must not be overwritten by the new one"
+                ).ok();
+                return 1;
+            }
+            cfg.cache(cache_index, stat_cache);
+        }
+        (None, None) => {}
+        _ => {
+            writeln!(&mut io::stderr(),
+                "Options `--cache-index` and `--stat-cache` \
+                 must be used together").ok();
+            return 1;
+        }
     }
     for dir in dirs.iter() {
-        let mut seq = dir.splitn(1, ':');
+        let mut seq = dir.splitn(2, ':');
         let (prefix, path) = match (seq.next().unwrap(), seq.next()) {
             (prefix, Some(dir)) => (Path::new(prefix), Path::new(dir)),
             (dir, None) => (Path::new("/"), Path::new(dir)),
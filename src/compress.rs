@@ -0,0 +1,153 @@
+//! Transparent compression of index files
+//!
+//! Compressed files are detected by magic bytes, plain index files always
+//! start with `DIRSIGNATURE` so there is no ambiguity.
+use std::io::{self, Read, Write, BufRead, BufReader};
+
+use flate2;
+use flate2::bufread::GzDecoder;
+use flate2::write::GzEncoder;
+use zstd;
+
+use Compression;
+
+const GZIP_MAGIC: &'static [u8] = b"\x1f\x8b";
+const ZSTD_MAGIC: &'static [u8] = b"\x28\xb5\x2f\xfd";
+
+/// Default compression level of zstd
+const ZSTD_LEVEL: i32 = 3;
+
+
+/// Writer which compresses data written to the underlying writer
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<W>),
+}
+
+/// Reader which decompresses data if it's compressed
+pub enum Decoder<R: BufRead> {
+    Plain(R),
+    Gzip(BufReader<GzDecoder<R>>),
+    Zstd(BufReader<zstd::Decoder<R>>),
+}
+
+/// Detects compression by the first bytes of the file
+pub fn detect(data: &[u8]) -> Compression {
+    if data.starts_with(GZIP_MAGIC) {
+        Compression::Gzip
+    } else if data.starts_with(ZSTD_MAGIC) {
+        Compression::Zstd
+    } else {
+        Compression::None
+    }
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(out: W, compression: Compression) -> io::Result<Encoder<W>> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain(out),
+            Compression::Gzip => Encoder::Gzip(
+                GzEncoder::new(out, flate2::Compression::Default)),
+            Compression::Zstd => Encoder::Zstd(
+                zstd::Encoder::new(out, ZSTD_LEVEL)?),
+        })
+    }
+    /// Writes end of the compressed stream
+    ///
+    /// Must be called after all the data is written, otherwise the
+    /// compressed file is truncated.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(out) => Ok(out),
+            Encoder::Gzip(enc) => enc.finish(),
+            Encoder::Zstd(enc) => enc.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Encoder::Plain(ref mut out) => out.write(buf),
+            Encoder::Gzip(ref mut enc) => enc.write(buf),
+            Encoder::Zstd(ref mut enc) => enc.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Encoder::Plain(ref mut out) => out.flush(),
+            Encoder::Gzip(ref mut enc) => enc.flush(),
+            Encoder::Zstd(ref mut enc) => enc.flush(),
+        }
+    }
+}
+
+impl<R: BufRead> Decoder<R> {
+    /// Looks at the start of the data and sets up decompression if needed
+    pub fn new(mut input: R) -> io::Result<Decoder<R>> {
+        let compression = detect(input.fill_buf()?);
+        Ok(match compression {
+            Compression::None => Decoder::Plain(input),
+            Compression::Gzip => Decoder::Gzip(
+                BufReader::new(GzDecoder::new(input)?)),
+            Compression::Zstd => Decoder::Zstd(
+                BufReader::new(zstd::Decoder::with_buffer(input)?)),
+        })
+    }
+    /// Returns the underlying reader if the data is not compressed
+    pub fn plain_mut(&mut self) -> Option<&mut R> {
+        match *self {
+            Decoder::Plain(ref mut input) => Some(input),
+            _ => None,
+        }
+    }
+    /// Returns mutable reference to the underlying reader
+    ///
+    /// The underlying reader may be read ahead of the decompressed data, so
+    /// its position must be restored before reading from the decoder again.
+    pub fn get_mut(&mut self) -> &mut R {
+        match *self {
+            Decoder::Plain(ref mut input) => input,
+            Decoder::Gzip(ref mut dec) => dec.get_mut().get_mut(),
+            Decoder::Zstd(ref mut dec) => dec.get_mut().get_mut(),
+        }
+    }
+    /// Returns the underlying reader
+    ///
+    /// Position of the reader is unspecified for compressed data.
+    pub fn into_inner(self) -> R {
+        match self {
+            Decoder::Plain(input) => input,
+            Decoder::Gzip(dec) => dec.into_inner().into_inner(),
+            Decoder::Zstd(dec) => dec.into_inner().finish(),
+        }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Decoder::Plain(ref mut input) => input.read(buf),
+            Decoder::Gzip(ref mut dec) => dec.read(buf),
+            Decoder::Zstd(ref mut dec) => dec.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Decoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match *self {
+            Decoder::Plain(ref mut input) => input.fill_buf(),
+            Decoder::Gzip(ref mut dec) => dec.fill_buf(),
+            Decoder::Zstd(ref mut dec) => dec.fill_buf(),
+        }
+    }
+    fn consume(&mut self, amt: usize) {
+        match *self {
+            Decoder::Plain(ref mut input) => input.consume(amt),
+            Decoder::Gzip(ref mut dec) => dec.consume(amt),
+            Decoder::Zstd(ref mut dec) => dec.consume(amt),
+        }
+    }
+}
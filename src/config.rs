@@ -1,6 +1,63 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::path::Path;
 
-use {ScannerConfig, HashType};
+use {ScannerConfig, HashType, Error};
+
+/// Smallest block size allowed by `ScannerConfig::block_size`
+pub const MIN_BLOCK_SIZE: u64 = 512;
+/// Largest block size allowed by `ScannerConfig::block_size`
+pub const MAX_BLOCK_SIZE: u64 = 64 << 20;
+
+
+/// Defines what happens when several added directories contain an entry
+/// with the same path
+///
+/// Directories having the same path are always merged. The policy applies
+/// when at least one of the entries is a file or a symlink. A file or a
+/// symlink having the same path as a parent directory of some prefix is
+/// always a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlay {
+    /// Entry from the directory added later replaces earlier ones
+    LastWins,
+    /// Entry from the directory added first is kept
+    FirstWins,
+    /// Scanning fails with `Error::Conflict`
+    Error,
+}
+
+/// Defines how files are split into blocks which are hashed separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Chunking {
+    /// Blocks of the `block_size`, only the last one of the file may be
+    /// shorter
+    Fixed,
+    /// Content-defined chunks found by the FastCDC algorithm
+    ///
+    /// The `block_size` is an average size of the chunks, every chunk
+    /// except the last one is from a quarter to four block sizes long.
+    /// Inserting or removing data in the middle of a file only changes
+    /// chunks around the change, which makes sync of such files much more
+    /// efficient.
+    FastCdc,
+}
+
+/// Defines how the index file is compressed
+///
+/// Parsers detect compression by the magic bytes at the start of the file,
+/// so compressed indexes can be read the same way as plain ones. The
+/// footer is always a hash of the uncompressed text, so compression
+/// doesn't change identity of the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// Plain text index
+    None,
+    /// Index compressed with gzip
+    Gzip,
+    /// Index compressed with zstd (faster and usually smaller than gzip)
+    Zstd,
+}
 
 
 impl ScannerConfig {
@@ -8,13 +65,25 @@ impl ScannerConfig {
     ///
     /// By default we use ``sha512/256`` hasher as it increases
     /// interoperability, but consider using ``blake2b/256`` as it 25% faster
+    /// or ``blake3/256`` which is even faster
     pub fn new() -> ScannerConfig {
         ScannerConfig {
-            threads: 0,
+            threads: 1,
             queue_size: None,
             hash: HashType::Sha512_256,
             block_size: 32768,
+            chunking: Chunking::Fixed,
+            file_hash: false,
+            dir_hash: false,
+            xattrs: false,
+            compression: Compression::None,
+            lookup: None,
+            header_extra: BTreeMap::new(),
             dirs: Vec::new(),
+            overlay: Overlay::LastWins,
+            patterns: Vec::new(),
+            ignore_file: None,
+            cache: None,
             print_progress: false,
         }
     }
@@ -23,6 +92,78 @@ impl ScannerConfig {
         self.hash = hash;
         self
     }
+    /// Set size of the blocks files are split into for hashing
+    ///
+    /// Size must be a power of two from 512 bytes to 64 MiB, otherwise
+    /// `Error::BadBlockSize` is returned and the config is left unchanged.
+    /// Default is 32768. Note that indexes having different block sizes
+    /// can't be merged.
+    pub fn block_size(&mut self, size: u64) -> Result<&mut Self, Error> {
+        if !valid_block_size(size) {
+            return Err(Error::BadBlockSize(size));
+        }
+        self.block_size = size;
+        Ok(self)
+    }
+    /// Set how files are split into blocks
+    ///
+    /// Default is `Chunking::Fixed`. Content-defined chunking is recorded
+    /// in the header of the index as `chunking=fastcdc`.
+    pub fn chunking(&mut self, chunking: Chunking) -> &mut Self {
+        self.chunking = chunking;
+        self
+    }
+    /// Also write a hash of the whole contents of every file
+    ///
+    /// The hash is computed in the same pass as block hashes and is
+    /// recorded in the header of the index as `file_hash=1`. It's useful
+    /// for identifying files by a single hash and for comparing them
+    /// without looking at every block hash.
+    pub fn file_hash(&mut self) -> &mut Self {
+        self.file_hash = true;
+        self
+    }
+    /// Write a hash of the whole subtree on every directory line
+    ///
+    /// Recorded in the header of the index as `dir_hash=1`. It allows to
+    /// skip equal subtrees when comparing indexes. Note that directory
+    /// line is written before its entries, so the index is written to a
+    /// temporary file first and copied to the output when the scan is
+    /// finished.
+    pub fn dir_hash(&mut self) -> &mut Self {
+        self.dir_hash = true;
+        self
+    }
+    /// Record extended attributes of every entry
+    ///
+    /// This includes POSIX ACLs and file capabilities, which are stored as
+    /// extended attributes. Recorded in the header of the index as
+    /// `xattrs=1`. Attributes are only compared by `v1::Diff` and
+    /// `v1::verify` when the index has them.
+    pub fn xattrs(&mut self) -> &mut Self {
+        self.xattrs = true;
+        self
+    }
+    /// Compress the index file
+    ///
+    /// Default is `Compression::None`. The header and the footer are the
+    /// same as in the plain index, only the output stream is compressed.
+    /// Only `v1::scan` supports compression.
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+    /// Write a lookup file for random access to the index by path
+    ///
+    /// The lookup file is written to `path` after the index is finished,
+    /// see `v1::lookup` module for the details. Offsets in the lookup file
+    /// refer to the uncompressed index, so it can't be used together with
+    /// `compression`. Only `v1::scan` supports lookup files, scanning
+    /// fails with `Error::BadLookup` otherwise.
+    pub fn lookup_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.lookup = Some(path.as_ref().to_path_buf());
+        self
+    }
     /// Set number of threads to use for scanning
     ///
     /// Default is 1 which means don't create additional threads run scanning
@@ -33,7 +174,7 @@ impl ScannerConfig {
     }
     /// Set number of index entries that can be queued in the background
     ///
-    /// It only makes sense if threads > 0 and you may need to tweak it only
+    /// It only makes sense if threads > 1 and you may need to tweak it only
     /// in very memory constraint situations
     ///
     /// Default is some value proportional to the number of threads.
@@ -41,10 +182,27 @@ impl ScannerConfig {
         self.queue_size = Some(num);
         self
     }
+    /// Add a custom key value pair to the header of the index
+    ///
+    /// Pairs are written after the `block_size` in sorted order and are
+    /// accounted in the footer hash, so they can be used to attach some
+    /// metadata (like a build id) to the index. Setting the same key again
+    /// replaces the value. Keys and values can't contain whitespace, keys
+    /// can't contain `=` and can't be `block_size`, `chunking`,
+    /// `file_hash`, `dir_hash` or `xattrs`, invalid pairs are reported by
+    /// the scan as `Error::BadHeaderKey`.
+    pub fn header_key<K, V>(&mut self, key: K, value: V) -> &mut Self
+        where K: AsRef<str>, V: AsRef<str>
+    {
+        self.header_extra.insert(key.as_ref().to_string(),
+                                 value.as_ref().to_string());
+        self
+    }
     /// Add a directory to the index
     ///
     /// `prefix` should either be `/` or a subdirectory where indexed files
-    /// will be placed
+    /// will be placed. Parent directories of the prefix are added to the
+    /// index automatically.
     pub fn add_dir<P, R>(&mut self, path: P, prefix: R) -> &mut Self
         where P: AsRef<Path>, R: AsRef<Path>
     {
@@ -52,9 +210,81 @@ impl ScannerConfig {
                         prefix.as_ref().to_path_buf()));
         self
     }
+    /// Set policy for entries having the same path in different
+    /// directories
+    ///
+    /// Default is `Overlay::LastWins`, i.e. directories added later are
+    /// laid over the earlier ones
+    pub fn overlay(&mut self, policy: Overlay) -> &mut Self {
+        self.overlay = policy;
+        self
+    }
+    /// Exclude entries matching a gitignore-style pattern
+    ///
+    /// Patterns are matched against paths in the index (i.e. prefixes
+    /// are taken into account), so `/target` only excludes `target` at
+    /// the root of the index and `*.o` excludes object files at any level.
+    /// Excluded directories are not descended into.
+    ///
+    /// Patterns are applied in the order they are added, the last matching
+    /// one wins. Invalid patterns are reported by the scan as
+    /// `Error::BadPattern`.
+    pub fn exclude<S: AsRef<str>>(&mut self, pattern: S) -> &mut Self {
+        self.patterns.push(pattern.as_ref().to_string());
+        self
+    }
+    /// Include entries matching a gitignore-style pattern
+    ///
+    /// This is the same as `exclude("!pattern")`, i.e. it re-includes
+    /// entries excluded by the patterns added earlier. Note that like with
+    /// git it's impossible to re-include a file if its parent directory is
+    /// excluded.
+    pub fn include<S: AsRef<str>>(&mut self, pattern: S) -> &mut Self {
+        self.patterns.push(format!("!{}", pattern.as_ref()));
+        self
+    }
+    /// Read additional patterns from files named `name` in every
+    /// scanned directory
+    ///
+    /// The file has `.gitignore` syntax and its patterns are relative to the
+    /// directory it's found in. Patterns added with `exclude` and `include`
+    /// take precedence over ones in the files, and files in deeper
+    /// directories take precedence over ones in their parents.
+    ///
+    /// The ignore file itself is indexed unless it's excluded too. Ignore
+    /// files which can't be read (e.g. aren't valid UTF-8) are skipped with
+    /// a warning.
+    pub fn ignore_file<S: AsRef<OsStr>>(&mut self, name: S) -> &mut Self {
+        self.ignore_file = Some(name.as_ref().to_os_string());
+        self
+    }
+    /// Reuse block hashes from the previous index for unchanged files
+    ///
+    /// A file is considered unchanged if its size, modification and change
+    /// times and inode number are the same as recorded in the `stat_cache`
+    /// file.
+    /// The stat cache is (re)written on every scan, so the `index` must be
+    /// the one written by the same scan as the stat cache. Resulting index
+    /// is exactly the same as one produced by a full scan.
+    ///
+    /// If any of the files doesn't exist yet, or the previous index has
+    /// different hash type or block size, all files are hashed. Note that
+    /// previous index must not be the same file the new index is written to.
+    pub fn cache<P, S>(&mut self, index: P, stat_cache: S) -> &mut Self
+        where P: AsRef<Path>, S: AsRef<Path>
+    {
+        self.cache = Some((index.as_ref().to_path_buf(),
+                           stat_cache.as_ref().to_path_buf()));
+        self
+    }
     /// Enable printing progress to stderr
     pub fn print_progress(&mut self) -> &mut Self {
         self.print_progress = true;
         self
     }
 }
+
+fn valid_block_size(size: u64) -> bool {
+    size.is_power_of_two() &&
+        size >= MIN_BLOCK_SIZE && size <= MAX_BLOCK_SIZE
+}
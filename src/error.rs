@@ -1,4 +1,10 @@
 use std::io;
+use std::path::PathBuf;
+
+use ignore;
+
+use config::{MIN_BLOCK_SIZE, MAX_BLOCK_SIZE};
+use v1::ParseError;
 
 /// This is just a common error returned from the library
 ///
@@ -27,10 +33,52 @@ quick_error! {
             description("error reading file")
             display("error reading file: {}", err)
         }
-        /// No root directory
-        // TODO(tailhook) lift this restriction
-        NoRootDirectory {
-            description("no root directory to build index for")
+        /// Same path exists in several directories and at least one of them
+        /// is not a directory
+        Conflict(path: PathBuf) {
+            description("conflicting entries in scanned directories")
+            display("conflicting entries in scanned directories: {:?}", path)
+        }
+        /// Invalid exclude or include pattern
+        BadPattern(err: ignore::Error) {
+            description("invalid exclude pattern")
+            display("invalid exclude pattern: {}", err)
+        }
+        /// Invalid custom header key or value
+        BadHeaderKey(key: String, value: String) {
+            description("invalid header key value pair")
+            display("invalid header key value pair: {:?}={:?}", key, value)
+        }
+        /// Block size is not a power of two or is out of range
+        BadBlockSize(size: u64) {
+            description("invalid block size")
+            display("invalid block size {}, must be a power of two \
+                     from {} to {}", size, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+        }
+        /// Lookup file can't be written together with the index
+        BadLookup(reason: &'static str) {
+            description("lookup file is not supported")
+            display("can't write lookup file: {}", reason)
+        }
+        /// Error reading previous index used as a cache
+        ReadIndex(err: io::Error) {
+            description("error reading previous index")
+            display("error reading previous index: {}", err)
+        }
+        /// Error parsing previous index used as a cache
+        ParseIndex(err: ParseError) {
+            description("error parsing previous index")
+            display("error parsing previous index: {}", err)
+        }
+        /// Error parsing the index being verified
+        VerifyIndex(err: ParseError) {
+            description("error parsing verified index")
+            display("error parsing verified index: {}", err)
+        }
+        /// Name of the custom hash algorithm can't be written to the header
+        BadHashName(name: String) {
+            description("invalid hash name")
+            display("invalid hash name: {:?}", name)
         }
         /// Unsupported hash algorithm
         UnsupportedHash {
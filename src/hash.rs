@@ -0,0 +1,245 @@
+use std::io;
+
+use sha2::{self, Digest};
+use blake2::Blake2b;
+use blake3;
+use typenum::{U64, U32};
+use generic_array::GenericArray;
+use digest_writer::Writer as DWriter;
+
+use {HashType, HashAlgorithm, Hasher};
+
+
+pub trait Hash: HashAlgorithm + Clone + Send + 'static {
+    type HexOutput: AsRef<[u8]>;
+    fn hash_file<F: io::Read>(&self, f: F, block_size: u64)
+        -> io::Result<Self::HexOutput>;
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy)]
+pub struct Sha512_256;
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy)]
+pub struct Blake2b_256;
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy)]
+pub struct Blake3_256;
+
+#[derive(Clone, Copy)]
+pub struct Sha256;
+
+/// Hash algorithm implemented outside of the library
+#[derive(Clone, Copy)]
+pub struct Custom(pub &'static dyn HashAlgorithm);
+
+#[allow(non_camel_case_types)]
+pub struct Sha512_256_Hex(GenericArray<u8, U64>);
+
+#[allow(non_camel_case_types)]
+pub struct Blake2b_256_Hex(GenericArray<u8, U32>);
+
+#[allow(non_camel_case_types)]
+pub struct Blake3_256_Hex(GenericArray<u8, U32>);
+
+pub struct Sha256Hex(GenericArray<u8, U32>);
+
+/// Adapts BLAKE3 hasher to the `Digest` trait used by other hashes
+#[derive(Clone)]
+pub struct Blake3Digest(blake3::Hasher);
+
+/// Adapts `Digest` to the `Hasher` trait, truncating the output
+struct DigestHasher<D>(D, usize);
+
+/// Feeds everything written to the hasher
+struct HasherWriter<'a>(&'a mut dyn Hasher);
+
+impl HashAlgorithm for Sha512_256 {
+    fn name(&self) -> &str {
+        "sha512/256"
+    }
+    fn output_bytes(&self) -> usize {
+        32
+    }
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(DigestHasher(sha2::Sha512::new(), 32))
+    }
+}
+
+impl Hash for Sha512_256 {
+    type HexOutput = Sha512_256_Hex;
+    fn hash_file<F: io::Read>(&self, f: F, block_size: u64)
+        -> io::Result<Sha512_256_Hex>
+    {
+        let mut digest = DWriter::new(sha2::Sha512::new());
+        io::copy(&mut f.take(block_size), &mut digest)?;
+        let d = digest.into_inner();
+        Ok(Sha512_256_Hex(d.result()))
+    }
+}
+
+impl HashAlgorithm for Blake2b_256 {
+    fn name(&self) -> &str {
+        "blake2b/256"
+    }
+    fn output_bytes(&self) -> usize {
+        32
+    }
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(DigestHasher(Blake2b::<U32>::new(), 32))
+    }
+}
+
+impl Hash for Blake2b_256 {
+    type HexOutput = Blake2b_256_Hex;
+    fn hash_file<F: io::Read>(&self, f: F, block_size: u64)
+        -> io::Result<Blake2b_256_Hex>
+    {
+        let mut digest = DWriter::new(Blake2b::new());
+        io::copy(&mut f.take(block_size), &mut digest)?;
+        let d = digest.into_inner();
+        Ok(Blake2b_256_Hex(d.result()))
+    }
+}
+
+impl HashAlgorithm for Blake3_256 {
+    fn name(&self) -> &str {
+        "blake3/256"
+    }
+    fn output_bytes(&self) -> usize {
+        32
+    }
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(DigestHasher(Blake3Digest::new(), 32))
+    }
+}
+
+impl Hash for Blake3_256 {
+    type HexOutput = Blake3_256_Hex;
+    fn hash_file<F: io::Read>(&self, f: F, block_size: u64)
+        -> io::Result<Blake3_256_Hex>
+    {
+        let mut digest = DWriter::new(Blake3Digest::new());
+        io::copy(&mut f.take(block_size), &mut digest)?;
+        let d = digest.into_inner();
+        Ok(Blake3_256_Hex(d.result()))
+    }
+}
+
+impl HashAlgorithm for Sha256 {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+    fn output_bytes(&self) -> usize {
+        32
+    }
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(DigestHasher(sha2::Sha256::new(), 32))
+    }
+}
+
+impl Hash for Sha256 {
+    type HexOutput = Sha256Hex;
+    fn hash_file<F: io::Read>(&self, f: F, block_size: u64)
+        -> io::Result<Sha256Hex>
+    {
+        let mut digest = DWriter::new(sha2::Sha256::new());
+        io::copy(&mut f.take(block_size), &mut digest)?;
+        let d = digest.into_inner();
+        Ok(Sha256Hex(d.result()))
+    }
+}
+
+impl HashAlgorithm for Custom {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+    fn output_bytes(&self) -> usize {
+        self.0.output_bytes()
+    }
+    fn hasher(&self) -> Box<dyn Hasher> {
+        self.0.hasher()
+    }
+}
+
+impl Hash for Custom {
+    type HexOutput = Vec<u8>;
+    fn hash_file<F: io::Read>(&self, f: F, block_size: u64)
+        -> io::Result<Vec<u8>>
+    {
+        let mut hasher = self.0.hasher();
+        io::copy(&mut f.take(block_size), &mut HasherWriter(&mut *hasher))?;
+        Ok(hasher.result())
+    }
+}
+
+impl Digest for Blake3Digest {
+    type OutputSize = U32;
+    fn new() -> Blake3Digest {
+        Blake3Digest(blake3::Hasher::new())
+    }
+    fn input(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    fn result(&self) -> GenericArray<u8, U32> {
+        GenericArray::clone_from_slice(self.0.finalize().as_bytes())
+    }
+}
+
+impl<D: Digest + Send> Hasher for DigestHasher<D> {
+    fn input(&mut self, data: &[u8]) {
+        self.0.input(data)
+    }
+    fn result(&self) -> Vec<u8> {
+        self.0.result()[..self.1].to_vec()
+    }
+}
+
+impl<'a> io::Write for HasherWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.input(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRef<[u8]> for Sha512_256_Hex {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..32]  // Truncated hash!
+    }
+}
+
+impl AsRef<[u8]> for Blake2b_256_Hex {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl AsRef<[u8]> for Blake3_256_Hex {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl AsRef<[u8]> for Sha256Hex {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+/// Creates a hasher for the hash type known only at runtime
+///
+/// It's used where the hash type is only known from the index file
+pub fn hasher(hash_type: HashType) -> Box<dyn Hasher> {
+    match hash_type {
+        HashType::Sha512_256 => Sha512_256.hasher(),
+        HashType::Blake2b_256 => Blake2b_256.hasher(),
+        HashType::Blake3_256 => Blake3_256.hasher(),
+        HashType::Sha256 => Sha256.hasher(),
+        HashType::Custom(alg) => alg.hasher(),
+    }
+}
@@ -1,21 +1,117 @@
+use std::fmt;
+use std::hash::{Hash, Hasher as StdHasher};
 use std::str::FromStr;
 use {Error};
 
 /// A type of hash supported by the library
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone)]
 #[allow(non_camel_case_types)]
 pub enum HashType {
     /// A SHA512 checksum truncated to 256 bits
     Sha512_256,
     /// The 256bits (32 bytes) Blake2b checksum
     Blake2b_256,
+    /// The 256bits (32 bytes) BLAKE3 checksum
+    Blake3_256,
+    /// A plain SHA256 checksum
+    Sha256,
+    /// A hash algorithm implemented outside of the library
+    ///
+    /// Use `HashType::custom` to check the name of the algorithm. Hash
+    /// types are compared by name, so the name can't be one of the
+    /// built-in hash types. To parse signature files using them create
+    /// parser with `v1::Parser::with_hashes`.
+    Custom(&'static dyn HashAlgorithm),
+}
+
+/// A hash algorithm which can be used for signature files
+///
+/// The algorithm is used both for hashing file blocks and for the footer.
+/// Implement it to use a hash that isn't supported by the library:
+///
+/// ```rust
+/// use dir_signature::{HashAlgorithm, Hasher, HashType, ScannerConfig};
+///
+/// struct Xor;
+/// struct XorHasher(u8);
+///
+/// impl HashAlgorithm for Xor {
+///     fn name(&self) -> &str { "xor/8" }
+///     fn output_bytes(&self) -> usize { 1 }
+///     fn hasher(&self) -> Box<dyn Hasher> { Box::new(XorHasher(0)) }
+/// }
+///
+/// impl Hasher for XorHasher {
+///     fn input(&mut self, data: &[u8]) {
+///         for b in data { self.0 ^= *b; }
+///     }
+///     fn result(&self) -> Vec<u8> { vec![self.0] }
+/// }
+///
+/// static XOR: Xor = Xor;
+///
+/// let mut cfg = ScannerConfig::new();
+/// cfg.hash(HashType::custom(&XOR).unwrap());
+/// ```
+pub trait HashAlgorithm: Sync {
+    /// Name of the hash written in the header of the signature file
+    ///
+    /// Must be lower case and must not contain whitespace.
+    fn name(&self) -> &str;
+    /// Size of the digest in bytes
+    fn output_bytes(&self) -> usize;
+    /// Creates a hasher for a new digest
+    fn hasher(&self) -> Box<dyn Hasher>;
+}
+
+/// Incremental hasher created by `HashAlgorithm::hasher`
+pub trait Hasher: Send {
+    /// Feeds data to the hasher
+    fn input(&mut self, data: &[u8]);
+    /// Returns digest of all the data fed so far
+    ///
+    /// Digest must be exactly `HashAlgorithm::output_bytes` long.
+    fn result(&self) -> Vec<u8>;
 }
 
 impl HashType {
+    /// Creates a custom hash type
+    ///
+    /// Returns `Error::BadHashName` if the name of the algorithm is empty,
+    /// isn't lower case or contains whitespace or `=`, because it couldn't
+    /// be written to the header of the signature file. The name of a
+    /// built-in hash type (like `sha256`) is rejected too, because such
+    /// signature file would be read using the built-in hash.
+    pub fn custom(alg: &'static dyn HashAlgorithm)
+        -> Result<HashType, Error>
+    {
+        let name = alg.name();
+        let valid = !name.is_empty() && name.chars().all(|c| {
+            !c.is_whitespace() && !c.is_control() && !c.is_uppercase() &&
+            c != '='
+        }) && name.parse::<HashType>().is_err();
+        if valid {
+            Ok(HashType::Custom(alg))
+        } else {
+            Err(Error::BadHashName(name.to_string()))
+        }
+    }
     /// Get the digest size in bytes
     pub fn output_bytes(self) -> usize {
         match self {
-            HashType::Sha512_256 | HashType::Blake2b_256 => 32,
+            HashType::Sha512_256 | HashType::Blake2b_256 |
+            HashType::Blake3_256 | HashType::Sha256 => 32,
+            HashType::Custom(alg) => alg.output_bytes(),
+        }
+    }
+    /// Get the name of the hash as written in the signature file
+    pub fn name(self) -> &'static str {
+        match self {
+            HashType::Sha512_256 => "sha512/256",
+            HashType::Blake2b_256 => "blake2b/256",
+            HashType::Blake3_256 => "blake3/256",
+            HashType::Sha256 => "sha256",
+            HashType::Custom(alg) => alg.name(),
         }
     }
 }
@@ -26,7 +122,35 @@ impl FromStr for HashType {
         match val {
             "sha512/256" => Ok(HashType::Sha512_256),
             "blake2b/256" => Ok(HashType::Blake2b_256),
+            "blake3/256" => Ok(HashType::Blake3_256),
+            "sha256" => Ok(HashType::Sha256),
             _ => Err(Error::UnsupportedHash),
         }
     }
 }
+
+impl fmt::Debug for HashType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HashType::Sha512_256 => f.write_str("Sha512_256"),
+            HashType::Blake2b_256 => f.write_str("Blake2b_256"),
+            HashType::Blake3_256 => f.write_str("Blake3_256"),
+            HashType::Sha256 => f.write_str("Sha256"),
+            HashType::Custom(alg) => write!(f, "Custom({:?})", alg.name()),
+        }
+    }
+}
+
+impl PartialEq for HashType {
+    fn eq(&self, other: &HashType) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for HashType {}
+
+impl Hash for HashType {
+    fn hash<H: StdHasher>(&self, state: &mut H) {
+        self.name().hash(state)
+    }
+}
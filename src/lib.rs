@@ -2,8 +2,7 @@
 //!
 //! ## v1
 //!
-//! Currently we have only implemented `v1` version of signature file,
-//! it has the following limitations:
+//! The `v1` version of signature file has the following limitations:
 //!
 //! * Only stores executable bit for files, no permissions and ownership
 //!   support (this also means files can be replicated without privileges)
@@ -12,8 +11,7 @@
 //!
 //! While these limitations are not enough for generic backup purposes they
 //! are fine for deploying configs and read-only images to production servers
-//! in 99% use cases. Latter was a primary use case for the library. We will
-//! probably make a more featureful format as v2 and later as deemed necessary.
+//! in 99% use cases. Latter was a primary use case for the library.
 //!
 //! Design of the format features the following things:
 //!
@@ -22,35 +20,61 @@
 //! * Usable for file synchronization
 //! * Can be produced and checked without loading full index into memory
 //!
+//! ## v2
+//!
+//! The `v2` version is a binary format which also stores permissions,
+//! ownership and modification times of files, directories and symlinks.
+//! It's useful when metadata must be replicated too, but the index of the
+//! copy is only equal when metadata is preserved. See `FORMAT.v2.rst`.
+//!
+//! ## Signatures
+//!
+//! The footer proves integrity of the index but not who made it. Indexes
+//! of both versions can be signed with Ed25519 keys, see `sign` module.
+//!
 #![warn(missing_docs)]
-#![recursion_limit="100"]
+#![recursion_limit="200"]
 
 extern crate openat;
+extern crate libc;
 extern crate sha2;
 extern crate blake2;
+extern crate blake3;
 extern crate digest_writer;
 extern crate generic_array;
 extern crate typenum;
-extern crate itertools;
+extern crate ignore;
+extern crate flate2;
+extern crate zstd;
+extern crate rustc_serialize;
+extern crate ed25519_dalek;
 #[macro_use] extern crate log;
 #[macro_use] extern crate quick_error;
 
 #[cfg(test)]
 #[macro_use] extern crate matches;
-#[cfg(test)]
-extern crate rustc_serialize;
 
 
 pub mod v1;
+pub mod v2;
+pub mod sign;
 mod error;
 mod config;
 mod hash_type;
 mod read;
+mod hash;
+mod scan;
+mod xattr;
+mod compress;
 
 pub use error::Error;
-pub use hash_type::HashType;
+pub use config::{Overlay, Chunking, Compression};
+pub use hash_type::{HashType, HashAlgorithm, Hasher};
 pub use read::get_hash;
+pub use xattr::Xattrs;
 
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::path::PathBuf;
 
 /// Scanner config contains a list of directories you will scan and other
@@ -60,6 +84,17 @@ pub struct ScannerConfig {
     queue_size: Option<usize>,
     hash: HashType,
     block_size: u64,
+    chunking: Chunking,
+    file_hash: bool,
+    dir_hash: bool,
+    xattrs: bool,
+    compression: Compression,
+    lookup: Option<PathBuf>,
+    header_extra: BTreeMap<String, String>,
     dirs: Vec<(PathBuf, PathBuf)>,
+    overlay: Overlay,
+    patterns: Vec<String>,
+    ignore_file: Option<OsString>,
+    cache: Option<(PathBuf, PathBuf)>,
     print_progress: bool,
 }
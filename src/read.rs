@@ -1,6 +1,8 @@
 use std::str::{from_utf8, FromStr};
-use std::io::{self, Read, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom, BufReader};
 
+use Compression;
+use compress::{self, Decoder};
 use hash_type::{HashType};
 
 
@@ -14,27 +16,90 @@ fn hex_to_digit(v: u8) -> Option<u8> {
 
 /// Get a hash from an index file
 ///
-/// That hash is a last line of the index file. It may serve either as a
-/// checksum of the file or as identifier if this image/directory
+/// That hash is a last line of the index file (or a footer record for v2
+/// index). It may serve either as a checksum of the file or as identifier
+/// if this image/directory
+///
+/// Compressed index files are supported too, but they have to be
+/// decompressed entirely to find the last line.
 pub fn get_hash<F: Read+Seek>(f: &mut F) -> Result<Vec<u8>, io::Error> {
-    let einval = io::ErrorKind::InvalidData;
     let mut signature = [0u8; 32];
     f.read(&mut signature)?;
-    if &signature[..16] != b"DIRSIGNATURE.v1 " {
-        return Err(einval.into());
+    if compress::detect(&signature) != Compression::None {
+        f.seek(SeekFrom::Start(0))?;
+        return get_compressed_hash(f);
+    }
+    let (binary, hash) = parse_signature(&signature)?;
+    let nbytes = footer_bytes(binary, hash);
+    f.seek(SeekFrom::End(- (nbytes as i64)))?;
+    let mut buf = Vec::with_capacity(nbytes);
+    f.take(nbytes as u64).read_to_end(&mut buf)?;
+    parse_footer(&buf, binary, hash)
+}
+
+fn get_compressed_hash<F: Read>(f: &mut F) -> Result<Vec<u8>, io::Error> {
+    let mut input = Decoder::new(BufReader::new(f))?;
+    let mut signature = [0u8; 32];
+    input.read_exact(&mut signature)?;
+    let (binary, hash) = parse_signature(&signature)?;
+    let nbytes = footer_bytes(binary, hash);
+    // keep only the tail of decompressed data
+    let mut tail = signature.to_vec();
+    let mut buf = [0u8; 65536];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > nbytes {
+            let extra = tail.len() - nbytes;
+            tail.drain(..extra);
+        }
     }
+    parse_footer(&tail, binary, hash)
+}
+
+/// Returns whether file is binary (v2) and its hash type
+fn parse_signature(signature: &[u8]) -> Result<(bool, HashType), io::Error> {
+    let einval = io::ErrorKind::InvalidData;
+    let binary = match &signature[..16] {
+        b"DIRSIGNATURE.v1 " => false,
+        b"DIRSIGNATURE.v2 " => true,
+        _ => return Err(einval.into()),
+    };
     let hash = signature[16..].iter().position(|&x| x == b' ')
         .and_then(|e| from_utf8(&signature[16..16+e]).ok())
         .and_then(|s| HashType::from_str(s).ok())
         .ok_or(einval)?;
+    Ok((binary, hash))
+}
 
-    let nbytes = hash.output_bytes()*2+2;
-    f.seek(SeekFrom::End(- (nbytes as i64)))?;
-    let mut buf = [0u8; 100];
-    assert!(buf.len() >= nbytes);
-    if f.read(&mut buf)? != nbytes {
+/// Size of the footer including the preceding newline or record tag
+fn footer_bytes(binary: bool, hash: HashType) -> usize {
+    if binary {
+        hash.output_bytes()+1
+    } else {
+        hash.output_bytes()*2+2
+    }
+}
+
+fn parse_footer(buf: &[u8], binary: bool, hash: HashType)
+    -> Result<Vec<u8>, io::Error>
+{
+    let einval = io::ErrorKind::InvalidData;
+    let nbytes = footer_bytes(binary, hash);
+    if buf.len() != nbytes {
         return Err(io::ErrorKind::UnexpectedEof.into());
     }
+    if binary {
+        if buf[0] != b'E' {
+            return Err(einval.into());
+        }
+        return Ok(buf[1..].to_vec());
+    }
     if buf[0] != b'\n' || buf[nbytes-1] != b'\n' {
         return Err(einval.into());
     }
@@ -61,16 +126,37 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /subdir
   .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
   file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
-11928917e3e44838af46bad1c7a43a8c16eb26052997f70328d7b07ae4dd6eac
+8459d4b1c12835153c2aa695cfd33395ab3793e1e1e7d015dbdc466d56146d92
 ";
 
     #[test]
     fn read() {
         let hash = get_hash(&mut Cursor::new(DATA)).unwrap();
         assert_eq!(hash,
-            vec![0x11, 0x92, 0x89, 0x17, 0xe3, 0xe4, 0x48, 0x38, 0xaf,
-                 0x46, 0xba, 0xd1, 0xc7, 0xa4, 0x3a, 0x8c, 0x16, 0xeb,
-                 0x26, 0x05, 0x29, 0x97, 0xf7, 0x03, 0x28, 0xd7, 0xb0,
-                 0x7a, 0xe4, 0xdd, 0x6e, 0xac]);
+            vec![0x84, 0x59, 0xd4, 0xb1, 0xc1, 0x28, 0x35, 0x15, 0x3c,
+                 0x2a, 0xa6, 0x95, 0xcf, 0xd3, 0x33, 0x95, 0xab, 0x37,
+                 0x93, 0xe1, 0xe1, 0xe7, 0xd0, 0x15, 0xdb, 0xdc, 0x46,
+                 0x6d, 0x56, 0x14, 0x6d, 0x92]);
+    }
+
+    const DATA_BLAKE3: &'static [u8] = b"\
+DIRSIGNATURE.v1 blake3/256 block_size=32768
+/
+  hello.txt f 6 cd48250d675584e374285667d9c1233b5aa4129afeea215dd47c169aa848d567
+  test.txt f 0
+/subdir
+  .hidden f 7 7c35189aed1a1fc6211843259bb0a5e584d4cbf490f23ddd22e19d23699eb0b5
+  file.txt f 10 9f974bb87d97e1a7b4dcb5ccfdc9e8afa13ab3548f459a828f77e2a9ea7383b2
+83ff6ef7b4d458026c3a629b973cef4e16f7e3443c4752c8f378d4293ed414d2
+";
+
+    #[test]
+    fn read_blake3() {
+        let hash = get_hash(&mut Cursor::new(DATA_BLAKE3)).unwrap();
+        assert_eq!(hash,
+            vec![0x83, 0xff, 0x6e, 0xf7, 0xb4, 0xd4, 0x58, 0x02, 0x6c,
+                 0x3a, 0x62, 0x9b, 0x97, 0x3c, 0xef, 0x4e, 0x16, 0xf7,
+                 0xe3, 0x44, 0x3c, 0x47, 0x52, 0xc8, 0xf3, 0x78, 0xd4,
+                 0x29, 0x3e, 0xd4, 0x14, 0xd2]);
     }
 }
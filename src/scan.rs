@@ -0,0 +1,285 @@
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf, Component};
+use std::sync::Arc;
+use std::collections::{BTreeMap, VecDeque};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use openat::{Dir, Entry};
+
+use {ScannerConfig, Error, Overlay};
+use Error::{OpenDir as EDir, ListDir as EList, ReadFile as ERead};
+
+
+/// Receives entries found by `scan` in the order of the index
+///
+/// Every directory is followed by its files and symlinks sorted by name
+/// and then by its subdirectories.
+pub trait Visitor {
+    /// Starts a directory, `dir` is the source directory which metadata
+    /// should be used, it's `None` for intermediate directories of prefixes
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>;
+    fn add_file(&mut self, dir: &Arc<Dir>, entry: Entry) -> Result<(), Error>;
+    fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
+        -> Result<(), Error>;
+    fn done(&mut self) -> Result<(), Error>;
+}
+
+/// Matchers read from ignore files, the deepest directory first
+type IgnoreStack = Vec<Arc<Gitignore>>;
+
+/// An entry found in one of the source directories
+enum Candidate {
+    /// Intermediate directory of a prefix, has no source directory
+    Prefix,
+    Dir(Arc<Dir>, PathBuf, IgnoreStack),
+    File(Arc<Dir>, Entry),
+    Symlink(Arc<Dir>, Entry),
+}
+
+impl Candidate {
+    fn is_dir(&self) -> bool {
+        match *self {
+            Candidate::Prefix | Candidate::Dir(..) => true,
+            Candidate::File(..) | Candidate::Symlink(..) => false,
+        }
+    }
+}
+
+/// Returns mounted directories with their prefixes normalized to absolute
+/// paths
+fn find_roots(config: &ScannerConfig)
+    -> Result<Vec<(PathBuf, Arc<Dir>)>, Error>
+{
+    let mut roots = Vec::new();
+    for &(ref path, ref prefix) in &config.dirs {
+        roots.push((
+            normalize_prefix(prefix),
+            Arc::new(Dir::open(path).map_err(EDir)?),
+        ));
+    }
+    return Ok(roots);
+}
+
+fn normalize_prefix(prefix: &Path) -> PathBuf {
+    let mut result = PathBuf::from("/");
+    for component in prefix.components() {
+        match component {
+            Component::Normal(name) => result.push(name),
+            Component::ParentDir => { result.pop(); }
+            Component::RootDir | Component::CurDir
+            | Component::Prefix(..) => {}
+        }
+    }
+    return result;
+}
+
+fn build_patterns(config: &ScannerConfig) -> Result<Gitignore, Error> {
+    let mut builder = GitignoreBuilder::new("/");
+    for line in &config.patterns {
+        builder.add_line(None, line).map_err(Error::BadPattern)?;
+    }
+    builder.build().map_err(Error::BadPattern)
+}
+
+/// Reads ignore file `name` from the `dir` which is at `path` in the index
+///
+/// Files which can't be read (e.g. a directory having the same name or a
+/// file that isn't valid UTF-8) are skipped with a warning.
+fn read_ignore_file(dir: &Dir, name: &OsStr, path: &Path)
+    -> Result<Option<Gitignore>, Error>
+{
+    let source = path.join(name);
+    let mut data = String::new();
+    let result = dir.open_file(Path::new(name))
+        .and_then(|mut file| file.read_to_string(&mut data));
+    match result {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            warn!("Can't read ignore file {:?}, skipping: {}", source, e);
+            return Ok(None);
+        }
+    }
+    let mut builder = GitignoreBuilder::new(path);
+    for line in data.lines() {
+        builder.add_line(Some(source.clone()), line)
+            .map_err(Error::BadPattern)?;
+    }
+    builder.build().map(Some).map_err(Error::BadPattern)
+}
+
+/// Checks patterns from the config and then ignore files, the first one
+/// that matches the path decides
+fn is_excluded(patterns: &Gitignore, stack: &IgnoreStack,
+    path: &Path, is_dir: bool)
+    -> bool
+{
+    let matched = patterns.matched(path, is_dir);
+    if !matched.is_none() {
+        return matched.is_ignore();
+    }
+    for ignore in stack {
+        let matched = ignore.matched(path, is_dir);
+        if !matched.is_none() {
+            return matched.is_ignore();
+        }
+    }
+    return false;
+}
+
+/// Applies overlay policy to entries having the same name
+///
+/// Candidates must be sorted by the index of the source directory. All
+/// returned candidates are either directories (which are merged) or there
+/// is a single file or symlink. When a directory wins, only directories
+/// which are not shadowed by a file are merged. A file clashing with the
+/// intermediate directory of a prefix is always a conflict, because the
+/// whole mounted directory would be lost otherwise.
+fn resolve(path: &Path, mut items: Vec<(usize, Candidate)>, overlay: Overlay)
+    -> Result<Vec<(usize, Candidate)>, Error>
+{
+    if items.len() == 1 || items.iter().all(|&(_, ref c)| c.is_dir()) {
+        return Ok(items);
+    }
+    let has_prefix = items.iter().any(|&(_, ref c)| match *c {
+        Candidate::Prefix => true,
+        _ => false,
+    });
+    if has_prefix {
+        return Err(Error::Conflict(path.to_path_buf()));
+    }
+    let winner = match overlay {
+        Overlay::Error => return Err(Error::Conflict(path.to_path_buf())),
+        Overlay::FirstWins => 0,
+        Overlay::LastWins => items.len() - 1,
+    };
+    if items[winner].1.is_dir() {
+        let items = match overlay {
+            Overlay::FirstWins => items.into_iter()
+                .take_while(|&(_, ref c)| c.is_dir())
+                .collect(),
+            _ => {
+                let last_file = items.iter()
+                    .rposition(|&(_, ref c)| !c.is_dir())
+                    .unwrap();
+                items.split_off(last_file + 1)
+            }
+        };
+        Ok(items)
+    } else {
+        Ok(vec![items.swap_remove(winner)])
+    }
+}
+
+pub fn scan<W: Visitor>(config: &ScannerConfig, index: &mut W)
+    -> Result<(), Error>
+{
+    use openat::SimpleType as T;
+    let roots = find_roots(config)?;
+    let patterns = build_patterns(config)?;
+    let mut queue = VecDeque::new();
+
+    queue.push_back((PathBuf::from("/"), Vec::new()));
+
+    while queue.len() > 0 {
+        let (path, mut dirs) = queue.pop_front().unwrap();
+        let mut entries = BTreeMap::<OsString, Vec<_>>::new();
+        for (idx, &(ref prefix, ref root)) in roots.iter().enumerate() {
+            if prefix == &path {
+                dirs.push((idx, root.clone(), PathBuf::from("."),
+                           Vec::new()));
+            } else if let Ok(suffix) = prefix.strip_prefix(&path) {
+                // intermediate directories of the prefix are always
+                // present in the index even if not in any source directory
+                if let Some(name) = suffix.iter().next() {
+                    entries.entry(name.to_os_string())
+                        .or_insert_with(Vec::new)
+                        .push((idx, Candidate::Prefix));
+                }
+            }
+        }
+        dirs.sort_by_key(|&(idx, _, _, _)| idx);
+        // metadata of merged directories is taken from the same source
+        // which would win if they were files
+        let mut meta_dir = None;
+        for (idx, base, name, mut ignores) in dirs {
+            let dir = Arc::new(base.sub_dir(&name).map_err(EList)?);
+            if meta_dir.is_none() || config.overlay != Overlay::FirstWins {
+                meta_dir = Some(dir.clone());
+            }
+            if let Some(ref file_name) = config.ignore_file {
+                if let Some(ign) = read_ignore_file(&dir, file_name, &path)? {
+                    ignores.insert(0, Arc::new(ign));
+                }
+            }
+            for entry in dir.list_dir(".").map_err(EList)? {
+                let entry = entry.map_err(EList)?;
+                let typ = match entry.simple_type() {
+                    Some(x) => x,
+                    None => dir.metadata(&entry).map_err(ERead)?.simple_type(),
+                };
+                let name = entry.file_name().to_os_string();
+                let is_dir = match typ { T::Dir => true, _ => false };
+                if is_excluded(&patterns, &ignores, &path.join(&name), is_dir) {
+                    continue;
+                }
+                let candidate = match typ {
+                    T::Dir => Candidate::Dir(dir.clone(), PathBuf::from(&name),
+                                             ignores.clone()),
+                    T::File => Candidate::File(dir.clone(), entry),
+                    T::Symlink => Candidate::Symlink(dir.clone(), entry),
+                    T::Other => {
+                        let base = dir.recover_path()
+                            // if recover fails, use destination path
+                            // this is better than nothing anyway
+                            .unwrap_or(path.clone());
+                        warn!("File {:?} has unknown type, ignoring",
+                            base.join(entry.file_name()));
+                        continue;
+                    }
+                };
+                entries.entry(name)
+                    .or_insert_with(Vec::new)
+                    .push((idx, candidate));
+            }
+        }
+        let mut files = Vec::new();
+        let mut subdirs = Vec::new();
+        for (name, mut items) in entries {
+            // prefixes were added first, but order of sources matters
+            items.sort_by_key(|&(idx, _)| idx);
+            let items = resolve(&path.join(&name), items, config.overlay)?;
+            if items[0].1.is_dir() {
+                subdirs.push((name, items.into_iter()
+                    .filter_map(|(idx, c)| match c {
+                        Candidate::Dir(base, name, ignores)
+                        => Some((idx, base, name, ignores)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()));
+            } else {
+                files.extend(items.into_iter().map(|(_, c)| c));
+            }
+        }
+        index.start_dir(&path, meta_dir.as_ref())?;
+        for file in files {
+            match file {
+                Candidate::File(dir, entry) => {
+                    index.add_file(&dir, entry)?;
+                }
+                Candidate::Symlink(dir, entry) => {
+                    index.add_symlink(&dir, entry)?;
+                }
+                Candidate::Prefix | Candidate::Dir(..) => unreachable!(),
+            }
+        }
+        // push in reverse order so that directories are visited sorted
+        for (name, seq) in subdirs.into_iter().rev() {
+            queue.push_front((path.join(name), seq));
+        }
+    }
+    index.done()?;
+    Ok(())
+}
@@ -0,0 +1,300 @@
+//! Detached Ed25519 signatures of index files
+//!
+//! The signature is made over the footer hash (see `get_hash`), which is a
+//! hash of the whole index including the header. The whole index is read
+//! both when signing and when checking the signature, to make sure that the
+//! entries match the footer. So both v1 and v2 indexes can be signed,
+//! either compressed or not. The signature file is a single line:
+//!
+//! ```text
+//! DIRSIGNATURE-SIGNATURE.v1 ed25519 <public key> <signature>
+//! ```
+//!
+//! Where the public key of the signer and the signature are hex encoded.
+//! Secret and public keys are stored as a single hex encoded line too.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write, BufRead, BufReader, Cursor};
+use std::str::FromStr;
+
+use ed25519_dalek as ed25519;
+use rustc_serialize::hex::{FromHex, ToHex};
+
+use compress::Decoder;
+use v1;
+use v2;
+
+
+const SIGNATURE_MAGIC: &'static str = "DIRSIGNATURE-SIGNATURE.v1";
+const ALGORITHM: &'static str = "ed25519";
+
+
+quick_error! {
+    /// Error signing an index or checking its signature
+    #[derive(Debug)]
+    pub enum SignError {
+        /// Error reading the index or a key
+        Io(err: io::Error) {
+            description("Io error")
+            display("Io error: {}", err)
+            from()
+        }
+        /// Key can't be parsed
+        InvalidKey(msg: String) {
+            description("invalid key")
+            display("invalid key: {}", msg)
+        }
+        /// Signature file can't be parsed
+        InvalidSignature(msg: String) {
+            description("invalid signature file")
+            display("invalid signature file: {}", msg)
+        }
+        /// Index can't be parsed or its entries don't match the footer
+        InvalidIndex(msg: String) {
+            description("invalid index")
+            display("invalid index: {}", msg)
+        }
+        /// Signature is made by a key which is not trusted
+        UntrustedKey(key: PublicKey) {
+            description("signature is made by untrusted key")
+            display("signature is made by untrusted key {}", key)
+        }
+        /// Signature doesn't match the index
+        Mismatch {
+            description("signature doesn't match the index")
+        }
+    }
+}
+
+/// Secret key used for signing indexes
+pub struct SecretKey {
+    secret: ed25519::SecretKey,
+    public: ed25519::PublicKey,
+}
+
+/// Public key used for checking signatures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(ed25519::PublicKey);
+
+/// Detached signature of an index file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    key: PublicKey,
+    signature: ed25519::Signature,
+}
+
+/// A set of public keys whose signatures are accepted
+#[derive(Debug, Clone, Default)]
+pub struct TrustedKeys {
+    keys: Vec<PublicKey>,
+}
+
+impl SecretKey {
+    /// Generates a new key using random bytes of the operating system
+    pub fn generate() -> io::Result<SecretKey> {
+        let mut bytes = [0u8; ed25519::SECRET_KEY_LENGTH];
+        File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        Ok(SecretKey::from_bytes(&bytes).expect("any 32 bytes are valid"))
+    }
+
+    /// Creates key from 32 bytes of the secret key
+    pub fn from_bytes(bytes: &[u8]) -> Result<SecretKey, SignError> {
+        let secret = ed25519::SecretKey::from_bytes(bytes)
+            .map_err(|e| SignError::InvalidKey(e.to_string()))?;
+        let public = ed25519::PublicKey::from(&secret);
+        Ok(SecretKey {
+            secret: secret,
+            public: public,
+        })
+    }
+
+    /// Reads key written by `SecretKey::write`
+    pub fn read<R: Read>(mut reader: R) -> Result<SecretKey, SignError> {
+        let mut data = String::new();
+        reader.read_to_string(&mut data)?;
+        SecretKey::from_bytes(&parse_hex(data.trim())
+            .map_err(SignError::InvalidKey)?)
+    }
+
+    /// Writes key as a hex encoded line
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{}\n", self.secret.as_bytes().to_hex())
+    }
+
+    /// Returns public key which is used to check signatures
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.public)
+    }
+
+    /// Signs the index file
+    ///
+    /// The whole index is read, `SignError::InvalidIndex` is returned if
+    /// the entries don't match the footer.
+    pub fn sign<R: Read>(&self, index: R) -> Result<Signature, SignError> {
+        let message = read_message(BufReader::new(index))?;
+        let expanded = ed25519::ExpandedSecretKey::from(&self.secret);
+        Ok(Signature {
+            key: self.public_key(),
+            signature: expanded.sign(&message, &self.public),
+        })
+    }
+}
+
+impl PublicKey {
+    /// Creates key from 32 bytes of the public key
+    pub fn from_bytes(bytes: &[u8]) -> Result<PublicKey, SignError> {
+        ed25519::PublicKey::from_bytes(bytes)
+            .map(PublicKey)
+            .map_err(|e| SignError::InvalidKey(e.to_string()))
+    }
+
+    /// Returns bytes of the key
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.as_bytes().to_hex())
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = SignError;
+    fn from_str(value: &str) -> Result<PublicKey, SignError> {
+        PublicKey::from_bytes(&parse_hex(value)
+            .map_err(SignError::InvalidKey)?)
+    }
+}
+
+impl Signature {
+    /// Reads signature file
+    pub fn read<R: Read>(mut reader: R) -> Result<Signature, SignError> {
+        let mut data = String::new();
+        reader.read_to_string(&mut data)?;
+        let mut parts = data.trim().split(' ');
+        match (parts.next(), parts.next(), parts.next(), parts.next(),
+               parts.next())
+        {
+            (Some(SIGNATURE_MAGIC), Some(ALGORITHM),
+             Some(key), Some(signature), None) => {
+                let key = key.parse()?;
+                let signature = parse_hex(signature)
+                    .and_then(|bytes| ed25519::Signature::from_bytes(&bytes)
+                        .map_err(|e| e.to_string()))
+                    .map_err(SignError::InvalidSignature)?;
+                Ok(Signature {
+                    key: key,
+                    signature: signature,
+                })
+            }
+            (Some(SIGNATURE_MAGIC), Some(alg), _, _, _) if alg != ALGORITHM
+            => {
+                Err(SignError::InvalidSignature(
+                    format!("unsupported algorithm {:?}", alg)))
+            }
+            _ => Err(SignError::InvalidSignature(
+                format!("expected {} line", SIGNATURE_MAGIC))),
+        }
+    }
+
+    /// Writes signature file
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{} {} {} {}\n", SIGNATURE_MAGIC, ALGORITHM,
+            self.key, self.signature.to_bytes().to_hex())
+    }
+
+    /// Returns public key of the signer
+    pub fn get_key(&self) -> &PublicKey {
+        &self.key
+    }
+}
+
+impl TrustedKeys {
+    /// Creates an empty set, which doesn't accept any signature
+    pub fn new() -> TrustedKeys {
+        TrustedKeys { keys: Vec::new() }
+    }
+
+    /// Adds a trusted key
+    pub fn add(&mut self, key: PublicKey) -> &mut Self {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+        self
+    }
+
+    /// Reads keys from a file, one hex encoded key per line
+    ///
+    /// Empty lines and lines starting with `#` are skipped.
+    pub fn read<R: BufRead>(&mut self, reader: R)
+        -> Result<&mut Self, SignError>
+    {
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.add(line.parse()?);
+        }
+        Ok(self)
+    }
+
+    /// Returns `true` if there are no trusted keys
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks that the index is signed by one of the trusted keys
+    ///
+    /// The whole index is read, `SignError::InvalidIndex` is returned if
+    /// the entries don't match the footer.
+    pub fn verify<R: Read>(&self, index: R, signature: &Signature)
+        -> Result<(), SignError>
+    {
+        if !self.keys.contains(&signature.key) {
+            return Err(SignError::UntrustedKey(signature.key));
+        }
+        let message = read_message(BufReader::new(index))?;
+        signature.key.0.verify_strict(&message, &signature.signature)
+            .map_err(|_| SignError::Mismatch)
+    }
+}
+
+/// Reads the whole index and returns the message which is signed
+///
+/// The message is the footer hash, it's returned only if the entries
+/// match it.
+fn read_message<R: BufRead>(reader: R) -> Result<Vec<u8>, SignError> {
+    let mut reader = Decoder::new(reader)?;
+    let mut header = Vec::new();
+    reader.read_until(b'\n', &mut header)?;
+    let hash = {
+        let input = Cursor::new(&header[..]).chain(&mut reader);
+        if header.starts_with(b"DIRSIGNATURE.v2 ") {
+            let mut parser = v2::Parser::new(input).map_err(invalid_index)?;
+            for entry in parser.iter() {
+                entry.map_err(invalid_index)?;
+            }
+            parser.get_footer().map(|hash| hash.to_vec())
+        } else {
+            let mut parser = v1::Parser::new(input).map_err(invalid_index)?;
+            for entry in parser.iter() {
+                entry.map_err(invalid_index)?;
+            }
+            parser.get_footer().map(|footer| footer.get_hash().to_vec())
+        }
+    };
+    hash.ok_or_else(|| SignError::InvalidIndex(format!("no footer")))
+}
+
+fn invalid_index<E: ToString>(err: E) -> SignError {
+    SignError::InvalidIndex(err.to_string())
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>, String> {
+    value.from_hex().map_err(|e| e.to_string())
+}
@@ -0,0 +1,295 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, rename, remove_file};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openat::{Dir, Entry};
+
+use error::Error::{self, WriteError as EWrite, ReadFile as EFile};
+use xattr;
+use super::parser::{self, Entry as IndexEntry, EntryKind, EntryIterator};
+use super::parser::ParseRowError;
+use super::writer::{Writer, HashedFile, Name, EXE_MASK};
+
+
+const STAT_CACHE_MAGIC: &'static str = "DIRSIGNATURE-STATS.v1";
+
+
+/// File metadata which is compared to find out whether file is changed
+///
+/// Change time is compared too, because mtime can be preserved when the
+/// file is rewritten (e.g. by `touch -r` or `rsync -t`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stat {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    ctime: i64,
+    ctime_nsec: i64,
+    ino: u64,
+}
+
+/// Reads stat cache sequentially as the scan proceeds
+///
+/// Entries are stored in the same order as in the index so only one line
+/// is kept in memory.
+struct StatReader<R> {
+    reader: R,
+    row: Vec<u8>,
+}
+
+/// A writer that reuses block hashes from the previous index for files
+/// whose metadata has not changed since the previous scan
+///
+/// It also writes a new stat cache for the next scan. The cache file is
+/// replaced only when the scan is done.
+pub struct CachedWriter<'a, W, R: 'a + BufRead> {
+    writer: W,
+    index: Option<EntryIterator<'a, R>>,
+    stats: Option<StatReader<BufReader<File>>>,
+    output: BufWriter<File>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    current_dir: PathBuf,
+    started: (i64, i64),
+    reused: usize,
+}
+
+impl Stat {
+    fn new(meta: &::std::fs::Metadata) -> Stat {
+        Stat {
+            size: meta.size(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            ctime: meta.ctime(),
+            ctime_nsec: meta.ctime_nsec(),
+            ino: meta.ino(),
+        }
+    }
+    fn parse(row: &[u8]) -> Result<(PathBuf, Stat), ParseRowError> {
+        let (path, row) = parser::parse_path_buf(row)?;
+        let (size, row) = parser::parse_u64(row)?;
+        let (mtime, row) = parse_i64(row)?;
+        let (mtime_nsec, row) = parse_i64(row)?;
+        let (ctime, row) = parse_i64(row)?;
+        let (ctime_nsec, row) = parse_i64(row)?;
+        let (ino, row) = parser::parse_u64(row)?;
+        if !row.is_empty() || !path.is_absolute() || path.parent().is_none() {
+            return Err(ParseRowError::InvalidLine(
+                format!("Invalid stat cache entry")));
+        }
+        Ok((path, Stat {
+            size: size,
+            mtime: mtime,
+            mtime_nsec: mtime_nsec,
+            ctime: ctime,
+            ctime_nsec: ctime_nsec,
+            ino: ino,
+        }))
+    }
+}
+
+fn parse_i64(data: &[u8]) -> Result<(i64, &[u8]), ParseRowError> {
+    let (field, tail) = parser::parse_field(data)?;
+    let v = str::from_utf8(field)?.parse()?;
+    Ok((v, tail))
+}
+
+impl<R: BufRead> StatReader<R> {
+    fn new(mut reader: R) -> Result<StatReader<R>, ParseRowError> {
+        let mut header = Vec::new();
+        reader.read_until(b'\n', &mut header)?;
+        if header != format!("{}\n", STAT_CACHE_MAGIC).as_bytes() {
+            return Err(ParseRowError::InvalidSignature(
+                String::from_utf8_lossy(&header).trim().to_string()));
+        }
+        Ok(StatReader {
+            reader: reader,
+            row: Vec::new(),
+        })
+    }
+    /// Finds cached metadata of the file
+    ///
+    /// Files must be looked up in the order of the index
+    fn find(&mut self, path: &Path) -> Result<Option<Stat>, ParseRowError> {
+        use std::cmp::Ordering::*;
+        loop {
+            if self.row.is_empty() {
+                if self.reader.read_until(b'\n', &mut self.row)? == 0 {
+                    return Ok(None);
+                }
+                if self.row.pop() != Some(b'\n') {
+                    return Err(ParseRowError::InvalidLine(
+                        format!("Every line must end with a newline")));
+                }
+            }
+            let (cur_path, stat) = Stat::parse(&self.row)?;
+            let cur = EntryKind::File(cur_path.as_path());
+            match cur.cmp(&EntryKind::File(path)) {
+                Less => self.row.clear(),
+                Greater => return Ok(None),
+                Equal => {
+                    self.row.clear();
+                    return Ok(Some(stat));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, W: Writer, R: BufRead> CachedWriter<'a, W, R> {
+    /// Creates a writer, `index` is `None` if previous index is absent or
+    /// incompatible, in this case stat cache is only written
+    pub fn new(writer: W, index: Option<EntryIterator<'a, R>>, path: &Path)
+        -> Result<CachedWriter<'a, W, R>, Error>
+    {
+        let stats = match File::open(path) {
+            Ok(f) => match StatReader::new(BufReader::new(f)) {
+                Ok(reader) => Some(reader),
+                Err(e) => {
+                    warn!("Ignoring stat cache {:?}: {}", path, e);
+                    None
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(EFile(e)),
+        };
+        let mut tmp_name = OsString::from(path.file_name()
+            .unwrap_or(OsStr::new("stats")));
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let output = BufWriter::new(
+            File::create(&tmp_path).map_err(EWrite)?);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .expect("time is after epoch");
+        let mut result = CachedWriter {
+            writer: writer,
+            index: index,
+            stats: stats,
+            output: output,
+            path: path.to_path_buf(),
+            tmp_path: tmp_path,
+            current_dir: PathBuf::from("/"),
+            started: (now.as_secs() as i64, now.subsec_nanos() as i64),
+            reused: 0,
+        };
+        writeln!(&mut result.output, "{}", STAT_CACHE_MAGIC)
+            .map_err(EWrite)?;
+        Ok(result)
+    }
+
+    /// Returns hashes from the previous index if file is unchanged
+    fn lookup(&mut self, path: &Path, stat: &Stat)
+        -> Result<Option<HashedFile>, Error>
+    {
+        let cached = match self.stats.as_mut().map(|s| s.find(path)) {
+            Some(Ok(cached)) => cached,
+            Some(Err(e)) => {
+                warn!("Error reading stat cache {:?}: {}. \
+                       Rest of the files will be rehashed", self.path, e);
+                self.stats = None;
+                None
+            }
+            None => None,
+        };
+        if cached != Some(*stat) {
+            return Ok(None);
+        }
+        let index = match self.index {
+            Some(ref mut index) => index,
+            None => return Ok(None),
+        };
+        match index.advance(&EntryKind::File(path)) {
+            Some(Ok(IndexEntry::File {
+                exe, size, hashes, file_hash, xattrs, ..
+            })) => {
+                if size != stat.size {
+                    return Ok(None);
+                }
+                let mut data = Vec::new();
+                for hash in hashes.iter() {
+                    data.extend_from_slice(hash);
+                }
+                let chunks = hashes.chunk_sizes()
+                    .map(|s| s.to_vec()).unwrap_or_else(Vec::new);
+                Ok(Some(HashedFile {
+                    exe: exe,
+                    size: size,
+                    hashes: data,
+                    chunks: chunks,
+                    file_hash: file_hash,
+                    xattrs: xattrs,
+                }))
+            }
+            Some(Ok(_)) | None => Ok(None),
+            Some(Err(e)) => Err(Error::ParseIndex(e)),
+        }
+    }
+}
+
+impl<'a, W: Writer, R: BufRead> Writer for CachedWriter<'a, W, R> {
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>
+    {
+        self.current_dir = path.to_path_buf();
+        self.writer.start_dir(path, dir)
+    }
+    fn add_file(&mut self, dir: &Arc<Dir>, entry: Entry)
+        -> Result<(), Error>
+    {
+        let path = self.current_dir.join(entry.file_name());
+        let f = dir.open_file(&entry).map_err(EFile)?;
+        let meta = f.metadata().map_err(EFile)?;
+        let stat = Stat::new(&meta);
+        if let Some(mut file) = self.lookup(&path, &stat)? {
+            self.reused += 1;
+            // mode and attributes can be changed without changing mtime
+            file.exe = meta.mode() & EXE_MASK > 0;
+            if file.xattrs.is_some() {
+                file.xattrs = Some(xattr::read_fd(&f).map_err(EFile)?);
+            }
+            self.writer.add_hashed_file(entry.file_name(), file)?;
+        } else {
+            self.writer.add_file(dir, entry)?;
+        }
+        // file changed in the same instant as the scan started might be
+        // changed again without changing ctime, so don't trust it
+        if (stat.ctime, stat.ctime_nsec) < self.started {
+            writeln!(&mut self.output, "{} {} {} {} {} {} {}",
+                Name(&path), stat.size, stat.mtime, stat.mtime_nsec,
+                stat.ctime, stat.ctime_nsec, stat.ino,
+            ).map_err(EWrite)?;
+        }
+        Ok(())
+    }
+    fn add_hashed_file(&mut self, name: &OsStr, file: HashedFile)
+        -> Result<(), Error>
+    {
+        self.writer.add_hashed_file(name, file)
+    }
+    fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
+        -> Result<(), Error>
+    {
+        self.writer.add_symlink(dir, entry)
+    }
+    fn done(&mut self) -> Result<(), Error> {
+        self.writer.done()?;
+        self.output.flush().map_err(EWrite)?;
+        rename(&self.tmp_path, &self.path).map_err(EWrite)?;
+        debug!("Reused hashes of {} files from the previous index",
+            self.reused);
+        Ok(())
+    }
+}
+
+impl<'a, W, R: BufRead> Drop for CachedWriter<'a, W, R> {
+    fn drop(&mut self) {
+        // the file is already renamed if the scan is done, otherwise
+        // partial stat cache is useless
+        remove_file(&self.tmp_path).ok();
+    }
+}
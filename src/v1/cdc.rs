@@ -0,0 +1,170 @@
+//! FastCDC content-defined chunking
+//!
+//! Chunk boundaries depend only on the contents of the file, so inserting
+//! or removing data changes only chunks around the change. See
+//! `FORMAT.v1.rst` for the exact algorithm.
+use std::io::{self, Read};
+
+use config::MAX_BLOCK_SIZE;
+
+
+pub struct Chunker {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    mask_small: u64,
+    mask_large: u64,
+    gear: [u64; 256],
+}
+
+/// Mask having `bits` highest bits set
+fn high_bits(bits: u32) -> u64 {
+    !0u64 << (64 - bits)
+}
+
+/// Returns `true` if chunks of `avg_size` average size can be produced
+///
+/// The size must be a power of two between 64 and `MAX_BLOCK_SIZE`.
+pub fn valid_avg_size(avg_size: u64) -> bool {
+    avg_size.is_power_of_two() &&
+        avg_size >= 64 && avg_size <= MAX_BLOCK_SIZE
+}
+
+impl Chunker {
+    /// Creates chunker with `avg_size` average chunk size
+    ///
+    /// Returns `InvalidInput` error if `avg_size` is not valid (see
+    /// `valid_avg_size`), it may come from the header of an index file.
+    pub fn new(avg_size: u64) -> io::Result<Chunker> {
+        if !valid_avg_size(avg_size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("invalid chunk size {}", avg_size)));
+        }
+        let bits = avg_size.trailing_zeros();
+        let mut gear = [0u64; 256];
+        let mut state = 0u64;
+        for item in gear.iter_mut() {
+            // splitmix64
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            *item = z ^ (z >> 31);
+        }
+        Ok(Chunker {
+            min_size: avg_size as usize / 4,
+            avg_size: avg_size as usize,
+            max_size: avg_size as usize * 4,
+            mask_small: high_bits(bits + 1),
+            mask_large: high_bits(bits - 1),
+            gear: gear,
+        })
+    }
+
+    /// Returns length of the chunk at the start of `data`
+    ///
+    /// The `data` must contain at least `max_size` bytes unless it's the
+    /// end of the file.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = if data.len() > self.max_size {
+            self.max_size
+        } else {
+            data.len()
+        };
+        let normal = if end > self.avg_size { self.avg_size } else { end };
+        let mut hash = 0u64;
+        for i in self.min_size..end {
+            hash = (hash << 1).wrapping_add(self.gear[data[i] as usize]);
+            let mask = if i < normal {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Splits the contents of the reader to chunks
+    ///
+    /// Calls `chunk` for each of them in order.
+    pub fn split<R, F>(&self, mut f: R, mut chunk: F) -> io::Result<()>
+        where R: Read, F: FnMut(&[u8]) -> io::Result<()>
+    {
+        let mut buf = Vec::with_capacity(self.max_size);
+        let mut eof = false;
+        loop {
+            if !eof && buf.len() < self.max_size {
+                let want = (self.max_size - buf.len()) as u64;
+                let n = (&mut f).take(want).read_to_end(&mut buf)?;
+                eof = (n as u64) < want;
+            }
+            if buf.is_empty() {
+                return Ok(());
+            }
+            let len = self.cut(&buf);
+            chunk(&buf[..len])?;
+            buf.drain(..len);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Chunker;
+
+    fn data(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect()
+    }
+
+    fn chunks(chunker: &Chunker, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut result = Vec::new();
+        chunker.split(data, |c| Ok(result.push(c.to_vec()))).unwrap();
+        result
+    }
+
+    #[test]
+    fn sizes() {
+        let chunker = Chunker::new(1024).unwrap();
+        let data = data(100000);
+        let chunks = chunks(&chunker, &data);
+        assert_eq!(chunks.concat(), data);
+        for c in &chunks[..chunks.len()-1] {
+            assert!(c.len() > 256 && c.len() <= 4096, "{}", c.len());
+        }
+        assert!(chunks.len() > 100000 / 4096);
+    }
+
+    #[test]
+    fn insertion() {
+        let chunker = Chunker::new(1024).unwrap();
+        let data = data(100000);
+        let mut changed = b"inserted".to_vec();
+        changed.extend_from_slice(&data);
+        let old = chunks(&chunker, &data);
+        let new = chunks(&chunker, &changed);
+        // only first chunk or two are changed by the insertion
+        let same = new.iter().filter(|c| old.contains(c)).count();
+        assert!(same + 2 >= new.len(), "{} of {}", same, new.len());
+    }
+
+    #[test]
+    fn invalid_size() {
+        assert!(Chunker::new(0).is_err());
+        assert!(Chunker::new(32).is_err());
+        assert!(Chunker::new(100).is_err());
+        assert!(Chunker::new(1 << 40).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! A module for comparing two directory signature files
+//!
+//!
+//! Entry points:
+//!
+//! * [`Diff::new`](struct.Diff.html#method.new) for comparing two parsed
+//!   signature files
+//!
+//! Both signature files are read sequentially in the same way
+//! [`MergedSignatures`](../merge/struct.MergedSignatures.html) does, so
+//! changes are yielded in the order of the signature files. If both of
+//! them are written with directory hashes, equal subtrees are skipped
+//! without parsing.
+
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::path::Path;
+
+use super::{Entry, Parser, ParseError};
+use super::merge::{MergeError, MergedSignatures, MergedEntriesIterator};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Old,
+    New,
+}
+
+/// A single change between two signature files
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Entry exists only in the new signature file
+    Added(Entry),
+    /// Entry exists only in the old signature file
+    Removed(Entry),
+    /// Entry exists in both signature files but differs
+    ///
+    /// Directories are only modified when their extended attributes
+    /// differ. Note that when a directory is replaced by a file or vice
+    /// versa it's reported as a pair of `Removed` and `Added` changes,
+    /// because directories and files are placed differently in a signature
+    /// file.
+    Modified {
+        /// Entry from the old signature file
+        old: Entry,
+        /// Entry from the new signature file
+        new: Entry,
+        /// Indices of the blocks that differ, including blocks that exist
+        /// only in one of the files. Empty unless both entries are files
+        ///
+        /// For content-defined chunking these are the indices of the chunks
+        /// of the new file whose hash is not found in the old file, since
+        /// chunks shift when data is inserted or removed.
+        blocks: Vec<usize>,
+    },
+}
+
+/// Comparison of two signature files
+pub struct Diff<R: BufRead> {
+    signatures: MergedSignatures<Side, R>,
+}
+
+/// Iterator over the changes between two signature files
+pub struct DiffIterator<'a, R: 'a + BufRead> {
+    entries: MergedEntriesIterator<'a, Side, R>,
+}
+
+impl Change {
+    /// Path of the changed entry
+    pub fn path(&self) -> &Path {
+        match *self {
+            Change::Added(ref entry) |
+            Change::Removed(ref entry) |
+            Change::Modified { new: ref entry, .. } => entry.path(),
+        }
+    }
+}
+
+impl<R: BufRead> Diff<R> {
+    /// Creates a comparison of `old` and `new` signature files
+    ///
+    /// Both should have the same hash type and block size
+    pub fn new(old: Parser<R>, new: Parser<R>) -> Result<Diff<R>, MergeError>
+    {
+        Ok(Diff {
+            signatures: MergedSignatures::new(
+                vec![(Side::Old, old), (Side::New, new)])?,
+        })
+    }
+
+    /// Creates iterator over changes
+    pub fn iter<'a>(&'a mut self) -> DiffIterator<'a, R> {
+        DiffIterator {
+            entries: self.signatures.iter(),
+        }
+    }
+}
+
+impl<'a, R: BufRead> Iterator for DiffIterator<'a, R> {
+    type Item = Result<Change, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut old = None;
+            let mut new = None;
+            let entries = match self.entries.next() {
+                Some(entries) => entries,
+                None => return None,
+            };
+            for (side, entry) in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => return Some(Err(e)),
+                };
+                match *side {
+                    Side::Old => old = Some(entry),
+                    Side::New => new = Some(entry),
+                }
+            }
+            let change = match (old, new) {
+                (Some(old), None) => Change::Removed(old),
+                (None, Some(new)) => Change::Added(new),
+                (Some(old), Some(new)) => {
+                    if unchanged(&old, &new) {
+                        if let Entry::Dir(..) = new {
+                            // the whole subtree is equal if hashes match
+                            if let Err(e) = self.entries.skip_equal_dir() {
+                                return Some(Err(e));
+                            }
+                        }
+                        continue;
+                    }
+                    let blocks = changed_blocks(&old, &new);
+                    Change::Modified { old: old, new: new, blocks: blocks }
+                }
+                (None, None) => unreachable!(),
+            };
+            return Some(Ok(change));
+        }
+    }
+}
+
+/// Compares entries except paths
+///
+/// Extended attributes are only compared if both signature files have
+/// them. Files are compared by the hash of the whole file if both signature
+/// files have it, and block by block otherwise.
+fn unchanged(old: &Entry, new: &Entry) -> bool {
+    if let (Some(old), Some(new)) = (old.xattrs(), new.xattrs()) {
+        if old != new {
+            return false;
+        }
+    }
+    match (old, new) {
+        (&Entry::File { exe: old_exe, size: old_size,
+                        file_hash: Some(ref old_hash), .. },
+         &Entry::File { exe: new_exe, size: new_size,
+                        file_hash: Some(ref new_hash), .. }) => {
+            old_exe == new_exe && old_size == new_size && old_hash == new_hash
+        }
+        (&Entry::File { exe: old_exe, .. },
+         &Entry::File { exe: new_exe, .. }) => {
+            old_exe == new_exe && old.same_contents(new)
+        }
+        (&Entry::Dir(..), &Entry::Dir(..)) => true,
+        (&Entry::Link(_, ref old_dest, _), &Entry::Link(_, ref new_dest, _))
+        => old_dest == new_dest,
+        _ => false,
+    }
+}
+
+fn changed_blocks(old: &Entry, new: &Entry) -> Vec<usize> {
+    match (old, new) {
+        (&Entry::File { hashes: ref old, .. },
+         &Entry::File { hashes: ref new, .. })
+        if new.chunk_sizes().is_some() => {
+            let old = old.iter().collect::<HashSet<_>>();
+            new.iter().enumerate()
+                .filter(|&(_, h)| !old.contains(h))
+                .map(|(idx, _)| idx)
+                .collect()
+        }
+        (&Entry::File { hashes: ref old, .. },
+         &Entry::File { hashes: ref new, .. }) => {
+            let mut old = old.iter();
+            let mut new = new.iter();
+            let mut blocks = Vec::new();
+            let mut idx = 0;
+            loop {
+                match (old.next(), new.next()) {
+                    (None, None) => break,
+                    (Some(a), Some(b)) if a == b => {}
+                    _ => blocks.push(idx),
+                }
+                idx += 1;
+            }
+            blocks
+        }
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,494 @@
+//! Conversion of index files to JSON Lines and back
+//!
+//! The first line is a header object, then there is an object per entry in
+//! the order of the index, and the last line is a footer object:
+//!
+//! ```text
+//! {"block_size":32768,"chunking":"fixed","dir_hash":false,"extra":{},...}
+//! {"path":"/","type":"dir"}
+//! {"exe":false,"hashes":["8dd4..."],"path":"/hello.txt","size":6,...}
+//! {"path":"/link","target":"hello.txt","type":"symlink"}
+//! {"hash":"c2c3...","type":"footer"}
+//! ```
+//!
+//! Paths, link targets and attribute names are plain strings. If any of
+//! them isn't valid UTF-8 the entry has `"escaped":true` and all of them
+//! are escaped the same way as in the index. Hashes and attribute values
+//! are hex encoded. Import writes exactly the same index and checks that
+//! the footer matches.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rustc_serialize::json::{Json, Object, ParserError};
+
+use {HashType, Hasher, Chunking, Xattrs};
+use hash;
+use super::cdc;
+use super::parser::{self, Entry, Header, Parser, ParseError};
+use super::writer::{self, Name, Hex, Attrs, VERSION};
+
+
+quick_error! {
+    /// The error type that can happen when converting to or from JSON
+    #[derive(Debug)]
+    pub enum JsonError {
+        /// Error reading or writing data
+        Io(err: io::Error) {
+            description("Io error")
+            display("Io error: {}", err)
+            from()
+        }
+        /// Error parsing the index
+        Parse(err: ParseError) {
+            description("Parse error")
+            display("Parse error: {}", err)
+            from()
+        }
+        /// Line is not valid JSON
+        Json(err: ParserError, line: usize) {
+            description("Invalid JSON")
+            display("Invalid JSON at line {}: {}", line, err)
+        }
+        /// Line is valid JSON but not a valid header, entry or footer
+        InvalidEntry(msg: String, line: usize) {
+            description("Invalid entry")
+            display("Invalid entry at line {}: {}", line, msg)
+        }
+        /// There is no footer at the end of data
+        MissingFooter {
+            description("Missing footer")
+        }
+        /// Footer doesn't match the written index
+        ChecksumMismatch {
+            description("Footer doesn't match the index")
+        }
+    }
+}
+
+/// State of the import which is set up by the header
+struct Importer {
+    hash_type: HashType,
+    chunking: Chunking,
+    file_hash: bool,
+    dir_hash: bool,
+    xattrs: bool,
+    dir: Option<PathBuf>,
+    hasher: Box<dyn Hasher>,
+}
+
+/// Writes the index as JSON Lines
+///
+/// Should be called on a freshly created parser. The footer is checked
+/// while reading, unless `Parser::skip_checksum` is called.
+pub fn export<R: BufRead, W: Write>(parser: &mut Parser<R>, out: &mut W)
+    -> Result<(), JsonError>
+{
+    writeln!(out, "{}", header_json(&parser.get_header()))?;
+    {
+        let mut iter = parser.iter();
+        while let Some(entry) = iter.next() {
+            let entry = entry?;
+            writeln!(out, "{}",
+                entry_json(&entry, iter.dir_hash()))?;
+        }
+    }
+    let footer = parser.get_footer().expect("footer is parsed");
+    let mut obj = BTreeMap::new();
+    obj.insert("type".to_string(), Json::String("footer".into()));
+    obj.insert("hash".to_string(), hex_json(footer.get_hash()));
+    writeln!(out, "{}", Json::Object(obj))?;
+    Ok(())
+}
+
+/// Reads JSON Lines written by `export` and writes the index
+///
+/// Fails with `JsonError::ChecksumMismatch` if the footer in the data
+/// doesn't match the contents of the written index. Lines are written to
+/// `out` as they are read and the footer is only written when it matches,
+/// so on error `out` contains a partial index without a footer, which
+/// should be discarded. Only built-in hash types are supported.
+pub fn import<R: BufRead, W: Write>(input: R, out: &mut W)
+    -> Result<(), JsonError>
+{
+    let mut lines = input.lines();
+    let mut buf = Vec::new();
+    let mut importer = match lines.next() {
+        Some(line) => {
+            let obj = parse_object(&line?, 1)?;
+            Importer::new(&obj, &mut buf)
+                .map_err(|e| JsonError::InvalidEntry(e, 1))?
+        }
+        None => return Err(JsonError::InvalidEntry(
+            format!("Header expected"), 1)),
+    };
+    importer.hasher.input(&buf);
+    out.write_all(&buf)?;
+    let mut footer = None;
+    for (idx, line) in lines.enumerate() {
+        let num = idx + 2;
+        let obj = parse_object(&line?, num)?;
+        if footer.is_some() {
+            return Err(JsonError::InvalidEntry(
+                format!("Data after the footer"), num));
+        }
+        buf.clear();
+        match importer.entry(&obj, &mut buf) {
+            Ok(Some(hash)) => footer = Some(hash),
+            Ok(None) => {
+                importer.hasher.input(&buf);
+                out.write_all(&buf)?;
+            }
+            Err(e) => return Err(JsonError::InvalidEntry(e, num)),
+        }
+    }
+    let footer = footer.ok_or(JsonError::MissingFooter)?;
+    if footer != importer.hasher.result() {
+        return Err(JsonError::ChecksumMismatch);
+    }
+    write!(out, "{}\n", Hex(&footer))?;
+    Ok(())
+}
+
+impl Importer {
+    /// Parses the header object and writes the header line into `buf`
+    fn new(obj: &Object, buf: &mut Vec<u8>) -> Result<Importer, String> {
+        check_type(obj, "header")?;
+        let version = get_str(obj, "version")?;
+        if version != VERSION {
+            return Err(format!("Unsupported version {:?}", version));
+        }
+        let hash_name = get_str(obj, "hash")?;
+        let hash_type = HashType::from_str(hash_name).map_err(|_|
+            format!("Unsupported hash type {:?}", hash_name))?;
+        let block_size = get_u64(obj, "block_size")?;
+        let chunking = match get_str(obj, "chunking")? {
+            "fixed" => Chunking::Fixed,
+            "fastcdc" => Chunking::FastCdc,
+            x => return Err(format!("Unsupported chunking {:?}", x)),
+        };
+        if block_size == 0 || chunking == Chunking::FastCdc &&
+            !cdc::valid_avg_size(block_size)
+        {
+            return Err(format!("Invalid block size {}", block_size));
+        }
+        let file_hash = get_bool(obj, "file_hash")?;
+        let dir_hash = get_bool(obj, "dir_hash")?;
+        let xattrs = get_bool(obj, "xattrs")?;
+        let mut extra = BTreeMap::new();
+        for (key, value) in get_object(obj, "extra")? {
+            let value = value.as_string().ok_or_else(||
+                format!("Header value of {:?} must be a string", key))?;
+            extra.insert(key.clone(), value.to_string());
+        }
+        writer::write_header(buf, hash_name, block_size, chunking,
+            file_hash, dir_hash, xattrs, &extra)
+            .expect("writing to vec never fails");
+        Ok(Importer {
+            hash_type: hash_type,
+            chunking: chunking,
+            file_hash: file_hash,
+            dir_hash: dir_hash,
+            xattrs: xattrs,
+            dir: None,
+            hasher: hash::hasher(hash_type),
+        })
+    }
+
+    /// Writes the entry line into `buf`, returns hash if it's the footer
+    fn entry(&mut self, obj: &Object, buf: &mut Vec<u8>)
+        -> Result<Option<Vec<u8>>, String>
+    {
+        let escaped = match obj.get("escaped") {
+            Some(value) => value.as_boolean()
+                .ok_or_else(|| format!("Field \"escaped\" must be boolean"))?,
+            None => false,
+        };
+        let line = match get_str(obj, "type")? {
+            "footer" => {
+                let hash = get_hash(obj, "hash", self.hash_type)?;
+                return Ok(Some(hash));
+            }
+            "dir" => {
+                let path = PathBuf::from(get_os_str(obj, "path", escaped)?);
+                if !path.is_absolute() {
+                    return Err(format!("Directory path must be absolute"));
+                }
+                let mut line = format!("{}", Name(&path));
+                if self.xattrs {
+                    let xattrs = get_xattrs(obj, escaped)?;
+                    line.push_str(&format!(" {}", Attrs(&xattrs)));
+                }
+                if self.dir_hash {
+                    let hash = get_hash(obj, "dir_hash", self.hash_type)?;
+                    line.push_str(&format!(" {}", Hex(&hash)));
+                }
+                self.dir = Some(path);
+                line
+            }
+            "file" => {
+                let name = self.file_name(obj, escaped)?;
+                let exe = get_bool(obj, "exe")?;
+                let mut line = format!("  {} {}",
+                    Name(Path::new(&name)), if exe { "x" } else { "f" });
+                if self.xattrs {
+                    let xattrs = get_xattrs(obj, escaped)?;
+                    line.push_str(&format!(" {}", Attrs(&xattrs)));
+                }
+                line.push_str(&format!(" {}", get_u64(obj, "size")?));
+                if self.file_hash {
+                    let hash = get_hash(obj, "file_hash", self.hash_type)?;
+                    line.push_str(&format!(" {}", Hex(&hash)));
+                }
+                let hashes = get_array(obj, "hashes")?;
+                let sizes = match self.chunking {
+                    Chunking::Fixed => None,
+                    Chunking::FastCdc => {
+                        let sizes = get_array(obj, "chunk_sizes")?;
+                        if sizes.len() != hashes.len() {
+                            return Err(format!("Number of chunk sizes \
+                                doesn't match number of hashes"));
+                        }
+                        Some(sizes)
+                    }
+                };
+                for (idx, value) in hashes.iter().enumerate() {
+                    let hash = parse_hash(value, self.hash_type)?;
+                    match sizes {
+                        Some(sizes) => {
+                            let size = sizes[idx].as_u64().ok_or_else(||
+                                format!("Chunk size must be an integer"))?;
+                            line.push_str(&format!(" {}:{}",
+                                size, Hex(&hash)));
+                        }
+                        None => line.push_str(&format!(" {}", Hex(&hash))),
+                    }
+                }
+                line
+            }
+            "symlink" => {
+                let name = self.file_name(obj, escaped)?;
+                let target = get_os_str(obj, "target", escaped)?;
+                let mut line = format!("  {} s", Name(Path::new(&name)));
+                if self.xattrs {
+                    let xattrs = get_xattrs(obj, escaped)?;
+                    line.push_str(&format!(" {}", Attrs(&xattrs)));
+                }
+                line.push_str(&format!(" {}", Name(Path::new(&target))));
+                line
+            }
+            x => return Err(format!("Unknown type {:?}", x)),
+        };
+        buf.extend_from_slice(line.as_bytes());
+        buf.push(b'\n');
+        Ok(None)
+    }
+
+    /// Returns name of the file or symlink, which must be in the current
+    /// directory
+    fn file_name(&self, obj: &Object, escaped: bool)
+        -> Result<OsString, String>
+    {
+        let path = PathBuf::from(get_os_str(obj, "path", escaped)?);
+        match (path.parent(), path.file_name(), self.dir.as_ref()) {
+            (Some(parent), Some(name), Some(dir)) if parent == dir => {
+                Ok(name.to_os_string())
+            }
+            _ => Err(format!("Entry {:?} must follow its directory", path)),
+        }
+    }
+}
+
+fn header_json(header: &Header) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("type".to_string(), Json::String("header".into()));
+    obj.insert("version".to_string(),
+        Json::String(header.get_version().to_string()));
+    obj.insert("hash".to_string(),
+        Json::String(header.get_hash_type().name().to_string()));
+    obj.insert("block_size".to_string(),
+        Json::U64(header.get_block_size()));
+    obj.insert("chunking".to_string(), Json::String(
+        match header.get_chunking() {
+            Chunking::Fixed => "fixed",
+            Chunking::FastCdc => "fastcdc",
+        }.to_string()));
+    obj.insert("file_hash".to_string(),
+        Json::Boolean(header.has_file_hash()));
+    obj.insert("dir_hash".to_string(), Json::Boolean(header.has_dir_hash()));
+    obj.insert("xattrs".to_string(), Json::Boolean(header.has_xattrs()));
+    obj.insert("extra".to_string(), Json::Object(
+        header.get_extra().iter()
+        .map(|(k, v)| (k.clone(), Json::String(v.clone())))
+        .collect()));
+    Json::Object(obj)
+}
+
+fn entry_json(entry: &Entry, dir_hash: Option<&[u8]>) -> Json {
+    let mut names = vec![entry.path().as_os_str()];
+    if let Entry::Link(_, ref dest, _) = *entry {
+        names.push(dest.as_os_str());
+    }
+    if let Some(xattrs) = entry.xattrs() {
+        names.extend(xattrs.iter().map(|&(ref name, _)| name.as_os_str()));
+    }
+    let escaped = names.iter().any(|name| name.to_str().is_none());
+
+    let mut obj = BTreeMap::new();
+    obj.insert("path".to_string(), os_str_json(entry.path(), escaped));
+    match *entry {
+        Entry::Dir(..) => {
+            obj.insert("type".to_string(), Json::String("dir".into()));
+            if let Some(hash) = dir_hash {
+                obj.insert("dir_hash".to_string(), hex_json(hash));
+            }
+        }
+        Entry::File { exe, size, ref hashes, ref file_hash, .. } => {
+            obj.insert("type".to_string(), Json::String("file".into()));
+            obj.insert("exe".to_string(), Json::Boolean(exe));
+            obj.insert("size".to_string(), Json::U64(size));
+            obj.insert("hashes".to_string(),
+                Json::Array(hashes.iter().map(hex_json).collect()));
+            if let Some(sizes) = hashes.chunk_sizes() {
+                obj.insert("chunk_sizes".to_string(), Json::Array(
+                    sizes.iter().map(|&s| Json::U64(s)).collect()));
+            }
+            if let Some(ref hash) = *file_hash {
+                obj.insert("file_hash".to_string(), hex_json(hash));
+            }
+        }
+        Entry::Link(_, ref dest, _) => {
+            obj.insert("type".to_string(), Json::String("symlink".into()));
+            obj.insert("target".to_string(), os_str_json(dest, escaped));
+        }
+    }
+    if let Some(xattrs) = entry.xattrs() {
+        obj.insert("xattrs".to_string(), Json::Object(
+            xattrs.iter().map(|&(ref name, ref value)| {
+                let name = match os_str_json(name, escaped) {
+                    Json::String(name) => name,
+                    _ => unreachable!(),
+                };
+                (name, hex_json(value))
+            }).collect()));
+    }
+    if escaped {
+        obj.insert("escaped".to_string(), Json::Boolean(true));
+    }
+    Json::Object(obj)
+}
+
+fn os_str_json<S: AsRef<OsStr>>(value: S, escaped: bool) -> Json {
+    let value = value.as_ref();
+    if escaped {
+        Json::String(format!("{}", Name(Path::new(value))))
+    } else {
+        Json::String(value.to_str().expect("checked utf-8").to_string())
+    }
+}
+
+fn hex_json(data: &[u8]) -> Json {
+    Json::String(format!("{}", Hex(data)))
+}
+
+fn parse_object(line: &str, num: usize) -> Result<Object, JsonError> {
+    match Json::from_str(line) {
+        Ok(Json::Object(obj)) => Ok(obj),
+        Ok(_) => Err(JsonError::InvalidEntry(
+            format!("Every line must be an object"), num)),
+        Err(e) => Err(JsonError::Json(e, num)),
+    }
+}
+
+fn check_type(obj: &Object, expected: &str) -> Result<(), String> {
+    match get_str(obj, "type")? {
+        x if x == expected => Ok(()),
+        x => Err(format!("Expected {:?} but found {:?}", expected, x)),
+    }
+}
+
+fn get_field<'a>(obj: &'a Object, key: &str) -> Result<&'a Json, String> {
+    obj.get(key).ok_or_else(|| format!("Missing field {:?}", key))
+}
+
+fn get_str<'a>(obj: &'a Object, key: &str) -> Result<&'a str, String> {
+    get_field(obj, key)?.as_string()
+        .ok_or_else(|| format!("Field {:?} must be a string", key))
+}
+
+fn get_u64(obj: &Object, key: &str) -> Result<u64, String> {
+    get_field(obj, key)?.as_u64()
+        .ok_or_else(|| format!("Field {:?} must be an integer", key))
+}
+
+fn get_bool(obj: &Object, key: &str) -> Result<bool, String> {
+    get_field(obj, key)?.as_boolean()
+        .ok_or_else(|| format!("Field {:?} must be boolean", key))
+}
+
+fn get_array<'a>(obj: &'a Object, key: &str)
+    -> Result<&'a Vec<Json>, String>
+{
+    get_field(obj, key)?.as_array()
+        .ok_or_else(|| format!("Field {:?} must be an array", key))
+}
+
+fn get_object<'a>(obj: &'a Object, key: &str) -> Result<&'a Object, String> {
+    get_field(obj, key)?.as_object()
+        .ok_or_else(|| format!("Field {:?} must be an object", key))
+}
+
+fn get_hash(obj: &Object, key: &str, hash_type: HashType)
+    -> Result<Vec<u8>, String>
+{
+    parse_hash(get_field(obj, key)?, hash_type)
+}
+
+fn get_os_str(obj: &Object, key: &str, escaped: bool)
+    -> Result<OsString, String>
+{
+    unescape(get_str(obj, key)?, escaped)
+}
+
+fn get_xattrs(obj: &Object, escaped: bool) -> Result<Xattrs, String> {
+    let mut items = Vec::new();
+    for (name, value) in get_object(obj, "xattrs")? {
+        let value = value.as_string().ok_or_else(||
+            format!("Value of attribute {:?} must be a string", name))?;
+        items.push((unescape(name, escaped)?, parse_hex(value)?));
+    }
+    Ok(Xattrs::new(items))
+}
+
+fn unescape(value: &str, escaped: bool) -> Result<OsString, String> {
+    if !escaped {
+        return Ok(OsString::from(value));
+    }
+    match parser::parse_path_buf(value.as_bytes()) {
+        Ok((path, tail)) if tail.is_empty() => Ok(path.into_os_string()),
+        _ => Err(format!("Invalid escaped value {:?}", value)),
+    }
+}
+
+fn parse_hash(value: &Json, hash_type: HashType) -> Result<Vec<u8>, String> {
+    let hash = value.as_string()
+        .ok_or_else(|| format!("Hash must be a string"))
+        .and_then(parse_hex)?;
+    if hash.len() != hash_type.output_bytes() {
+        return Err(format!("Expected hash of {} bytes",
+            hash_type.output_bytes()));
+    }
+    Ok(hash)
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>, String> {
+    if value.len() % 2 != 0 {
+        return Err(format!("Invalid hex {:?}", value));
+    }
+    value.as_bytes().chunks(2).map(parser::parse_hex)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid hex {:?}", value))
+}
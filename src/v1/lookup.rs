@@ -0,0 +1,149 @@
+//! A sidecar file for random access to the index by path
+//!
+//! The lookup file maps every directory of the index to a byte offset of its
+//! line. It's built either by the scan (see `ScannerConfig::lookup_file`)
+//! or from an existing index by
+//! [`Parser::build_lookup`](../struct.Parser.html#method.build_lookup),
+//! and used by
+//! [`Parser::set_lookup`](../struct.Parser.html#method.set_lookup).
+//!
+//! The file is text, the first line is `DIRSIGNATURE-LOOKUP.v1` and the
+//! footer of the index it's built for, every other line is an escaped path
+//! of a directory and the offset of the directory line in the index, in
+//! the order of the index.
+
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use quick_error::ResultExt;
+
+use super::parser::{self, ParseError, ParseRowError};
+use super::writer::{Name, Hex};
+
+
+const LOOKUP_MAGIC: &'static str = "DIRSIGNATURE-LOOKUP.v1";
+
+
+quick_error! {
+    /// The error type that can happen when accessing index by path
+    #[derive(Debug)]
+    pub enum LookupError {
+        /// Error reading or seeking the index
+        Io(err: io::Error) {
+            description("Io error")
+            display("Io error: {}", err)
+            from()
+        }
+        /// Error parsing the index
+        Parse(err: ParseError) {
+            description("Parse error")
+            display("Parse error: {}", err)
+            from()
+        }
+        /// Lookup file is built for another index
+        HashMismatch {
+            description("Lookup file doesn't match the index")
+        }
+        /// Compressed index can't be seeked
+        Compressed {
+            description("Can't seek in compressed index")
+        }
+        /// No lookup file is set for the parser
+        NoLookup {
+            description("No lookup file is set")
+        }
+    }
+}
+
+/// Offsets of the directory lines of an index file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup {
+    hash: Vec<u8>,
+    dirs: Vec<(PathBuf, u64)>,
+}
+
+impl Lookup {
+    /// Creates lookup from the footer hash and directory offsets in the
+    /// order of the index
+    pub fn new(hash: Vec<u8>, dirs: Vec<(PathBuf, u64)>) -> Lookup {
+        Lookup {
+            hash: hash,
+            dirs: dirs,
+        }
+    }
+
+    /// Reads lookup file
+    pub fn read<R: BufRead>(mut reader: R) -> Result<Lookup, ParseError> {
+        let mut row = Vec::new();
+        reader.read_until(b'\n', &mut row)?;
+        let hash = {
+            let magic = LOOKUP_MAGIC.as_bytes();
+            if !row.starts_with(magic) || row.get(magic.len()) != Some(&b' ')
+                || !row.ends_with(b"\n")
+            {
+                return Err(ParseError::Parse(
+                    ParseRowError::InvalidSignature(
+                        String::from_utf8_lossy(&row).trim().to_string()),
+                    1));
+            }
+            let hex = &row[magic.len()+1..row.len()-1];
+            if hex.is_empty() || hex.len() % 2 != 0 {
+                return Err(ParseError::Parse(
+                    ParseRowError::InvalidHash(
+                        format!("Invalid footer of the index")),
+                    1));
+            }
+            hex.chunks(2).map(parser::parse_hex)
+                .collect::<Result<Vec<_>, _>>().context(1)?
+        };
+        let mut dirs = Vec::new();
+        let mut row_num = 1;
+        loop {
+            row.clear();
+            row_num += 1;
+            if reader.read_until(b'\n', &mut row)? == 0 {
+                break;
+            }
+            if row.pop() != Some(b'\n') {
+                return Err(ParseError::Parse(ParseRowError::InvalidLine(
+                    format!("Every line must end with a newline")),
+                    row_num));
+            }
+            let (path, tail) = parser::parse_path_buf(&row)
+                .context(row_num)?;
+            let (offset, tail) = parser::parse_u64(tail)
+                .context(row_num)?;
+            if !tail.is_empty() || !path.is_absolute() {
+                return Err(ParseError::Parse(ParseRowError::InvalidLine(
+                    format!("Invalid lookup entry")), row_num));
+            }
+            dirs.push((path, offset));
+        }
+        Ok(Lookup::new(hash, dirs))
+    }
+
+    /// Writes lookup file
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{} {}\n", LOOKUP_MAGIC, Hex(&self.hash))?;
+        for &(ref path, offset) in &self.dirs {
+            write!(out, "{} {}\n", Name(path), offset)?;
+        }
+        Ok(())
+    }
+
+    /// Returns footer hash of the index which the lookup is built for
+    pub fn get_hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Returns offset of the directory line in the index
+    ///
+    /// Runs in logarithmic time as directories are sorted in the index.
+    pub fn dir_offset<P: AsRef<Path>>(&self, dir: P) -> Option<u64> {
+        // directories are ordered just by path, see `EntryKind`
+        let dir = dir.as_ref();
+        self.dirs.binary_search_by(|&(ref p, _)| p.as_path().cmp(dir))
+            .ok()
+            .map(|idx| self.dirs[idx].1)
+    }
+}
@@ -12,7 +12,7 @@ use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::path::{Path, PathBuf};
 
-use ::HashType;
+use {HashType, Chunking};
 use super::{Entry, EntryKind, Parser, ParseError};
 use super::parser::EntryIterator;
 
@@ -41,6 +41,11 @@ quick_error! {
             description("Block sizes mismatch")
             display("Block sizes mismatch: {:?}", block_sizes)
         }
+        /// Signature files split files into blocks differently
+        ChunkingMismatch(chunkings: Vec<Chunking>) {
+            description("Chunking mismatch")
+            display("Chunking mismatch: {:?}", chunkings)
+        }
     }
 }
 
@@ -94,7 +99,7 @@ pub struct MergedSignatures<K, R: BufRead> {
 impl<K, R: BufRead> MergedSignatures<K, R> {
     /// Creates merged signatures struct over `parsers`
     ///
-    /// All hash types and block size should be the same
+    /// All hash types, block sizes and chunking modes should be the same
     pub fn new<I>(parsers: I)
         -> Result<MergedSignatures<K, R>, MergeError>
         where I: IntoIterator<Item=(K, Parser<R>)>
@@ -112,11 +117,35 @@ impl<K, R: BufRead> MergedSignatures<K, R> {
         if !check_same(&block_sizes) {
             return Err(MergeError::BlockSizesMismatch(block_sizes));
         }
+        let chunkings = parsers.iter()
+            .map(|p| p.1.get_header().get_chunking())
+            .collect::<Vec<_>>();
+        if !check_same(&chunkings) {
+            return Err(MergeError::ChunkingMismatch(chunkings));
+        }
         Ok(MergedSignatures {
             parsers: parsers,
         })
     }
 
+    /// Returns hash type of the signature files, `None` if there are no
+    /// signature files
+    pub fn hash_type(&self) -> Option<HashType> {
+        self.parsers.first().map(|p| p.1.get_header().get_hash_type())
+    }
+
+    /// Returns block size of the signature files, `None` if there are no
+    /// signature files
+    pub fn block_size(&self) -> Option<u64> {
+        self.parsers.first().map(|p| p.1.get_header().get_block_size())
+    }
+
+    /// Returns chunking mode of the signature files, `None` if there are no
+    /// signature files
+    pub fn chunking(&self) -> Option<Chunking> {
+        self.parsers.first().map(|p| p.1.get_header().get_chunking())
+    }
+
     /// Creates iterator
     pub fn iter<'a>(&'a mut self) -> MergedEntriesIterator<'a, K, R> {
         MergedEntriesIterator::new(self)
@@ -156,6 +185,34 @@ impl<'a, K, R: BufRead> MergedEntriesIterator<'a, K, R> {
         }
         entries
     }
+
+    /// Skips the subtree of the directory returned by the last `next` if
+    /// the subtree is the same in all signature files
+    ///
+    /// Subtrees are compared by directory hashes, so nothing is skipped
+    /// unless the signature files are written with `dir_hash=1` and the
+    /// directory is present in all of them. Returns `true` if the subtree
+    /// was skipped.
+    pub fn skip_equal_dir(&mut self) -> Result<bool, ParseError> {
+        if self.iterator_ixs.len() != self.iterators.len() {
+            return Ok(false);
+        }
+        {
+            let mut hashes = self.iterators.iter()
+                .map(|&(_, ref iterator)| iterator.tail.dir_hash());
+            let first = match hashes.next() {
+                Some(Some(hash)) => hash,
+                _ => return Ok(false),
+            };
+            if !hashes.all(|hash| hash == Some(first)) {
+                return Ok(false);
+            }
+        }
+        for &mut (_, ref mut iterator) in self.iterators.iter_mut() {
+            iterator.tail.skip_dir()?;
+        }
+        Ok(true)
+    }
 }
 
 struct PeekableEntryIterator<'a, R: 'a + BufRead> {
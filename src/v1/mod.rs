@@ -5,60 +5,150 @@
 //!
 //! * [`scan`](fn.scan.html) function for creating index file
 //! * [`Parser::new`](struct.Parser.html#method.new) for reading index file
+//! * [`verify`](fn.verify.html) for checking a directory against the index
+//! * [`sync`](fn.sync.html) for bringing a directory to the indexed state
+//! * [`diff::Diff::new`](diff/struct.Diff.html#method.new) for comparing
+//!   two index files
+//! * [`Parser::set_lookup`](struct.Parser.html#method.set_lookup) for
+//!   finding entries by path without reading the whole index
+//! * [`json::export`](json/fn.export.html) and
+//!   [`json::import`](json/fn.import.html) for converting index file to
+//!   JSON Lines and back
 //!
 //! There is also global [`get_hash`](../fn.get_hash.html) for getting just
 //! checksum of an index file.
 
 mod writer;
 mod progress;
-mod hash;
-mod scan;
+mod threads;
+mod cdc;
+mod cache;
+mod verify;
+mod sync;
 mod parser;
 pub mod merge;
+pub mod diff;
+pub mod lookup;
+pub mod json;
 
-use std::io;
+use std::fs::File;
+use std::io::{self, BufReader};
 
 pub use error::Error;
 pub use self::parser::{Hashes, Header, Entry, EntryKind, Parser, EntryIterator};
-pub use self::parser::{ParseError, ParseRowError};
+pub use self::parser::{EntryRef, HashesRef, HexHashes};
+pub use self::parser::{Footer, ParseError, ParseRowError};
+pub use self::verify::{verify, Report, Problem, EntryType};
+pub use self::sync::{sync, SyncError};
 
 use self::progress::Progress;
-use self::writer::SyncWriter;
-use {ScannerConfig, HashType};
+use self::threads::ThreadedWriter;
+use self::cache::CachedWriter;
+use self::writer::{SyncWriter, Writer};
+use {ScannerConfig, HashType, Compression};
+use hash::{self, Hash};
+use compress::Encoder;
+use scan;
+
+/// Default number of queued entries per hashing thread
+const QUEUE_PER_THREAD: usize = 16;
 
 /// Create an index using specified config
 ///
-/// It's better to use some buffered output file here.
+/// It's better to use some buffered output file here. The index is
+/// compressed if `ScannerConfig::compression` is set.
 pub fn scan<F: io::Write>(config: &ScannerConfig, out: &mut F)
     -> Result<(), Error>
 {
-    if config.print_progress {
-        match config.hash {
-            HashType::Sha512_256 => {
-                scan::scan(config,
-                    &mut Progress::new(io::stderr(),
-                        SyncWriter::new(out,
-                            hash::Sha512_256, config.block_size)?))
-            }
-            HashType::Blake2b_256 => {
-                scan::scan(config,
-                    &mut Progress::new(io::stderr(),
-                        SyncWriter::new(out,
-                            hash::Blake2b_256, config.block_size)?))
-            }
+    match config.hash {
+        HashType::Sha512_256 => scan_with(config, out, hash::Sha512_256),
+        HashType::Blake2b_256 => scan_with(config, out, hash::Blake2b_256),
+        HashType::Blake3_256 => scan_with(config, out, hash::Blake3_256),
+        HashType::Sha256 => scan_with(config, out, hash::Sha256),
+        HashType::Custom(alg) => {
+            HashType::custom(alg)?;
+            scan_with(config, out, hash::Custom(alg))
+        }
+    }
+}
+
+fn scan_with<F: io::Write, H: Hash>(config: &ScannerConfig, out: &mut F,
+    hash: H)
+    -> Result<(), Error>
+{
+    if config.lookup.is_some() && config.compression != Compression::None {
+        return Err(Error::BadLookup(
+            "offsets can't be used with a compressed index"));
+    }
+    let mut out = Encoder::new(out, config.compression)
+        .map_err(Error::WriteError)?;
+    {
+        let writer = SyncWriter::new(&mut out, hash.clone(),
+            config.block_size, config.chunking, config.file_hash,
+            config.dir_hash, config.xattrs, &config.header_extra,
+            config.lookup.as_ref())?;
+        if config.print_progress {
+            scan_threads(config, Progress::new(io::stderr(), writer), hash)?;
+        } else {
+            scan_threads(config, writer, hash)?;
         }
+    }
+    // compressed stream must be finished after the footer is written
+    out.finish().map_err(Error::WriteError)?;
+    Ok(())
+}
+
+fn scan_threads<W: Writer, H: Hash>(config: &ScannerConfig, writer: W,
+    hash: H)
+    -> Result<(), Error>
+{
+    if config.threads > 1 {
+        let queue_size = config.queue_size
+            .unwrap_or(config.threads * QUEUE_PER_THREAD);
+        scan_cached(config, ThreadedWriter::new(writer,
+            hash, config.block_size, config.chunking, config.file_hash,
+            config.xattrs, config.threads, queue_size))
     } else {
-        match config.hash {
-            HashType::Sha512_256 => {
-                scan::scan(config,
-                    &mut SyncWriter::new(out,
-                        hash::Sha512_256, config.block_size)?)
-            }
-            HashType::Blake2b_256 => {
-                scan::scan(config,
-                    &mut SyncWriter::new(out,
-                        hash::Blake2b_256, config.block_size)?)
+        scan_cached(config, writer)
+    }
+}
+
+fn scan_cached<W: Writer>(config: &ScannerConfig, mut writer: W)
+    -> Result<(), Error>
+{
+    let (index_path, stats_path) = match config.cache {
+        Some((ref index, ref stats)) => (index, stats),
+        None => return scan::scan(config, &mut writer),
+    };
+    let mut parser = match File::open(index_path) {
+        Ok(f) => Some(Parser::new(BufReader::new(f))
+                      .map_err(Error::ParseIndex)?),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            info!("Previous index {:?} not found, hashing all files",
+                index_path);
+            None
+        }
+        Err(e) => return Err(Error::ReadIndex(e)),
+    };
+    let index = match parser {
+        Some(ref mut parser) => {
+            let header = parser.get_header();
+            if header.get_hash_type() == config.hash &&
+               header.get_block_size() == config.block_size &&
+               header.get_chunking() == config.chunking &&
+               header.has_file_hash() == config.file_hash &&
+               header.has_xattrs() == config.xattrs
+            {
+                Some(parser.iter())
+            } else {
+                warn!("Previous index {:?} has different hash type, \
+                       block size, chunking, file hash or xattrs setting, \
+                       hashing all files", index_path);
+                None
             }
         }
-    }
+        None => None,
+    };
+    let mut writer = CachedWriter::new(writer, index, stats_path)?;
+    scan::scan(config, &mut writer)
 }
@@ -1,18 +1,26 @@
 use std;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::cmp::Ordering;
 use std::convert::From;
 use std::ffi::{OsStr, OsString};
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
-use std::io::{self, BufRead};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
 use std::slice::Chunks;
 use std::str::FromStr;
 
 use quick_error::ResultExt;
 
 use ::HashType;
-use super::writer::{MAGIC, VERSION};
+use {Hasher, Chunking, Xattrs};
+use hash;
+use compress::Decoder;
+use read::get_hash;
+use sign::{Signature, SignError, TrustedKeys};
+use super::cdc;
+use super::lookup::{Lookup, LookupError};
+use super::writer::{MAGIC, VERSION, Name};
 
 quick_error! {
     /// The error type that represents errors which can happen when parsing
@@ -68,10 +76,49 @@ quick_error! {
             description("Invalid block size")
             display("Invalid block size: {}", block_size)
         }
+        /// Footer doesn't match the hash of the preceding lines
+        ChecksumMismatch {
+            description("Checksum mismatch")
+            display("Checksum mismatch: footer doesn't match contents \
+                     of the signature file")
+        }
         /// Invalid hash
         InvalidHash(msg: String) {
             display("Invalid hash: {}", msg)
         }
+        /// Invalid extended attributes field
+        InvalidXattrs(msg: String) {
+            description("Invalid extended attributes")
+            display("Invalid extended attributes: {}", msg)
+        }
+        /// Entry is placed before the entry which it should follow
+        ///
+        /// Only reported by `Parser::strict`.
+        Unsorted(path: PathBuf) {
+            description("Entry is out of order")
+            display("Entry is out of order: {:?}", path)
+        }
+        /// Entry with the same name is already in the directory
+        ///
+        /// Only reported by `Parser::strict`.
+        Duplicate(path: PathBuf) {
+            description("Duplicate entry")
+            display("Duplicate entry: {:?}", path)
+        }
+        /// File or symlink is placed before the first directory
+        ///
+        /// Only reported by `Parser::strict`.
+        OutsideDir(path: PathBuf) {
+            description("Entry is outside of any directory")
+            display("Entry is outside of any directory: {:?}", path)
+        }
+        /// Name is not escaped the same way as the writer does it
+        ///
+        /// Only reported by `Parser::strict`.
+        NonCanonicalName(name: String) {
+            description("Name is not escaped canonically")
+            display("Name is not escaped canonically: {:?}", name)
+        }
         /// Invalid file type
         InvalidFileType(file_type: String) {
             description("Invalid file type")
@@ -102,6 +149,22 @@ quick_error! {
     }
 }
 
+impl ParseRowError {
+    /// Returns `true` for errors only reported by `Parser::strict`
+    ///
+    /// The entry is valid otherwise and it's returned by the next call
+    /// of the iterator.
+    pub fn is_violation(&self) -> bool {
+        match *self {
+            ParseRowError::Unsorted(..) |
+            ParseRowError::Duplicate(..) |
+            ParseRowError::OutsideDir(..) |
+            ParseRowError::NonCanonicalName(..) => true,
+            _ => false,
+        }
+    }
+}
+
 quick_error! {
     /// The error type that can happen when parsing directory signature file
     #[derive(Debug)]
@@ -248,10 +311,17 @@ pub struct Header {
     version: String,
     hash_type: HashType,
     block_size: u64,
+    chunking: Chunking,
+    file_hash: bool,
+    dir_hash: bool,
+    xattrs: bool,
+    extra: BTreeMap<String, String>,
 }
 
 impl Header {
-    fn parse(row: &[u8]) -> Result<Header, ParseRowError> {
+    fn parse(row: &[u8], custom_hashes: &[HashType])
+        -> Result<Header, ParseRowError>
+    {
         let line = std::str::from_utf8(row).map_err(|e|
             ParseRowError::InvalidHeader(format!("{}", e)))?;
         let mut parts = line.split_whitespace();
@@ -276,8 +346,10 @@ impl Header {
             return Err(ParseRowError::MissingHeader);
         };
         let hash_type = if let Some(hash_type_str) = parts.next() {
-            HashType::from_str(hash_type_str)
-                .map_err(|_| ParseRowError::UnsupportedHashType(
+            HashType::from_str(hash_type_str).ok()
+                .or_else(|| custom_hashes.iter()
+                    .find(|h| h.name() == hash_type_str).cloned())
+                .ok_or_else(|| ParseRowError::UnsupportedHashType(
                     hash_type_str.to_string()))?
         } else {
             return Err(ParseRowError::MissingHashType);
@@ -293,9 +365,11 @@ impl Header {
                 },
                 Some(_) => {
                     match block_size_kv.next() {
-                        Some(v) => u64::from_str_radix(v, 10)
-                            .map_err(|_| ParseRowError::InvalidBlockSize(
-                                v.to_string()))?,
+                        Some(v) => match u64::from_str_radix(v, 10) {
+                            Ok(size) if size > 0 => size,
+                            _ => return Err(ParseRowError::InvalidBlockSize(
+                                v.to_string())),
+                        },
                         None => return Err(ParseRowError::MissingBlockSize),
                     }
                 },
@@ -303,10 +377,71 @@ impl Header {
         } else {
             return Err(ParseRowError::MissingBlockSize);
         };
+        let mut chunking = None;
+        let mut file_hash = None;
+        let mut dir_hash = None;
+        let mut xattrs = None;
+        let mut extra = BTreeMap::new();
+        for attr in parts {
+            let mut kv = attr.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("chunking"), Some(v)) => {
+                    if chunking.is_some() {
+                        return Err(ParseRowError::InvalidHeader(
+                            format!("Duplicate key: \"chunking\"")));
+                    }
+                    chunking = Some(match v {
+                        "fastcdc" => Chunking::FastCdc,
+                        _ => return Err(ParseRowError::InvalidHeader(
+                            format!("Unsupported chunking: {:?}", v))),
+                    });
+                },
+                (Some(k @ "file_hash"), Some(v)) |
+                (Some(k @ "dir_hash"), Some(v)) |
+                (Some(k @ "xattrs"), Some(v)) => {
+                    let flag = match k {
+                        "file_hash" => &mut file_hash,
+                        "dir_hash" => &mut dir_hash,
+                        _ => &mut xattrs,
+                    };
+                    if flag.is_some() {
+                        return Err(ParseRowError::InvalidHeader(
+                            format!("Duplicate key: {:?}", k)));
+                    }
+                    if v != "1" {
+                        return Err(ParseRowError::InvalidHeader(
+                            format!("Invalid {}: {:?}", k, v)));
+                    }
+                    *flag = Some(true);
+                },
+                // unknown keys may be written by future versions, so
+                // they are skipped rather than rejected
+                (Some(k), Some(v)) if !k.is_empty() => {
+                    if k == "block_size" || extra.contains_key(k) {
+                        warn!("Skipping duplicate header key {:?}", k);
+                        continue;
+                    }
+                    extra.insert(k.to_string(), v.to_string());
+                },
+                _ => {
+                    warn!("Skipping invalid header pair {:?}", attr);
+                },
+            }
+        }
+        let chunking = chunking.unwrap_or(Chunking::Fixed);
+        if chunking == Chunking::FastCdc && !cdc::valid_avg_size(block_size) {
+            return Err(ParseRowError::InvalidBlockSize(
+                block_size.to_string()));
+        }
         Ok(Header {
             version: version.to_string(),
             hash_type: hash_type,
             block_size: block_size,
+            chunking: chunking,
+            file_hash: file_hash.unwrap_or(false),
+            dir_hash: dir_hash.unwrap_or(false),
+            xattrs: xattrs.unwrap_or(false),
+            extra: extra,
         })
     }
 
@@ -324,9 +459,41 @@ impl Header {
     pub fn get_block_size(&self) -> u64 {
         self.block_size
     }
+
+    /// Returns how files are split into blocks
+    ///
+    /// For `Chunking::FastCdc` the block size is the average chunk size.
+    pub fn get_chunking(&self) -> Chunking {
+        self.chunking
+    }
+
+    /// Returns `true` if file entries contain a hash of the whole file
+    pub fn has_file_hash(&self) -> bool {
+        self.file_hash
+    }
+
+    /// Returns `true` if directory lines contain a hash of the subtree
+    pub fn has_dir_hash(&self) -> bool {
+        self.dir_hash
+    }
+
+    /// Returns `true` if entries contain extended attributes
+    pub fn has_xattrs(&self) -> bool {
+        self.xattrs
+    }
+
+    /// Returns additional key value pairs following the block size
+    ///
+    /// The `chunking`, `file_hash`, `dir_hash` and `xattrs` keys are not
+    /// included, use the respective methods instead. Pairs without a key
+    /// and repeated keys are skipped, only the first value is kept.
+    pub fn get_extra(&self) -> &BTreeMap<String, String> {
+        &self.extra
+    }
 }
 
-#[derive(Debug)]
+/// Represents footer of the dir signature file
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Footer(Vec<u8>);
 
 impl Footer {
@@ -341,6 +508,13 @@ impl Footer {
         }
         Ok(Footer(data))
     }
+
+    /// Returns hash of the signature file contents
+    ///
+    /// The same hash is returned by `get_hash` function
+    pub fn get_hash(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 /// Entry hashes iterator
@@ -348,6 +522,7 @@ impl Footer {
 pub struct Hashes {
     data: Vec<u8>,
     hash_type: HashType,
+    sizes: Option<Vec<u64>>,
 }
 
 impl Hashes {
@@ -355,6 +530,44 @@ impl Hashes {
         Hashes {
             data: data,
             hash_type: hash_type,
+            sizes: None,
+        }
+    }
+
+    fn with_sizes(data: Vec<u8>, hash_type: HashType, sizes: Vec<u64>)
+        -> Hashes
+    {
+        Hashes {
+            data: data,
+            hash_type: hash_type,
+            sizes: Some(sizes),
+        }
+    }
+
+    /// Sizes of the chunks for content-defined chunking
+    ///
+    /// Returns `None` if the file is split into fixed size blocks.
+    pub fn chunk_sizes(&self) -> Option<&[u64]> {
+        self.sizes.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns offset and length of the block for every hash
+    ///
+    /// `size` and `block_size` are the file size and the block size from
+    /// the header, they are only used for fixed size blocks.
+    pub fn blocks(&self, size: u64, block_size: u64) -> Vec<(u64, u64)> {
+        match self.sizes {
+            Some(ref sizes) => {
+                let mut offset = 0;
+                sizes.iter().map(|&len| {
+                    offset += len;
+                    (offset - len, len)
+                }).collect()
+            }
+            None => (0..self.len() as u64).map(|i| {
+                let offset = i * block_size;
+                (offset, std::cmp::min(block_size, size - offset))
+            }).collect(),
         }
     }
 
@@ -372,8 +585,8 @@ impl Hashes {
 /// Represents an entry from dir signature file
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum Entry {
-    /// Direcory
-    Dir(PathBuf),
+    /// Direcory and its extended attributes
+    Dir(PathBuf, Option<Xattrs>),
     /// File
     File {
         /// File path (joined with current directory)
@@ -383,59 +596,57 @@ pub enum Entry {
         /// File size
         size: u64,
         /// Blocks hashes
-        hashes: Hashes
+        hashes: Hashes,
+        /// Hash of the whole file, only if the header has `file_hash=1`
+        file_hash: Option<Vec<u8>>,
+        /// Extended attributes, only if the header has `xattrs=1`
+        xattrs: Option<Xattrs>,
     },
-    /// Link
-    Link(PathBuf, PathBuf),
+    /// Link, its destination and extended attributes
+    Link(PathBuf, PathBuf, Option<Xattrs>),
 }
 
 impl Entry {
-    fn parse(row: &[u8], current_dir: &Path, hash_type: HashType, block_size: u64)
+    fn parse(row: &[u8], current_dir: &Path, hash_type: HashType,
+        block_size: u64, chunking: Chunking, file_hash: bool, xattrs: bool)
         -> Result<Option<Entry>, ParseRowError>
     {
-        let (entry, tail) = if row.starts_with(b"/") {
-            let (path, row) = parse_path_buf(row)?;
-            (Entry::Dir(path), row)
-        } else if row.starts_with(b"  ") {
-            let row = &row[2..];
-            let (path, row) = parse_path(row)?;
-            let path = current_dir.join(&path);
-            let (file_type, row) = parse_os_str(row)?;
-            if file_type == "f" || file_type == "x" {
-                let (file_size, row) = parse_u64(row)?;
-                let hashes_num = ((file_size + block_size - 1) / block_size) as usize;
-                let (hashes_data, row) = parse_hashes(row, hash_type, hashes_num)?;
-                let hashes = Hashes::new(hashes_data, hash_type);
-                (Entry::File {
-                    path: path,
-                    exe: file_type == "x",
-                    size: file_size,
-                    hashes: hashes },
-                 row)
-            } else if file_type == "s" {
-                let (dest, row) = parse_path_buf(row)?;
-                (Entry::Link(path, dest), row)
-            } else {
-                return Err(ParseRowError::InvalidFileType(
-                    format!("{}", String::from_utf8_lossy(file_type.as_bytes()))));
-            }
-        } else {
-            return Ok(None);
-        };
-        if !tail.is_empty() {
-            return Err(ParseRowError::InvalidLine(
-                format!("Entry is not fully consumed: {:?}",
-                    String::from_utf8_lossy(tail))));
-        }
-        Ok(Some(entry))
+        Ok(EntryRef::parse(row, current_dir, hash_type, block_size,
+                chunking, file_hash, xattrs)?
+            .map(EntryRef::into_owned))
     }
 
     /// Get path of the entry
     pub fn path(&self) -> &Path {
         match *self {
-            Entry::Dir(ref path) |
+            Entry::Dir(ref path, _) |
             Entry::File{ref path, ..} |
-            Entry::Link(ref path, _) => path
+            Entry::Link(ref path, _, _) => path
+        }
+    }
+
+    /// Returns extended attributes of the entry
+    ///
+    /// It's `None` if the signature file is written without `xattrs=1`.
+    pub fn xattrs(&self) -> Option<&Xattrs> {
+        match *self {
+            Entry::Dir(_, ref xattrs) |
+            Entry::File { ref xattrs, .. } |
+            Entry::Link(_, _, ref xattrs) => xattrs.as_ref(),
+        }
+    }
+
+    /// Checks whether two file entries have the same contents
+    ///
+    /// Compares sizes and hashes of every block. Paths and executable bits
+    /// are not compared. Returns `false` if either entry isn't a file.
+    pub fn same_contents(&self, other: &Entry) -> bool {
+        match (self, other) {
+            (&Entry::File { size: a_size, hashes: ref a_hashes, .. },
+             &Entry::File { size: b_size, hashes: ref b_hashes, .. }) => {
+                a_size == b_size && a_hashes == b_hashes
+            }
+            _ => false,
         }
     }
 
@@ -444,9 +655,259 @@ impl Entry {
     /// method
     pub fn kind(&self) -> EntryKind<&Path> {
         match *self {
-            Entry::Dir(ref path) => EntryKind::Dir(path.as_ref()),
+            Entry::Dir(ref path, _) => EntryKind::Dir(path.as_ref()),
             Entry::File{ref path, ..} |
-            Entry::Link(ref path, _) => EntryKind::File(path.as_ref()),
+            Entry::Link(ref path, _, _) => EntryKind::File(path.as_ref()),
+        }
+    }
+}
+
+/// Borrowed view of an entry, see `EntryIterator::next_ref`
+///
+/// Names and hashes refer to the line buffer of the iterator and files
+/// refer to its current directory, so files and links are parsed without
+/// allocations unless their names contain escapes or the signature file
+/// has extended attributes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntryRef<'a> {
+    /// Direcory and its extended attributes
+    Dir(Cow<'a, Path>, Option<Xattrs>),
+    /// File
+    File {
+        /// Directory of the file
+        dir: &'a Path,
+        /// File name
+        name: Cow<'a, Path>,
+        /// Is executable
+        exe: bool,
+        /// File size
+        size: u64,
+        /// Blocks hashes
+        hashes: HashesRef<'a>,
+        /// Hex encoded hash of the whole file, only if the header has
+        /// `file_hash=1`
+        file_hash: Option<&'a [u8]>,
+        /// Extended attributes, only if the header has `xattrs=1`
+        xattrs: Option<Xattrs>,
+    },
+    /// Link
+    Link {
+        /// Directory of the link
+        dir: &'a Path,
+        /// Link name
+        name: Cow<'a, Path>,
+        /// Link destination
+        dest: Cow<'a, Path>,
+        /// Extended attributes, only if the header has `xattrs=1`
+        xattrs: Option<Xattrs>,
+    },
+}
+
+/// Hex encoded hashes of a file, see `EntryRef`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashesRef<'a> {
+    /// Space separated hashes, with `LEN:` prefixes for chunks
+    data: &'a [u8],
+    hash_type: HashType,
+    len: usize,
+    chunked: bool,
+}
+
+/// Iterator over hex encoded hashes, see `HashesRef::iter`
+pub struct HexHashes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> EntryRef<'a> {
+    fn parse(row: &'a [u8], current_dir: &'a Path, hash_type: HashType,
+        block_size: u64, chunking: Chunking, file_hash: bool, xattrs: bool)
+        -> Result<Option<EntryRef<'a>>, ParseRowError>
+    {
+        if row.starts_with(b"/") {
+            let (path, xattrs) = EntryRef::parse_dir(row, xattrs)?;
+            Ok(Some(EntryRef::Dir(path, xattrs)))
+        } else if row.starts_with(b"  ") {
+            EntryRef::parse_file(&row[2..], current_dir, hash_type,
+                block_size, chunking, file_hash, xattrs).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_dir(row: &'a [u8], xattrs: bool)
+        -> Result<(Cow<'a, Path>, Option<Xattrs>), ParseRowError>
+    {
+        let (path, row) = parse_path(row)?;
+        let (xattrs, row) = parse_xattrs(row, xattrs)?;
+        check_consumed(row)?;
+        Ok((path, xattrs))
+    }
+
+    /// Parses file or link row without the leading spaces
+    fn parse_file(row: &'a [u8], dir: &'a Path, hash_type: HashType,
+        block_size: u64, chunking: Chunking, file_hash: bool, xattrs: bool)
+        -> Result<EntryRef<'a>, ParseRowError>
+    {
+        let (name, row) = parse_path(row)?;
+        let (file_type, row) = parse_os_str(row)?;
+        let (entry, tail) = if file_type == "f" || file_type == "x" {
+            let (xattrs, row) = parse_xattrs(row, xattrs)?;
+            let (file_size, row) = parse_u64(row)?;
+            let (file_hash, row) = if file_hash {
+                let (hash, row) = split_hashes(row, hash_type, 1)?;
+                (Some(hash), row)
+            } else {
+                (None, row)
+            };
+            let (hashes, row) = match chunking {
+                Chunking::Fixed => {
+                    let hashes_num =
+                        ((file_size + block_size - 1) / block_size) as usize;
+                    let (data, row) =
+                        split_hashes(row, hash_type, hashes_num)?;
+                    (HashesRef::new(data, hash_type, hashes_num, false), row)
+                }
+                Chunking::FastCdc => {
+                    let (data, num, row) =
+                        split_chunks(row, hash_type, file_size)?;
+                    (HashesRef::new(data, hash_type, num, true), row)
+                }
+            };
+            (EntryRef::File {
+                dir: dir,
+                name: name,
+                exe: file_type == "x",
+                size: file_size,
+                hashes: hashes,
+                file_hash: file_hash,
+                xattrs: xattrs },
+             row)
+        } else if file_type == "s" {
+            let (xattrs, row) = parse_xattrs(row, xattrs)?;
+            let (dest, row) = parse_path(row)?;
+            (EntryRef::Link {
+                dir: dir,
+                name: name,
+                dest: dest,
+                xattrs: xattrs },
+             row)
+        } else {
+            return Err(ParseRowError::InvalidFileType(
+                format!("{}", String::from_utf8_lossy(file_type.as_bytes()))));
+        };
+        check_consumed(tail)?;
+        Ok(entry)
+    }
+
+    /// Get path of the entry
+    ///
+    /// Allocates for files and links as the name is joined with the
+    /// directory.
+    pub fn path(&self) -> Cow<Path> {
+        match *self {
+            EntryRef::Dir(ref path, _) => Cow::Borrowed(path),
+            EntryRef::File { dir, ref name, .. } |
+            EntryRef::Link { dir, ref name, .. } => {
+                Cow::Owned(dir.join(name))
+            }
+        }
+    }
+
+    /// Returns extended attributes of the entry
+    ///
+    /// It's `None` if the signature file is written without `xattrs=1`.
+    pub fn xattrs(&self) -> Option<&Xattrs> {
+        match *self {
+            EntryRef::Dir(_, ref xattrs) |
+            EntryRef::File { ref xattrs, .. } |
+            EntryRef::Link { ref xattrs, .. } => xattrs.as_ref(),
+        }
+    }
+
+    /// Converts into an owned `Entry`
+    pub fn into_owned(self) -> Entry {
+        match self {
+            EntryRef::Dir(path, xattrs) => {
+                Entry::Dir(path.into_owned(), xattrs)
+            }
+            EntryRef::File { dir, name, exe, size, hashes, file_hash, xattrs }
+            => {
+                Entry::File {
+                    path: dir.join(&name),
+                    exe: exe,
+                    size: size,
+                    hashes: hashes.to_hashes(),
+                    file_hash: file_hash.map(|hash| decode_hashes(hash,
+                        hashes.hash_type.output_bytes())),
+                    xattrs: xattrs,
+                }
+            }
+            EntryRef::Link { dir, name, dest, xattrs } => {
+                Entry::Link(dir.join(&name), dest.into_owned(), xattrs)
+            }
+        }
+    }
+}
+
+impl<'a> HashesRef<'a> {
+    fn new(data: &'a [u8], hash_type: HashType, len: usize, chunked: bool)
+        -> HashesRef<'a>
+    {
+        HashesRef {
+            data: data,
+            hash_type: hash_type,
+            len: len,
+            chunked: chunked,
+        }
+    }
+
+    /// Number of hashes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns iterator over hex encoded hashes
+    pub fn iter(&self) -> HexHashes<'a> {
+        HexHashes { data: self.data }
+    }
+
+    /// Decodes hashes
+    pub fn to_hashes(&self) -> Hashes {
+        let data = decode_hashes(self.data,
+            self.len * self.hash_type.output_bytes());
+        if self.chunked {
+            let sizes = self.data.split(|&c| c == b' ')
+                .filter(|field| !field.is_empty())
+                .map(|field| {
+                    let colon = field.iter().position(|&c| c == b':')
+                        .unwrap_or(0);
+                    std::str::from_utf8(&field[..colon]).ok()
+                        .and_then(|s| u64::from_str_radix(s, 10).ok())
+                        .expect("chunk size is checked by the parser")
+                })
+                .collect();
+            Hashes::with_sizes(data, self.hash_type, sizes)
+        } else {
+            Hashes::new(data, self.hash_type)
+        }
+    }
+}
+
+impl<'a> Iterator for HexHashes<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let (field, tail) = match self.data.iter().position(|&c| c == b' ') {
+            Some(pos) => (&self.data[..pos], &self.data[pos+1..]),
+            None => (self.data, &self.data[0..0]),
+        };
+        self.data = tail;
+        match field.iter().position(|&c| c == b':') {
+            Some(colon) => Some(&field[colon+1..]),
+            None => Some(field),
         }
     }
 }
@@ -454,35 +915,122 @@ impl Entry {
 /// v1 format parser
 pub struct Parser<R: BufRead> {
     header: Header,
-    reader: R,
+    reader: Decoder<R>,
+    /// Offset of the next line in the uncompressed data
+    position: u64,
+    hasher: Option<Box<dyn Hasher>>,
+    footer: Option<Footer>,
+    lookup: Option<Lookup>,
+    strict: bool,
 }
 
 impl<R: BufRead> Parser<R> {
     /// Creates a directory signature parser (format v1)
     /// Tries to parse header
-    pub fn new(mut reader: R) -> Result<Parser<R>, ParseError> {
+    ///
+    /// Files compressed with gzip or zstd are decompressed transparently.
+    pub fn new(reader: R) -> Result<Parser<R>, ParseError> {
+        Parser::with_hashes(reader, &[])
+    }
+
+    /// Creates a parser which also supports custom hash types
+    ///
+    /// Hash type of the signature file is looked up by name in `hashes`
+    /// if it's not one of the built-in ones.
+    pub fn with_hashes(reader: R, hashes: &[HashType])
+        -> Result<Parser<R>, ParseError>
+    {
+        let mut reader = Decoder::new(reader)?;
         let mut header_line = vec!();
-        read_line(&mut reader, &mut header_line).context(1)?;
-        let header = Header::parse(&header_line).context(1)?;
+        let position = read_line(&mut reader, &mut header_line).context(1)?;
+        let header = Header::parse(&header_line, hashes).context(1)?;
+        let mut hasher = hash::hasher(header.hash_type);
+        hasher.input(&header_line);
+        hasher.input(b"\n");
         Ok(Parser {
             header: header,
             reader: reader,
+            position: position,
+            hasher: Some(hasher),
+            footer: None,
+            lookup: None,
+            strict: false,
         })
     }
 
+    /// Don't check that footer matches the contents of the file
+    ///
+    /// By default all the lines are hashed while iterating and
+    /// `ParseRowError::ChecksumMismatch` is returned at the end if the
+    /// footer doesn't match. Disabling the check makes parsing faster but
+    /// truncated or corrupted files are only detected if they are not
+    /// parseable. Should be called before iterating.
+    pub fn skip_checksum(&mut self) -> &mut Self {
+        self.hasher = None;
+        self
+    }
+
+    /// Checks ordering and escaping rules of the format
+    ///
+    /// By default entries are only parsed, so unsorted directories and
+    /// files, duplicate names and names which are not escaped canonically
+    /// are accepted. Such files break `EntryIterator::advance` and merging
+    /// in subtle ways. In strict mode they are reported as
+    /// `ParseRowError::Unsorted`, `ParseRowError::Duplicate`,
+    /// `ParseRowError::OutsideDir` and `ParseRowError::NonCanonicalName`.
+    /// Iteration may be continued after these errors, the entry itself is
+    /// returned by the next call. Should be called before iterating.
+    pub fn strict(&mut self) -> &mut Self {
+        self.strict = true;
+        self
+    }
+
     /// Returns parsed `Header`
     pub fn get_header(&self) -> Header {
         self.header.clone()
     }
 
+    /// Returns parsed `Footer`
+    ///
+    /// The footer is available only when iterator has reached the end of
+    /// the signature file, otherwise `None` is returned.
+    pub fn get_footer(&self) -> Option<&Footer> {
+        self.footer.as_ref()
+    }
+
     /// Creates iterator over directory signature entries
     pub fn iter(&mut self) -> EntryIterator<R> {
-        EntryIterator::new(&mut self.reader,
-            self.header.hash_type, self.header.block_size)
+        EntryIterator::new(&mut self.reader, &mut self.position,
+            &mut self.hasher, &mut self.footer, &self.header, self.strict)
+    }
+
+    /// Builds a lookup file for random access to the signature file
+    ///
+    /// Reads all the entries, so it should be called on a freshly created
+    /// parser. The lookup can be saved with `Lookup::write` and used with
+    /// `set_lookup` when the signature file is opened next time.
+    pub fn build_lookup(&mut self) -> Result<Lookup, ParseError> {
+        let mut dirs = Vec::new();
+        {
+            let mut iter = self.iter();
+            loop {
+                let offset = *iter.position;
+                match iter.next() {
+                    Some(Ok(Entry::Dir(path, _))) => dirs.push((path, offset)),
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e),
+                    None => break,
+                }
+            }
+        }
+        let footer = self.footer.as_ref().expect("footer is parsed");
+        Ok(Lookup::new(footer.get_hash().to_vec(), dirs))
     }
 
     /// Consumes the parser returning ownership of the underlying reader
     ///
+    /// For compressed files position of the reader is unspecified, but
+    /// seeking to the start works the same way as for plain ones.
     /// It can be used to parse signature file again from the beginning:
     ///
     /// # Example
@@ -500,69 +1048,316 @@ impl<R: BufRead> Parser<R> {
     /// let mut parser = Parser::new(reader).unwrap();
     /// ```
     pub fn into_reader(self) -> R {
-        self.reader
+        self.reader.into_inner()
+    }
+}
+
+impl<R: BufRead + Seek> Parser<R> {
+    /// Sets a lookup file which allows to access entries by path
+    ///
+    /// Fails if the lookup file is built for another signature file or
+    /// if the signature file is compressed. Note that after `lookup` or
+    /// `iter_from` the checksum is not verified any more, since lines
+    /// before the found directory are not read.
+    pub fn set_lookup(&mut self, lookup: Lookup)
+        -> Result<&mut Self, LookupError>
+    {
+        let hash = {
+            let reader = self.reader.plain_mut()
+                .ok_or(LookupError::Compressed)?;
+            let position = reader.seek(SeekFrom::Current(0))?;
+            reader.seek(SeekFrom::Start(0))?;
+            let hash = get_hash(reader);
+            reader.seek(SeekFrom::Start(position))?;
+            hash?
+        };
+        if hash != lookup.get_hash() {
+            return Err(LookupError::HashMismatch);
+        }
+        self.lookup = Some(lookup);
+        Ok(self)
+    }
+
+    /// Checks that the file is signed by one of the trusted keys
+    ///
+    /// Should be called before reading entries, so that nothing from
+    /// untrusted file is used. The whole file is read from the start to
+    /// check that the entries match the footer, then the position is
+    /// restored.
+    pub fn check_signature(&mut self, signature: &Signature,
+        keys: &TrustedKeys)
+        -> Result<&mut Self, SignError>
+    {
+        let reader = self.reader.get_mut();
+        let position = reader.seek(SeekFrom::Current(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let result = keys.verify(&mut *reader, signature);
+        reader.seek(SeekFrom::Start(position))?;
+        result?;
+        Ok(self)
+    }
+
+    /// Finds an entry by path using the lookup file
+    ///
+    /// Only the directory of the entry is parsed, so it takes logarithmic
+    /// time in the number of directories. Returns `None` if there is no
+    /// such entry.
+    pub fn lookup<P: AsRef<Path>>(&mut self, path: P)
+        -> Result<Option<Entry>, LookupError>
+    {
+        let path = path.as_ref();
+        if self.seek_dir(path)? {
+            return match self.iter().next() {
+                Some(Ok(entry)) => Ok(Some(entry)),
+                Some(Err(e)) => Err(e.into()),
+                None => Ok(None),
+            };
+        }
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+        if !self.seek_dir(parent)? {
+            return Ok(None);
+        }
+        match self.iter().advance(&EntryKind::File(path)) {
+            Some(Ok(entry)) => Ok(Some(entry)),
+            Some(Err(e)) => Err(e.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates iterator starting at the directory using the lookup file
+    ///
+    /// The iterator yields the directory itself, then all the entries
+    /// after it up to the end of the signature file. Returns `None` if
+    /// there is no such directory.
+    pub fn iter_from<P: AsRef<Path>>(&mut self, dir: P)
+        -> Result<Option<EntryIterator<R>>, LookupError>
+    {
+        if !self.seek_dir(dir.as_ref())? {
+            return Ok(None);
+        }
+        Ok(Some(self.iter()))
+    }
+
+    /// Moves to the line of the directory, returns `false` if the
+    /// directory is not in the lookup file
+    fn seek_dir(&mut self, dir: &Path) -> Result<bool, LookupError> {
+        let offset = match self.lookup {
+            Some(ref lookup) => match lookup.dir_offset(dir) {
+                Some(offset) => offset,
+                None => return Ok(false),
+            },
+            None => return Err(LookupError::NoLookup),
+        };
+        self.reader.plain_mut().ok_or(LookupError::Compressed)?
+            .seek(SeekFrom::Start(offset))?;
+        // lines before the offset are skipped, checksum can't be verified
+        self.hasher = None;
+        self.footer = None;
+        self.position = offset;
+        Ok(true)
     }
 }
 
 /// Iterator over the entries of the signature file
 pub struct EntryIterator<'a, R: 'a + BufRead> {
-    reader: &'a mut R,
-    hash_type: HashType,
-    block_size: u64,
+    reader: &'a mut Decoder<R>,
+    position: &'a mut u64,
+    hasher: &'a mut Option<Box<dyn Hasher>>,
+    footer: &'a mut Option<Footer>,
+    header: &'a Header,
     current_row: Vec<u8>,
+    current_row_hashed: bool,
+    /// Current row is referenced by the `EntryRef` returned last
+    current_row_lent: bool,
     current_row_num: usize,
     current_dir: PathBuf,
+    dir_hash: Option<Vec<u8>>,
+    strict: Option<Strict>,
     exhausted: bool,
 }
 
+/// State of the strict validation, see `Parser::strict`
+struct Strict {
+    /// The entry returned last
+    last: Option<EntryKind<PathBuf>>,
+    /// Current directory and its parents with the names of their files
+    dirs: Vec<(PathBuf, Vec<OsString>)>,
+}
+
 impl<'a, R: BufRead> EntryIterator<'a, R> {
-    fn new(reader: &'a mut R, hash_type: HashType, block_size: u64)
-        -> EntryIterator<R>
+    fn new(reader: &'a mut Decoder<R>, position: &'a mut u64,
+        hasher: &'a mut Option<Box<dyn Hasher>>,
+        footer: &'a mut Option<Footer>, header: &'a Header, strict: bool)
+        -> EntryIterator<'a, R>
     {
         EntryIterator {
             reader: reader.by_ref(),
-            hash_type: hash_type,
-            block_size: block_size,
+            position: position,
+            hasher: hasher,
+            footer: footer,
+            header: header,
             current_row: vec!(),
+            current_row_hashed: false,
+            current_row_lent: false,
             current_row_num: 1,
             current_dir: PathBuf::new(),
+            dir_hash: None,
+            strict: if strict { Some(Strict::new()) } else { None },
             exhausted: false,
         }
     }
 
+    /// Accounts the current row in the checksum unless it's done already
+    fn hash_row(&mut self) {
+        hash_row(self.hasher, &self.current_row,
+            &mut self.current_row_hashed);
+    }
+
+    /// Drops the row referenced by the `EntryRef` returned last
+    fn release_row(&mut self) {
+        if self.current_row_lent {
+            self.current_row.clear();
+            self.current_row_lent = false;
+        }
+    }
+
     fn parse_entry(&mut self) -> Result<Option<Entry>, ParseError> {
+        Ok(self.parse_ref(false)?.map(EntryRef::into_owned))
+    }
+
+    /// Parses the current row, reads the next one if there is none
+    ///
+    /// If `lend` is `true` the row is dropped on the next call, otherwise
+    /// it's kept until the caller clears it.
+    fn parse_ref(&mut self, lend: bool)
+        -> Result<Option<EntryRef>, ParseError>
+    {
+        self.release_row();
         if self.exhausted {
             return Ok(None);
         }
-        self.current_row_num += 1;
+        self.dir_hash = None;
         if self.current_row.is_empty() {
-            read_line(self.reader.by_ref(), &mut self.current_row)
+            self.current_row_num += 1;
+            *self.position += read_line(self.reader.by_ref(),
+                    &mut self.current_row)
                 .context(self.current_row_num)?;
+            self.current_row_hashed = false;
         }
-        let row = &self.current_row[..];
-        let entry = Entry::parse(
-                row, &self.current_dir, self.hash_type, self.block_size)
-            .context(self.current_row_num)?;
-        match entry {
-            None => {
-                let _footer = Footer::parse(row, self.hash_type)
-                    .context(self.current_row_num)?;
-                let mut test_buf = [0; 1];
-                if self.reader.read(&mut test_buf)? != 0 {
+        self.current_row_lent = lend;
+        let header = self.header;
+        let row_num = self.current_row_num;
+        let mut row = &self.current_row[..];
+        let mut dir_hash = None;
+        if header.dir_hash && row.starts_with(b"/") {
+            let (path, hash) = split_dir_hash(row, header.hash_type)
+                .context(row_num)?;
+            row = path;
+            dir_hash = Some(hash);
+        }
+        let entry = if row.starts_with(b"/") {
+            let (path, xattrs) = EntryRef::parse_dir(row, header.xattrs)
+                .context(row_num)?;
+            self.current_dir = path.to_path_buf();
+            self.dir_hash = dir_hash;
+            EntryRef::Dir(path, xattrs)
+        } else if row.starts_with(b"  ") {
+            EntryRef::parse_file(&row[2..], &self.current_dir,
+                    header.hash_type, header.block_size,
+                    header.chunking, header.file_hash, header.xattrs)
+                .context(row_num)?
+        } else {
+            let footer = Footer::parse(row, header.hash_type)
+                .context(row_num)?;
+            if let Some(ref hasher) = *self.hasher {
+                if hasher.result() != footer.0 {
+                    self.exhausted = true;
                     return Err(ParseError::Parse(
-                        ParseRowError::InvalidLine(
-                            format!("Found extra lines after the footer")),
-                        self.current_row_num));
-                }
-                self.exhausted = true;
-                Ok(None)
-            },
-            Some(entry) => {
-                if let Entry::Dir(ref dir_path) = entry {
-                    self.current_dir = dir_path.clone();
+                        ParseRowError::ChecksumMismatch, row_num));
                 }
-                Ok(Some(entry))
-            },
+            }
+            let mut test_buf = [0; 1];
+            if self.reader.read(&mut test_buf)? != 0 {
+                return Err(ParseError::Parse(
+                    ParseRowError::InvalidLine(
+                        format!("Found extra lines after the footer")),
+                    row_num));
+            }
+            *self.footer = Some(footer);
+            self.exhausted = true;
+            return Ok(None);
+        };
+        // row is parsed again after `advance` stops before it
+        let mut violation = None;
+        if !self.current_row_hashed {
+            if let Some(ref mut strict) = self.strict {
+                violation = strict.check(&entry, row).err();
+            }
+        }
+        hash_row(self.hasher, &self.current_row,
+            &mut self.current_row_hashed);
+        if let Some(err) = violation {
+            // the entry itself is returned by the next call
+            self.current_row_lent = false;
+            return Err(ParseError::Parse(err, row_num));
+        }
+        Ok(Some(entry))
+    }
+
+    /// Returns the next entry borrowing the line buffer of the iterator
+    ///
+    /// Unlike `next` doesn't allocate a path and hashes for every entry,
+    /// see `EntryRef`. The entry is valid until the iterator is used
+    /// again. Both methods can be mixed.
+    pub fn next_ref(&mut self) -> Option<Result<EntryRef, ParseError>> {
+        match self.parse_ref(true) {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Returns hash of the subtree of the directory returned last
+    ///
+    /// It's `None` if the last returned entry is not a directory or if
+    /// the signature file doesn't have `dir_hash=1` in the header.
+    pub fn dir_hash(&self) -> Option<&[u8]> {
+        self.dir_hash.as_ref().map(|h| &h[..])
+    }
+
+    /// Skips all the entries inside the directory returned last
+    ///
+    /// Skipped lines are read and accounted in the checksum but not parsed.
+    /// This is useful to skip subtrees known to be equal by `dir_hash`.
+    pub fn skip_dir(&mut self) -> Result<(), ParseError> {
+        self.release_row();
+        if self.exhausted {
+            return Ok(());
+        }
+        self.dir_hash = None;
+        loop {
+            if self.current_row.is_empty() {
+                self.current_row_num += 1;
+                *self.position += read_line(self.reader.by_ref(),
+                        &mut self.current_row)
+                    .context(self.current_row_num)?;
+                self.current_row_hashed = false;
+            }
+            let inside = if self.current_row.starts_with(b"/") {
+                let (path, _) = parse_path_buf(&self.current_row)
+                    .context(self.current_row_num)?;
+                path.starts_with(&self.current_dir)
+            } else {
+                self.current_row.starts_with(b"  ")
+            };
+            if !inside {
+                return Ok(());
+            }
+            self.hash_row();
+            self.current_row.clear();
         }
     }
 
@@ -585,6 +1380,7 @@ impl<'a, R: BufRead> EntryIterator<'a, R> {
                             continue;
                         },
                         Greater => {
+                            // entry is returned by the next call
                             return None;
                         },
                         Equal => {
@@ -600,6 +1396,112 @@ impl<'a, R: BufRead> EntryIterator<'a, R> {
     }
 }
 
+impl Strict {
+    fn new() -> Strict {
+        Strict {
+            last: None,
+            dirs: Vec::new(),
+        }
+    }
+
+    /// Checks the entry against the entries returned before
+    ///
+    /// The state is updated even if the entry is invalid, so that the
+    /// following entries are checked too.
+    fn check(&mut self, entry: &EntryRef, row: &[u8])
+        -> Result<(), ParseRowError>
+    {
+        use std::cmp::Ordering::*;
+
+        let path = entry.path();
+        let path = &*path;
+        if !path.is_absolute() {
+            return Err(ParseRowError::OutsideDir(path.to_path_buf()));
+        }
+        let mut result = check_names(entry, row);
+        let kind = match *entry {
+            EntryRef::Dir(..) => EntryKind::Dir(path),
+            _ => EntryKind::File(path),
+        };
+        if let Some(ref last) = self.last {
+            match kind.cmp(&last.as_ref()) {
+                Greater => {}
+                Equal => {
+                    result = result.and(Err(ParseRowError::Duplicate(
+                        path.to_path_buf())));
+                }
+                Less => {
+                    result = result.and(Err(ParseRowError::Unsorted(
+                        path.to_path_buf())));
+                }
+            }
+        }
+        let name = path.file_name().unwrap_or(OsStr::new(""));
+        if let EntryRef::Dir(..) = *entry {
+            while self.dirs.last().map_or(false, |&(ref d, _)| {
+                !path.starts_with(d)
+            }) {
+                self.dirs.pop();
+            }
+            // files of the parent are listed before its subdirectories
+            if let Some(&(ref parent, ref names)) = self.dirs.last() {
+                if path.parent() == Some(parent) &&
+                    names.iter().any(|n| &n[..] == name)
+                {
+                    result = result.and(Err(ParseRowError::Duplicate(
+                        path.to_path_buf())));
+                }
+            }
+            self.dirs.push((path.to_path_buf(), Vec::new()));
+        } else if let Some(&mut (_, ref mut names)) = self.dirs.last_mut() {
+            names.push(name.to_os_string());
+        }
+        self.last = Some(kind.cloned());
+        result
+    }
+}
+
+/// Checks that names in the row are escaped the same way as the writer
+/// does it, so there is the only way to write every path
+fn check_names(entry: &EntryRef, row: &[u8])
+    -> Result<(), ParseRowError>
+{
+    match *entry {
+        EntryRef::Dir(ref path, _) => {
+            if path.components().any(|c| c == Component::ParentDir) {
+                return Err(ParseRowError::NonCanonicalName(
+                    format!("{}", Name(path))));
+            }
+            check_name(path, parse_field(row)?.0)
+        }
+        EntryRef::File { ref name, .. } | EntryRef::Link { ref name, .. } => {
+            let raw = parse_field(&row[2..])?.0;
+            if name.as_os_str().as_bytes().contains(&b'/') {
+                return Err(ParseRowError::NonCanonicalName(
+                    String::from_utf8_lossy(raw).into_owned()));
+            }
+            check_name(name, raw)?;
+            if let EntryRef::Link { ref dest, .. } = *entry {
+                let raw = match row.iter().rposition(|&c| c == b' ') {
+                    Some(pos) => &row[pos+1..],
+                    None => row,
+                };
+                check_name(dest, raw)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn check_name(path: &Path, raw: &[u8]) -> Result<(), ParseRowError> {
+    let canonical = format!("{}", Name(path));
+    if raw.is_empty() || canonical.as_bytes() != raw {
+        return Err(ParseRowError::NonCanonicalName(
+            String::from_utf8_lossy(raw).into_owned()));
+    }
+    Ok(())
+}
+
 impl<'a, R: BufRead> Iterator for EntryIterator<'a, R> {
     type Item = Result<Entry, ParseError>;
 
@@ -609,21 +1511,25 @@ impl<'a, R: BufRead> Iterator for EntryIterator<'a, R> {
             Ok(None) => None,
             Err(e) => Some(Err(e)),
         };
-        self.current_row.clear();
+        match res {
+            Some(Err(ParseError::Parse(ref e, _))) if e.is_violation() => {}
+            _ => self.current_row.clear(),
+        }
         res
     }
 }
 
+/// Reads a line without the newline, returns number of bytes read
 fn read_line<R: BufRead>(reader: &mut R, mut buf: &mut Vec<u8>)
-    -> Result<(), ParseRowError>
+    -> Result<u64, ParseRowError>
 {
-    let _ = reader.read_until(b'\n', &mut buf)?;
+    let bytes = reader.read_until(b'\n', &mut buf)?;
     if !buf.ends_with(b"\n") {
         return Err(ParseRowError::InvalidLine(
             format!("Every line must end with a newline")));
     }
     buf.pop();
-    Ok(())
+    Ok(bytes as u64)
 }
 
 fn parse_path<'a>(data: &'a [u8])
@@ -637,7 +1543,7 @@ fn parse_path<'a>(data: &'a [u8])
     Ok((unescaped_path, tail))
  }
 
-fn parse_path_buf<'a>(data: &'a [u8])
+pub fn parse_path_buf<'a>(data: &'a [u8])
     -> Result<(PathBuf, &'a [u8]), ParseRowError>
 {
     let (path, tail) = parse_os_str(data)?;
@@ -652,7 +1558,7 @@ fn parse_os_str<'a>(data: &'a [u8])
     Ok((OsStr::from_bytes(field), tail))
 }
 
-fn parse_u64<'a>(data: &'a [u8])
+pub fn parse_u64<'a>(data: &'a [u8])
     -> Result<(u64, &'a [u8]), ParseRowError>
 {
     let (field, tail) = parse_field(data)?;
@@ -661,7 +1567,7 @@ fn parse_u64<'a>(data: &'a [u8])
     Ok((v, tail))
 }
 
-fn parse_field<'a>(data: &'a [u8])
+pub fn parse_field<'a>(data: &'a [u8])
     -> Result<(&'a [u8], &'a [u8]), ParseRowError>
 {
 
@@ -678,18 +1584,25 @@ fn parse_field<'a>(data: &'a [u8])
 fn parse_hashes<'a>(data: &'a [u8], hash_type: HashType, hashes_num: usize)
     -> Result<(Vec<u8>, &'a [u8]), ParseRowError>
 {
-    let mut data = data;
+    let (hashes, tail) = split_hashes(data, hash_type, hashes_num)?;
+    Ok((decode_hashes(hashes, hashes_num * hash_type.output_bytes()), tail))
+}
 
-    let digest_len = hash_type.output_bytes();
-    let hash_len = digest_len * 2;
+/// Checks `hashes_num` hex encoded hashes, returns them and the rest of
+/// the row
+fn split_hashes<'a>(data: &'a [u8], hash_type: HashType, hashes_num: usize)
+    -> Result<(&'a [u8], &'a [u8]), ParseRowError>
+{
+    let mut tail = data;
+    let hash_len = hash_type.output_bytes() * 2;
 
     let mut i = 0;
-    let mut buf = Vec::with_capacity(hashes_num * digest_len);
+    let mut end = 0;
     loop {
         if i == hashes_num {
             break;
         }
-        let (hash, tail) = parse_field(data)?;
+        let (hash, rest) = parse_field(tail)?;
         if hash.is_empty() {
             break;
         }
@@ -699,19 +1612,164 @@ fn parse_hashes<'a>(data: &'a [u8], hash_type: HashType, hashes_num: usize)
                     hash_len, String::from_utf8_lossy(hash))));
         }
         for d in hash.chunks(2) {
-            buf.push(parse_hex(d)?);
+            parse_hex(d)?;
         }
-        data = tail;
+        end = data.len() - tail.len() + hash.len();
+        tail = rest;
         i += 1;
     }
 
     if i != hashes_num {
         return Err(ParseRowError::InvalidHash(
-            format!("Expected {} hashes but found {}",
-                hashes_num, buf.len() / digest_len)));
+            format!("Expected {} hashes but found {}", hashes_num, i)));
+    }
+
+    Ok((&data[..end], tail))
+}
+
+/// Decodes hashes checked by `split_hashes` or `split_chunks`
+///
+/// Sizes of the chunks are skipped.
+fn decode_hashes(data: &[u8], capacity: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(capacity);
+    for hash in (HexHashes { data: data }) {
+        for d in hash.chunks(2) {
+            buf.push(parse_hex_unchecked(d));
+        }
     }
+    buf
+}
 
-    Ok((buf, data))
+/// Splits directory line to the rest of the line and the hash of the
+/// subtree which is the last field
+fn split_dir_hash(row: &[u8], hash_type: HashType)
+    -> Result<(&[u8], Vec<u8>), ParseRowError>
+{
+    let (head, tail) = match row.iter().rposition(|&c| c == b' ') {
+        Some(pos) => (&row[..pos], &row[pos+1..]),
+        None => (row, &row[0..0]),
+    };
+    if head.ends_with(b" ") {
+        return Err(ParseRowError::InvalidLine(
+            format!("Row has multiple spaces")));
+    }
+    let (hash, _) = parse_hashes(tail, hash_type, 1)?;
+    Ok((head, hash))
+}
+
+/// Parses extended attributes field if `enabled`
+///
+/// The field is either `-` or comma-separated `name=value` pairs sorted
+/// by name.
+fn parse_xattrs<'a>(data: &'a [u8], enabled: bool)
+    -> Result<(Option<Xattrs>, &'a [u8]), ParseRowError>
+{
+    if !enabled {
+        return Ok((None, data));
+    }
+    let (field, tail) = parse_field(data)?;
+    if field == b"-" {
+        return Ok((Some(Xattrs::default()), tail));
+    }
+    let mut items: Vec<(OsString, Vec<u8>)> = Vec::new();
+    for pair in field.split(|&c| c == b',') {
+        let mut kv = pair.splitn(2, |&c| c == b'=');
+        let (name, value) = match (kv.next(), kv.next()) {
+            (Some(name), Some(value)) if !name.is_empty() => (name, value),
+            _ => {
+                return Err(ParseRowError::InvalidXattrs(
+                    format!("Invalid attribute: {:?}",
+                        String::from_utf8_lossy(pair))));
+            }
+        };
+        let name = unescape_hex(OsStr::from_bytes(name)).into_owned();
+        let value = unescape_hex(OsStr::from_bytes(value)).into_owned();
+        if let Some(&(ref prev, _)) = items.last() {
+            if *prev >= name {
+                return Err(ParseRowError::InvalidXattrs(
+                    format!("Attributes are not sorted: {:?}", name)));
+            }
+        }
+        items.push((name, value.into_vec()));
+    }
+    Ok((Some(Xattrs::new(items)), tail))
+}
+
+/// Parses `LEN:HASH` pairs until chunk sizes add up to the `file_size`
+fn parse_chunks<'a>(data: &'a [u8], hash_type: HashType, file_size: u64)
+    -> Result<(Hashes, &'a [u8]), ParseRowError>
+{
+    let (chunks, num, tail) = split_chunks(data, hash_type, file_size)?;
+    Ok((HashesRef::new(chunks, hash_type, num, true).to_hashes(), tail))
+}
+
+/// Checks `LEN:HASH` pairs, returns them, their number and the rest of
+/// the row
+fn split_chunks<'a>(data: &'a [u8], hash_type: HashType, file_size: u64)
+    -> Result<(&'a [u8], usize, &'a [u8]), ParseRowError>
+{
+    let mut tail = data;
+    let hash_len = hash_type.output_bytes() * 2;
+    let mut num = 0;
+    let mut end = 0;
+    let mut total = 0u64;
+    while total < file_size {
+        let (field, rest) = parse_field(tail)?;
+        if field.is_empty() {
+            break;
+        }
+        let colon = field.iter().position(|&c| c == b':')
+            .ok_or_else(|| ParseRowError::InvalidHash(
+                format!("Expected chunk size before hash: {:?}",
+                    String::from_utf8_lossy(field))))?;
+        let (len, _) = parse_u64(&field[..colon])?;
+        let hash = &field[colon+1..];
+        if len == 0 {
+            return Err(ParseRowError::InvalidHash(
+                format!("Empty chunk: {:?}", String::from_utf8_lossy(field))));
+        }
+        if hash.len() != hash_len {
+            return Err(ParseRowError::InvalidHash(
+                format!("Expected hash with length of {}: {:?}",
+                    hash_len, String::from_utf8_lossy(hash))));
+        }
+        for d in hash.chunks(2) {
+            parse_hex(d)?;
+        }
+        num += 1;
+        total = total.saturating_add(len);
+        end = data.len() - tail.len() + field.len();
+        tail = rest;
+    }
+    if total != file_size {
+        return Err(ParseRowError::InvalidHash(
+            format!("Chunks have total size of {} but file size is {}",
+                total, file_size)));
+    }
+    Ok((&data[..end], num, tail))
+}
+
+/// Checks that nothing is left after the last field of the entry
+fn check_consumed(tail: &[u8]) -> Result<(), ParseRowError> {
+    if !tail.is_empty() {
+        return Err(ParseRowError::InvalidLine(
+            format!("Entry is not fully consumed: {:?}",
+                String::from_utf8_lossy(tail))));
+    }
+    Ok(())
+}
+
+/// Accounts the row in the checksum unless it's done already
+fn hash_row(hasher: &mut Option<Box<dyn Hasher>>, row: &[u8],
+    hashed: &mut bool)
+{
+    if !*hashed {
+        if let Some(ref mut hasher) = *hasher {
+            hasher.input(row);
+            hasher.input(b"\n");
+        }
+        *hashed = true;
+    }
 }
 
 fn unescape_hex(s: &OsStr) -> Cow<OsStr> {
@@ -746,7 +1804,7 @@ fn unescape_hex(s: &OsStr) -> Cow<OsStr> {
     Cow::Owned(OsString::from_vec(v))
 }
 
-fn parse_hex(v: &[u8]) -> Result<u8, ParseRowError> {
+pub fn parse_hex(v: &[u8]) -> Result<u8, ParseRowError> {
     Ok((hex_to_digit(v[0])? << 4) | hex_to_digit(v[1])?)
 }
 
@@ -789,9 +1847,10 @@ mod test {
 
     use rustc_serialize::hex::FromHex;
 
-    use ::HashType;
-    use super::{Entry, Footer, Hashes, Header, ParseRowError};
-    use super::{parse_hashes, parse_hex, is_hex, is_hex_encoding, unescape_hex};
+    use {HashType, Chunking, Xattrs};
+    use super::{Entry, EntryRef, Footer, Hashes, Header, ParseRowError};
+    use super::{parse_hashes, parse_chunks};
+    use super::{parse_hex, is_hex, is_hex_encoding, unescape_hex};
 
     #[test]
     fn test_entry_kind_ord() {
@@ -817,78 +1876,140 @@ mod test {
 
     #[test]
     fn test_header_parse() {
-        let res = Header::parse(b"");
+        let res = Header::parse(b"", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::MissingHeader)),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"\xff");
+        let res = Header::parse(b"\xff", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::InvalidHeader(ref msg))
                 if msg.starts_with("invalid utf-8")),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"DIRSIGNATURE");
+        let res = Header::parse(b"DIRSIGNATURE", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::MissingVersion)),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"DIRSIGNATURE.v2");
+        let res = Header::parse(b"DIRSIGNATURE.v2", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::InvalidVersion(ref v))
                 if v == "v2"),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"DIRSIGNATURE.v1");
+        let res = Header::parse(b"DIRSIGNATURE.v1", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::MissingHashType)),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"DIRSIGNATURE.v1 sha512/25");
+        let res = Header::parse(b"DIRSIGNATURE.v1 sha512/25", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::UnsupportedHashType(ref h))
                 if h == "sha512/25"),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"DIRSIGNATURE.v1 sha512/256 size=2");
+        let res = Header::parse(b"DIRSIGNATURE.v1 sha512/256 size=2", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::MissingBlockSize)),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"DIRSIGNATURE.v1 sha512/256 block_size=dead");
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=dead", &[]);
         assert!(matches!(res,
                 Err(ParseRowError::InvalidBlockSize(ref b))
                 if b == "dead"),
             "Result was: {:?}", res);
 
-        let res = Header::parse(b"DIRSIGNATURE.v1 sha512/256 block_size=1234");
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=0", &[]);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidBlockSize(ref b))
+                if b == "0"),
+            "Result was: {:?}", res);
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1234", &[]);
         let header = res.unwrap();
         assert_eq!(header.get_version(), "v1");
         assert!(matches!(header.get_hash_type(), HashType::Sha512_256));
         assert_eq!(header.get_block_size(), 1234);
+        assert_eq!(header.get_chunking(), Chunking::Fixed);
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1024 chunking=fastcdc", &[]);
+        let header = res.unwrap();
+        assert_eq!(header.get_chunking(), Chunking::FastCdc);
+        assert!(header.get_extra().is_empty());
+        assert!(!header.has_file_hash());
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1024 file_hash=1", &[]);
+        let header = res.unwrap();
+        assert!(header.has_file_hash());
+        assert!(!header.has_dir_hash());
+        assert!(header.get_extra().is_empty());
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1024 dir_hash=1", &[]);
+        let header = res.unwrap();
+        assert!(header.has_dir_hash());
+        assert!(!header.has_xattrs());
+        assert!(header.get_extra().is_empty());
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1024 xattrs=1", &[]);
+        let header = res.unwrap();
+        assert!(header.has_xattrs());
+        assert!(header.get_extra().is_empty());
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1024 dir_hash=1 dir_hash=1",
+            &[]);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidHeader(ref msg))
+                if msg == "Duplicate key: \"dir_hash\""),
+            "Result was: {:?}", res);
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1024 file_hash=yes", &[]);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidHeader(ref msg))
+                if msg == "Invalid file_hash: \"yes\""),
+            "Result was: {:?}", res);
+
+        let res = Header::parse(
+            b"DIRSIGNATURE.v1 sha512/256 block_size=1024 chunking=rabin", &[]);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidHeader(ref msg))
+                if msg == "Unsupported chunking: \"rabin\""),
+            "Result was: {:?}", res);
     }
 
     #[test]
     fn test_entry_parse() {
         let t = HashType::Sha512_256;
         let b = 32768;
+        let c = Chunking::Fixed;
+        let f = false;
 
-        let res = Entry::parse(b"", Path::new(""), t, b);
+        let res = Entry::parse(b"", Path::new(""), t, b, c, f, f);
         assert!(matches!(res, Ok(None)));
 
-        let res = Entry::parse(b"/test", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"/test", Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
-                Ok(Some(Entry::Dir(ref dir_path)))
+                Ok(Some(Entry::Dir(ref dir_path, _)))
                 if dir_path == Path::new("/test")),
             "Result was: {:?}", res);
 
-        let res = Entry::parse(b"/test\\x20escaped\\x5cx20", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"/test\\x20escaped\\x5cx20",
+            Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
-                Ok(Some(Entry::Dir(ref dir_path)))
+                Ok(Some(Entry::Dir(ref dir_path, _)))
                 if dir_path == Path::new("/test escaped\\x20")),
             "Result was: {:?}", res);
 
-        let res = Entry::parse(b"  test f 0", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"  test f 0", Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
                 Ok(Some(Entry::File { ref path, exe, size, .. }))
                 if path == Path::new("/dir/test") && !exe && size == 0),
@@ -896,42 +2017,122 @@ mod test {
 
         let res = Entry::parse(
             b"  test x 100 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc",
-            Path::new("/dir"), t, b);
+            Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
                 Ok(Some(Entry::File { ref path, exe, size, .. }))
                 if path == Path::new("/dir/test") && exe && size == 100),
             "Result was: {:?}", res);
 
-        let res = Entry::parse(b"  test s ../dest", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"  test s ../dest",
+            Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
-                Ok(Some(Entry::Link(ref path, ref dest)))
+                Ok(Some(Entry::Link(ref path, ref dest, _)))
                 if path == Path::new("/dir/test") && dest == Path::new("../dest")),
             "Result was: {:?}", res);
 
-        let res = Entry::parse(b"  test f x00", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"  test f x00", Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
                 Err(ParseRowError::InvalidInt(..))),
             "Result was: {:?}", res);
 
-        let res = Entry::parse(b"  test l ../dest", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"  test l ../dest",
+            Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
                 Err(ParseRowError::InvalidFileType(ref t))
                 if t == "l"),
             "Result was: {:?}", res);
 
-        let res = Entry::parse(b"  test s  ../dest", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"  test s  ../dest",
+            Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
                 Err(ParseRowError::InvalidLine(ref msg))
                 if msg == "Row has multiple spaces"),
             "Result was: {:?}", res);
 
-        let res = Entry::parse(b"  test s ../dest tail", Path::new("/dir"), t, b);
+        let res = Entry::parse(b"  test s ../dest tail",
+            Path::new("/dir"), t, b, c, f, f);
         assert!(matches!(res,
                 Err(ParseRowError::InvalidLine(ref msg))
                 if msg.starts_with("Entry is not fully consumed: \"tail\"")),
             "Result was: {:?}", res);
     }
 
+    #[test]
+    fn test_entry_parse_file_hash() {
+        let t = HashType::Sha512_256;
+        let b = 32768;
+        let c = Chunking::Fixed;
+
+        let res = Entry::parse(
+            b"  test f 0 cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce",
+            Path::new("/dir"), t, b, c, true, false);
+        let hash = "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce"
+            .from_hex().unwrap();
+        assert!(matches!(res, Ok(Some(Entry::File { ref file_hash, .. }))
+                if file_hash.as_ref() == Some(&hash)),
+            "Result was: {:?}", res);
+
+        let res = Entry::parse(b"  test f 0", Path::new("/dir"), t, b, c, true, false);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidHash(ref msg))
+                if msg == "Expected 1 hashes but found 0"),
+            "Result was: {:?}", res);
+
+        let res = Entry::parse(b"  test f 0", Path::new("/dir"),
+            t, b, c, false, false);
+        assert!(matches!(res, Ok(Some(Entry::File { file_hash: None, .. }))),
+            "Result was: {:?}", res);
+    }
+
+    #[test]
+    fn test_entry_parse_xattrs() {
+        let t = HashType::Sha512_256;
+        let b = 32768;
+        let c = Chunking::Fixed;
+
+        let res = EntryRef::parse(b"/test -", Path::new(""),
+            t, b, c, false, true);
+        let entry = res.unwrap().unwrap();
+        assert_eq!(entry.path(), Path::new("/test"));
+        assert_eq!(entry.xattrs(), Some(&Xattrs::default()));
+
+        let res = EntryRef::parse(
+            b"  test x security.capability=\\x01\\x00,user.a\\x3d=b\\x2cc 0",
+            Path::new("/dir"), t, b, c, false, true);
+        let entry = res.unwrap().unwrap();
+        {
+            let xattrs = entry.xattrs().unwrap();
+            assert_eq!(xattrs.len(), 2);
+            assert_eq!(xattrs.get("security.capability"),
+                Some(&b"\x01\x00"[..]));
+            assert_eq!(xattrs.get("user.a="), Some(&b"b,c"[..]));
+        }
+        assert!(matches!(entry, EntryRef::File { exe: true, size: 0, .. }));
+
+        let res = EntryRef::parse(b"  test s user.a= ../dest",
+            Path::new("/dir"), t, b, c, false, true);
+        assert!(matches!(res,
+                Ok(Some(EntryRef::Link { ref dest, xattrs: Some(ref xattrs),
+                                         .. }))
+                if dest == Path::new("../dest") &&
+                   xattrs.get("user.a") == Some(b"")),
+            "Result was: {:?}", res);
+
+        let res = Entry::parse(b"  test f user.b=1,user.a=2 0",
+            Path::new("/dir"), t, b, c, false, true);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidXattrs(ref msg))
+                if msg == "Attributes are not sorted: \"user.a\""),
+            "Result was: {:?}", res);
+
+        let res = Entry::parse(b"  test f 0",
+            Path::new("/dir"), t, b, c, false, true);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidXattrs(ref msg))
+                if msg == "Invalid attribute: \"0\""),
+            "Result was: {:?}", res);
+    }
+
     #[test]
     fn test_parse_hashes() {
         let res = parse_hashes(
@@ -971,6 +2172,48 @@ mod test {
             "Result was: {:?}", res);
     }
 
+    #[test]
+    fn test_parse_chunks() {
+        let t = HashType::Blake2b_256;
+        let res = parse_chunks(
+            b"700:8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc \
+              300:c384d6b21c50e0aa9bf80124256d56ba36c6a05ce0cc09bf858fa09e84aa19d4",
+            t, 1000);
+        let (hashes, tail) = res.unwrap();
+        assert!(tail.is_empty());
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes.chunk_sizes(), Some(&[700, 300][..]));
+        assert_eq!(hashes.blocks(1000, 512), vec![(0, 700), (700, 300)]);
+
+        let res = parse_chunks(
+            b"700:8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc",
+            t, 1000);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidHash(ref msg))
+                if msg == "Chunks have total size of 700 but file size is 1000"),
+            "Result was: {:?}", res);
+
+        let res = parse_chunks(
+            b"8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc",
+            t, 1000);
+        assert!(matches!(res,
+                Err(ParseRowError::InvalidHash(ref msg))
+                if msg.starts_with("Expected chunk size before hash")),
+            "Result was: {:?}", res);
+
+        let (hashes, _) = parse_chunks(b"", t, 0).unwrap();
+        assert_eq!(hashes.len(), 0);
+        assert!(hashes.blocks(0, 512).is_empty());
+    }
+
+    #[test]
+    fn test_hashes_blocks() {
+        let hashes = Hashes::new(vec![0; 96], HashType::Sha512_256);
+        assert_eq!(hashes.chunk_sizes(), None);
+        assert_eq!(hashes.blocks(2100, 1024),
+            vec![(0, 1024), (1024, 1024), (2048, 52)]);
+    }
+
     #[test]
     fn test_hashes_eq() {
         assert_eq!(
@@ -1,9 +1,10 @@
 use std::io;
+use std::ffi::OsStr;
 use std::sync::Arc;
 use std::path::Path;
 use std::time::{Instant, Duration};
 
-use super::writer::Writer;
+use super::writer::{Writer, HashedFile};
 use openat::{Dir, Entry};
 use {Error};
 
@@ -48,9 +49,11 @@ impl<W: Writer, S: io::Write> Progress<W, S> {
 
 
 impl<W: Writer, S: io::Write> Writer for Progress<W, S> {
-    fn start_dir(&mut self, path: &Path) -> Result<(), Error> {
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>
+    {
         self.dirs += 1;
-        self.dest.start_dir(path)?;
+        self.dest.start_dir(path, dir)?;
         self.check_print();
         Ok(())
     }
@@ -60,6 +63,14 @@ impl<W: Writer, S: io::Write> Writer for Progress<W, S> {
         self.check_print();
         Ok(())
     }
+    fn add_hashed_file(&mut self, name: &OsStr, file: HashedFile)
+        -> Result<(), Error>
+    {
+        self.files += 1;
+        self.dest.add_hashed_file(name, file)?;
+        self.check_print();
+        Ok(())
+    }
     fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
         -> Result<(), Error>
     {
@@ -0,0 +1,441 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf, Component};
+
+use quick_error::ResultExt;
+
+use {HashType, Chunking};
+use super::cdc::Chunker;
+use hash::{self, Hash};
+use super::merge::{MergedSignatures, MergedEntriesIterator};
+use super::parser::{Entry, EntryKind, Hashes, Parser, ParseError};
+use super::writer::EXE_MASK;
+
+
+quick_error! {
+    /// The error type that can happen when synchronizing a directory
+    #[derive(Debug)]
+    pub enum SyncError {
+        /// Error reading or writing a file
+        Io(path: PathBuf, err: io::Error) {
+            cause(err)
+            description("io error")
+            display("{:?}: {}", path, err)
+            context(path: &'a Path, err: io::Error)
+                -> (path.to_path_buf(), err)
+        }
+        /// Error parsing target or source signature file
+        Parse(err: ParseError) {
+            cause(err)
+            description("parse error")
+            display("parse error: {}", err)
+            from()
+        }
+        /// Source signature files have different hash type, block size or
+        /// chunking than the target one
+        SourcesMismatch {
+            description("source signature files are not compatible \
+                         with the target one")
+        }
+        /// Path in the target signature file is not a normalized absolute
+        /// path or its parent directory is not in the signature file
+        InvalidPath(path: PathBuf) {
+            description("invalid path in signature file")
+            display("invalid path in signature file: {:?}", path)
+        }
+        /// None of the destination file and the source directories
+        /// contain the block
+        MissingBlock(path: PathBuf, block: usize) {
+            description("block is not found in any source")
+            display("block {} of {:?} is not found in any source",
+                block, path)
+        }
+        /// Written file doesn't match the signature file, probably some
+        /// source file was changed during synchronization
+        HashMismatch(path: PathBuf) {
+            description("written file doesn't match signature")
+            display("written file {:?} doesn't match signature", path)
+        }
+    }
+}
+
+struct Syncer<'a, 'b, H, K: 'a, R: 'a + BufRead> {
+    hash: H,
+    block_size: u64,
+    chunking: Chunking,
+    dest: &'b Path,
+    sources: MergedEntriesIterator<'a, K, R>,
+    /// Directories being synchronized with the names they should contain
+    dirs: Vec<(PathBuf, HashSet<OsString>)>,
+    buf: Vec<u8>,
+}
+
+/// Brings the `dest` directory to the state described by the `target`
+/// signature file
+///
+/// Blocks of the files which are already in `dest` are reused when they
+/// match. Other blocks are copied from the files with the same path in the
+/// source directories, keys of the `sources` are paths to those directories
+/// and values are their signature files. Every file is written to a
+/// temporary file and verified before replacing the destination one.
+/// Entries that are not in the target signature file are removed.
+///
+/// Only the executable bit of the permissions is applied, newly written
+/// files are created with either `0o644` or `0o755` mode.
+pub fn sync<R, K, S, P>(target: &mut Parser<R>,
+    sources: &mut MergedSignatures<K, S>, dest: P)
+    -> Result<(), SyncError>
+    where R: BufRead, K: AsRef<Path>, S: BufRead, P: AsRef<Path>
+{
+    let header = target.get_header();
+    if sources.hash_type().unwrap_or(header.get_hash_type())
+        != header.get_hash_type() ||
+       sources.block_size().unwrap_or(header.get_block_size())
+        != header.get_block_size() ||
+       sources.chunking().unwrap_or(header.get_chunking())
+        != header.get_chunking()
+    {
+        return Err(SyncError::SourcesMismatch);
+    }
+    match header.get_hash_type() {
+        HashType::Sha512_256 => {
+            sync_with(target, sources, dest.as_ref(), hash::Sha512_256)
+        }
+        HashType::Blake2b_256 => {
+            sync_with(target, sources, dest.as_ref(), hash::Blake2b_256)
+        }
+        HashType::Blake3_256 => {
+            sync_with(target, sources, dest.as_ref(), hash::Blake3_256)
+        }
+        HashType::Sha256 => {
+            sync_with(target, sources, dest.as_ref(), hash::Sha256)
+        }
+        HashType::Custom(alg) => {
+            sync_with(target, sources, dest.as_ref(), hash::Custom(alg))
+        }
+    }
+}
+
+fn sync_with<R, K, S, H>(target: &mut Parser<R>,
+    sources: &mut MergedSignatures<K, S>, dest: &Path, hash: H)
+    -> Result<(), SyncError>
+    where R: BufRead, K: AsRef<Path>, S: BufRead, H: Hash
+{
+    let block_size = target.get_header().get_block_size();
+    let mut syncer = Syncer {
+        hash: hash,
+        block_size: block_size,
+        chunking: target.get_header().get_chunking(),
+        dest: dest,
+        sources: sources.iter(),
+        dirs: Vec::new(),
+        buf: Vec::with_capacity(block_size as usize),
+    };
+    for entry in target.iter() {
+        syncer.entry(entry?)?;
+    }
+    while let Some((path, names)) = syncer.dirs.pop() {
+        syncer.remove_extra(&path, &names)?;
+    }
+    Ok(())
+}
+
+/// Checks that path is absolute and has no `..` or `.` components
+fn is_normalized(path: &Path) -> bool {
+    let mut components = path.components();
+    components.next() == Some(Component::RootDir) &&
+        components.all(|c| match c {
+            Component::Normal(_) => true,
+            _ => false,
+        })
+}
+
+fn remove_any(path: &Path) -> Result<(), SyncError> {
+    match fs::symlink_metadata(path) {
+        Ok(ref meta) if meta.is_dir() => {
+            Ok(fs::remove_dir_all(path).context(path)?)
+        }
+        Ok(_) => Ok(fs::remove_file(path).context(path)?),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(SyncError::Io(path.to_path_buf(), e)),
+    }
+}
+
+impl<'a, 'b, H, K, R> Syncer<'a, 'b, H, K, R>
+    where H: Hash, K: AsRef<Path>, R: BufRead
+{
+    fn entry(&mut self, entry: Entry) -> Result<(), SyncError> {
+        let path = entry.path().to_path_buf();
+        if !is_normalized(&path) {
+            return Err(SyncError::InvalidPath(path));
+        }
+        let parent = match entry {
+            Entry::Dir(..) => path.parent().map(|p| p.to_path_buf()),
+            _ => Some(path.parent().expect("file has a parent").to_path_buf()),
+        };
+        if let Some(parent) = parent {
+            // directories are visited depth first, so all the directories
+            // that are not parents of the entry are already synchronized
+            while self.dirs.last().map(|&(ref p, _)| p != &parent)
+                .unwrap_or(false)
+            {
+                let (dir, names) = self.dirs.pop().unwrap();
+                self.remove_extra(&dir, &names)?;
+            }
+            match self.dirs.last_mut() {
+                Some(&mut (_, ref mut names)) => {
+                    names.insert(path.file_name().unwrap().to_os_string());
+                }
+                None => return Err(SyncError::InvalidPath(path)),
+            }
+        } else if !self.dirs.is_empty() {
+            return Err(SyncError::InvalidPath(path));
+        }
+        let dest = self.dest.join(path.strip_prefix("/").unwrap());
+        match entry {
+            Entry::Dir(path, _) => {
+                match fs::symlink_metadata(&dest) {
+                    Ok(ref meta) if meta.is_dir() => {}
+                    Ok(_) => {
+                        remove_any(&dest)?;
+                        fs::create_dir(&dest).context(dest.as_path())?;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        fs::create_dir(&dest).context(dest.as_path())?;
+                    }
+                    Err(e) => return Err(SyncError::Io(dest, e)),
+                }
+                self.dirs.push((path, HashSet::new()));
+            }
+            Entry::File { path, exe, size, hashes, .. } => {
+                self.sync_file(&path, &dest, exe, size, &hashes)?;
+            }
+            Entry::Link(_, target, _) => {
+                match fs::read_link(&dest) {
+                    Ok(ref cur) if cur == &target => {}
+                    _ => {
+                        remove_any(&dest)?;
+                        symlink(&target, &dest).context(dest.as_path())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_extra(&self, path: &Path, names: &HashSet<OsString>)
+        -> Result<(), SyncError>
+    {
+        let dir = self.dest.join(path.strip_prefix("/").unwrap());
+        for item in fs::read_dir(&dir).context(dir.as_path())? {
+            let item = item.context(dir.as_path())?;
+            if !names.contains(&item.file_name()) {
+                remove_any(&item.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a block into the buffer and checks its hash
+    fn read_block<F: Read + Seek>(&mut self, file: &mut F, offset: u64,
+        len: u64, expected: &[u8])
+        -> io::Result<bool>
+    {
+        file.seek(SeekFrom::Start(offset))?;
+        self.buf.clear();
+        file.take(len).read_to_end(&mut self.buf)?;
+        if self.buf.len() as u64 != len {
+            return Ok(false);
+        }
+        let actual = self.hash.hash_file(&self.buf[..], len)?;
+        Ok(actual.as_ref() == expected)
+    }
+
+    /// Returns offsets of the blocks in the destination file which can be
+    /// reused
+    ///
+    /// Fixed size blocks are only looked up at the same offset. With
+    /// content-defined chunking the file is split into chunks again, so
+    /// chunks are found even when data was inserted or removed before them.
+    fn check_existing(&mut self, dest: &Path, blocks: &[(u64, u64)],
+        hashes: &Hashes)
+        -> Result<(Option<File>, Vec<Option<u64>>), SyncError>
+    {
+        match fs::symlink_metadata(dest) {
+            Ok(ref meta) if meta.is_file() => {}
+            _ => return Ok((None, vec![None; hashes.len()])),
+        }
+        let mut file = File::open(dest).context(dest)?;
+        let mut matched = Vec::with_capacity(hashes.len());
+        match self.chunking {
+            Chunking::Fixed => {
+                for (expected, &(offset, len)) in hashes.iter().zip(blocks) {
+                    let ok = self.read_block(&mut file, offset, len, expected)
+                        .context(dest)?;
+                    matched.push(if ok { Some(offset) } else { None });
+                }
+            }
+            Chunking::FastCdc => {
+                let mut chunks = HashMap::new();
+                let mut offset = 0;
+                let hash = &self.hash;
+                let chunker = Chunker::new(self.block_size).context(dest)?;
+                chunker.split(&mut file, |chunk| {
+                    let len = chunk.len() as u64;
+                    let h = hash.hash_file(chunk, len)?;
+                    chunks.entry(h.as_ref().to_vec()).or_insert((offset, len));
+                    offset += len;
+                    Ok(())
+                }).context(dest)?;
+                for (expected, &(_, len)) in hashes.iter().zip(blocks) {
+                    matched.push(match chunks.get(expected) {
+                        Some(&(offset, chunk_len)) if chunk_len == len => {
+                            Some(offset)
+                        }
+                        _ => None,
+                    });
+                }
+            }
+        }
+        Ok((Some(file), matched))
+    }
+
+    fn sync_file(&mut self, path: &Path, dest: &Path,
+        exe: bool, size: u64, hashes: &Hashes)
+        -> Result<(), SyncError>
+    {
+        let block_size = self.block_size;
+        let blocks = hashes.blocks(size, block_size);
+        let sources = self.sources.advance(&EntryKind::File(path));
+        let (existing, matched) = self.check_existing(dest, &blocks, hashes)?;
+        if let Some(ref file) = existing {
+            let meta = file.metadata().context(dest)?;
+            let unchanged = matched.iter().zip(&blocks)
+                .all(|(m, &(offset, _))| *m == Some(offset));
+            if meta.len() == size && unchanged {
+                let mode = meta.permissions().mode();
+                if (mode & EXE_MASK > 0) != exe {
+                    let mode = if exe { mode | 0o111 } else { mode & !0o111 };
+                    fs::set_permissions(dest, PermissionsExt::from_mode(mode))
+                        .context(dest)?;
+                }
+                return Ok(());
+            }
+        }
+        let mut sources = sources.into_iter()
+            .filter_map(|(base, entry)| match entry {
+                Ok(Entry::File { size, hashes, .. }) => {
+                    let src_blocks = hashes.iter().map(|h| h.to_vec())
+                        .zip(hashes.blocks(size, block_size))
+                        .collect::<HashMap<_, _>>();
+                    Some(Ok((base.as_ref().join(path.strip_prefix("/")
+                                                .unwrap()),
+                             src_blocks, None)))
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tmp = tmp_path(dest);
+        let mut out = OpenOptions::new().read(true).write(true)
+            .create(true).truncate(true)
+            .open(&tmp).context(tmp.as_path())?;
+        let result = self.write_blocks(path, &mut out, existing, &matched,
+            &mut sources, &blocks, hashes)
+            .and_then(|()| {
+                self.verify(path, &tmp, &mut out, size, &blocks, hashes)
+            });
+        if let Err(e) = result {
+            fs::remove_file(&tmp).ok();
+            return Err(e);
+        }
+        let mode = if exe { 0o755 } else { 0o644 };
+        fs::set_permissions(&tmp, PermissionsExt::from_mode(mode))
+            .context(tmp.as_path())?;
+        match fs::symlink_metadata(dest) {
+            Ok(ref meta) if meta.is_dir() => remove_any(dest)?,
+            _ => {}
+        }
+        fs::rename(&tmp, dest).context(dest)?;
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, path: &Path, out: &mut File,
+        mut existing: Option<File>, matched: &[Option<u64>],
+        sources: &mut [(PathBuf, HashMap<Vec<u8>, (u64, u64)>, Option<File>)],
+        blocks: &[(u64, u64)], hashes: &Hashes)
+        -> Result<(), SyncError>
+    {
+        'blocks: for (idx, (expected, &(_, len)))
+            in hashes.iter().zip(blocks).enumerate()
+        {
+            if let Some(offset) = matched[idx] {
+                let file = existing.as_mut().expect("existing file");
+                let ok = self.read_block(file, offset, len, expected)
+                    .context(path)?;
+                if ok {
+                    out.write_all(&self.buf).context(path)?;
+                    continue 'blocks;
+                }
+            }
+            for &mut (ref src_path, ref src_blocks, ref mut file)
+                in sources.iter_mut()
+            {
+                let offset = match src_blocks.get(expected) {
+                    Some(&(offset, src_len)) if src_len == len => offset,
+                    _ => continue,
+                };
+                if file.is_none() {
+                    match File::open(src_path) {
+                        Ok(f) => *file = Some(f),
+                        Err(e) => {
+                            warn!("Can't open source file {:?}: {}",
+                                src_path, e);
+                            continue;
+                        }
+                    }
+                }
+                let ok = self.read_block(file.as_mut().unwrap(),
+                                         offset, len, expected)
+                    .context(src_path.as_path())?;
+                if ok {
+                    out.write_all(&self.buf).context(path)?;
+                    continue 'blocks;
+                }
+                warn!("Source file {:?} doesn't match its signature",
+                    src_path);
+            }
+            return Err(SyncError::MissingBlock(path.to_path_buf(), idx));
+        }
+        Ok(())
+    }
+
+    fn verify(&mut self, path: &Path, tmp: &Path, out: &mut File,
+        size: u64, blocks: &[(u64, u64)], hashes: &Hashes)
+        -> Result<(), SyncError>
+    {
+        out.flush().context(tmp)?;
+        if out.metadata().context(tmp)?.len() != size {
+            return Err(SyncError::HashMismatch(path.to_path_buf()));
+        }
+        for (expected, &(offset, len)) in hashes.iter().zip(blocks) {
+            if !self.read_block(out, offset, len, expected)
+                .context(tmp)?
+            {
+                return Err(SyncError::HashMismatch(path.to_path_buf()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tmp_path(dest: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(dest.file_name().unwrap_or(OsStr::new("file")));
+    name.push(".dirsig-tmp");
+    dest.with_file_name(name)
+}
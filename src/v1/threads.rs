@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use openat::{Dir, Entry};
+
+use {Error, Chunking};
+use hash::Hash;
+use super::writer::{Writer, HashedFile, hash_file};
+
+
+type Job = (Arc<Dir>, Entry, SyncSender<Result<HashedFile, Error>>);
+
+enum Item {
+    Dir(PathBuf, Option<Arc<Dir>>),
+    File(OsString, Receiver<Result<HashedFile, Error>>),
+    Symlink(Arc<Dir>, Entry),
+}
+
+/// Writer that hashes files in a pool of threads
+///
+/// Entries are passed to the underlying writer in the same order they were
+/// added, so output is exactly the same as when hashing in a single thread.
+/// No more than `queue_size` entries are kept in memory.
+pub struct ThreadedWriter<W> {
+    dest: W,
+    queue: VecDeque<Item>,
+    queue_size: usize,
+    jobs: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<W: Writer> ThreadedWriter<W> {
+    pub fn new<H: Hash>(dest: W, hash: H, block_size: u64,
+        chunking: Chunking, file_hash: bool, xattrs: bool,
+        threads: usize, queue_size: usize)
+        -> ThreadedWriter<W>
+    {
+        let queue_size = if queue_size > 0 { queue_size } else { 1 };
+        let (tx, rx) = sync_channel::<Job>(queue_size);
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = (0..threads).map(|_| {
+            let rx = rx.clone();
+            let hash = hash.clone();
+            thread::spawn(move || {
+                loop {
+                    let job = rx.lock().expect("job queue lock").recv();
+                    match job {
+                        Ok((dir, entry, result)) => {
+                            let file = hash_file(&hash, block_size,
+                                chunking, file_hash, xattrs, &dir, &entry);
+                            // receiver may be gone if writing failed
+                            result.send(file).ok();
+                        }
+                        Err(_) => break,
+                    }
+                }
+            })
+        }).collect();
+        ThreadedWriter {
+            dest: dest,
+            queue: VecDeque::with_capacity(queue_size+1),
+            queue_size: queue_size,
+            jobs: Some(tx),
+            workers: workers,
+        }
+    }
+    fn write_item(&mut self) -> Result<(), Error> {
+        match self.queue.pop_front() {
+            Some(Item::Dir(path, dir)) => {
+                self.dest.start_dir(&path, dir.as_ref())
+            }
+            Some(Item::File(name, result)) => {
+                let file = result.recv()
+                    .expect("hashing thread finished unexpectedly")?;
+                self.dest.add_hashed_file(&name, file)
+            }
+            Some(Item::Symlink(dir, entry)) => {
+                self.dest.add_symlink(&dir, entry)
+            }
+            None => Ok(()),
+        }
+    }
+    fn push(&mut self, item: Item) -> Result<(), Error> {
+        self.queue.push_back(item);
+        while self.queue.len() > self.queue_size {
+            self.write_item()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Writer> Writer for ThreadedWriter<W> {
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>
+    {
+        self.push(Item::Dir(path.to_path_buf(), dir.cloned()))
+    }
+    fn add_file(&mut self, dir: &Arc<Dir>, entry: Entry) -> Result<(), Error> {
+        let (tx, rx) = sync_channel(1);
+        let name = entry.file_name().to_os_string();
+        self.jobs.as_ref().expect("writer is not finished")
+            .send((dir.clone(), entry, tx))
+            .expect("hashing threads are alive");
+        self.push(Item::File(name, rx))
+    }
+    fn add_hashed_file(&mut self, name: &OsStr, file: HashedFile)
+        -> Result<(), Error>
+    {
+        let (tx, rx) = sync_channel(1);
+        tx.send(Ok(file)).expect("channel is open");
+        self.push(Item::File(name.to_os_string(), rx))
+    }
+    fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
+        -> Result<(), Error>
+    {
+        self.push(Item::Symlink(dir.clone(), entry))
+    }
+    fn done(&mut self) -> Result<(), Error> {
+        while self.queue.len() > 0 {
+            self.write_item()?;
+        }
+        self.dest.done()
+    }
+}
+
+impl<W> Drop for ThreadedWriter<W> {
+    fn drop(&mut self) {
+        // closing the channel stops the workers
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::fmt;
+use std::ffi::OsStr;
+use std::io::BufRead;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use openat::{Dir, Entry as DirEntry};
+
+use {ScannerConfig, HashType, Xattrs};
+use error::Error::{self, ReadFile as EFile};
+use hash::{self, Hash};
+use super::parser::{Entry, EntryKind, EntryIterator, Parser, ParseError};
+use scan;
+use xattr;
+use super::writer::{Writer, HashedFile, HashReader, EXE_MASK};
+
+
+/// Type of the entry in the index or in the directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntryType {
+    /// Directory
+    Dir,
+    /// Regular file
+    File,
+    /// Symbolic link
+    Symlink,
+}
+
+/// A difference between the index and the directory found by `verify`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Entry is in the index but not in the directory
+    Missing {
+        /// Path of the entry
+        path: PathBuf,
+        /// Type of the entry in the index
+        typ: EntryType,
+    },
+    /// Entry is in the directory but not in the index
+    Extra {
+        /// Path of the entry
+        path: PathBuf,
+        /// Type of the entry in the directory
+        typ: EntryType,
+    },
+    /// Entry has different type in the directory
+    TypeChanged {
+        /// Path of the entry
+        path: PathBuf,
+        /// Type of the entry in the index
+        expected: EntryType,
+        /// Type of the entry in the directory
+        found: EntryType,
+    },
+    /// Executable bit of the file differs
+    ExeChanged {
+        /// Path of the file
+        path: PathBuf,
+        /// Whether file is executable according to the index
+        expected: bool,
+    },
+    /// Size of the file differs, hashes are not checked in this case
+    SizeChanged {
+        /// Path of the file
+        path: PathBuf,
+        /// Size of the file in the index
+        expected: u64,
+        /// Size of the file in the directory
+        found: u64,
+    },
+    /// Some blocks of the file have different hashes
+    HashMismatch {
+        /// Path of the file
+        path: PathBuf,
+        /// Numbers of the mismatched blocks (starting from zero)
+        blocks: Vec<usize>,
+    },
+    /// All blocks match but the hash of the whole file doesn't, checked
+    /// only if the index has `file_hash=1`
+    FileHashMismatch {
+        /// Path of the file
+        path: PathBuf,
+    },
+    /// Symlink points to a different destination
+    LinkChanged {
+        /// Path of the symlink
+        path: PathBuf,
+        /// Destination in the index
+        expected: PathBuf,
+        /// Destination in the directory
+        found: PathBuf,
+    },
+    /// Extended attributes differ, checked only if the index has them
+    XattrsChanged {
+        /// Path of the entry
+        path: PathBuf,
+        /// Attributes in the index
+        expected: Xattrs,
+        /// Attributes in the directory
+        found: Xattrs,
+    },
+}
+
+/// Result of the directory verification
+#[derive(Debug)]
+pub struct Report {
+    problems: Vec<Problem>,
+}
+
+struct Verifier<'a, R: 'a + BufRead, H> {
+    index: EntryIterator<'a, R>,
+    /// Entry read from the index but not consumed yet
+    peeked: Option<Result<Entry, ParseError>>,
+    hash: H,
+    block_size: u64,
+    current_dir: PathBuf,
+    problems: Vec<Problem>,
+}
+
+/// Check that the directory matches the index
+///
+/// Both the index and the directory are read sequentially, so memory usage
+/// depends only on the number of problems found. Note that file contents
+/// are hashed only for the files having the expected size.
+pub fn verify<R: BufRead, P: AsRef<Path>>(index: &mut Parser<R>, dir: P)
+    -> Result<Report, Error>
+{
+    let header = index.get_header();
+    let mut config = ScannerConfig::new();
+    config.hash(header.get_hash_type());
+    // block size is only validated for scanning, any size from the
+    // header works for verification
+    config.block_size = header.get_block_size();
+    config.chunking(header.get_chunking());
+    config.add_dir(dir, "/");
+    match header.get_hash_type() {
+        HashType::Sha512_256 => verify_with(&config, index, hash::Sha512_256),
+        HashType::Blake2b_256 => verify_with(&config, index, hash::Blake2b_256),
+        HashType::Blake3_256 => verify_with(&config, index, hash::Blake3_256),
+        HashType::Sha256 => verify_with(&config, index, hash::Sha256),
+        HashType::Custom(alg) => {
+            verify_with(&config, index, hash::Custom(alg))
+        }
+    }
+}
+
+fn verify_with<R: BufRead, H: Hash>(config: &ScannerConfig,
+    index: &mut Parser<R>, hash: H)
+    -> Result<Report, Error>
+{
+    let mut verifier = Verifier {
+        index: index.iter(),
+        peeked: None,
+        hash: hash,
+        block_size: config.block_size,
+        current_dir: PathBuf::from("/"),
+        problems: Vec::new(),
+    };
+    scan::scan(config, &mut verifier)?;
+    Ok(Report { problems: merge_type_changes(verifier.problems) })
+}
+
+/// Replaces a pair of missing and extra entries with the same path by
+/// a single `TypeChanged` problem
+///
+/// Such pairs appear when a file is replaced by a directory or vice versa,
+/// because files and directories are placed differently in the index.
+fn merge_type_changes(problems: Vec<Problem>) -> Vec<Problem> {
+    use self::Problem::*;
+    let mut result = Vec::with_capacity(problems.len());
+    let mut extra = problems.iter()
+        .filter_map(|p| match *p {
+            Extra { ref path, typ } => Some((path.clone(), typ)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    extra.sort();
+    let mut changed = Vec::new();
+    for problem in problems {
+        match problem {
+            Missing { path, typ } => {
+                match extra.binary_search_by(|&(ref p, _)| p.cmp(&path)) {
+                    Ok(idx) => {
+                        changed.push(path.clone());
+                        result.push(TypeChanged {
+                            path: path,
+                            expected: typ,
+                            found: extra[idx].1,
+                        });
+                    }
+                    Err(_) => result.push(Missing { path: path, typ: typ }),
+                }
+            }
+            problem => result.push(problem),
+        }
+    }
+    changed.sort();
+    result.retain(|p| match *p {
+        Extra { ref path, .. } => changed.binary_search(path).is_err(),
+        _ => true,
+    });
+    return result;
+}
+
+impl Report {
+    /// Returns `true` if the directory matches the index exactly
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+    /// Returns the list of problems in the order of the index
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+}
+
+impl<'a, R: BufRead, H: Hash> Verifier<'a, R, H> {
+    fn peek(&mut self) -> Option<&Result<Entry, ParseError>> {
+        if self.peeked.is_none() {
+            self.peeked = self.index.next();
+        }
+        self.peeked.as_ref()
+    }
+    fn next(&mut self) -> Option<Result<Entry, ParseError>> {
+        match self.peeked.take() {
+            Some(entry) => Some(entry),
+            None => self.index.next(),
+        }
+    }
+    /// Reports all index entries before `kind` as missing and returns
+    /// the entry equal to `kind` if there is one
+    fn find(&mut self, kind: EntryKind<&Path>) -> Result<Option<Entry>, Error>
+    {
+        use std::cmp::Ordering::*;
+        loop {
+            let order = match self.peek() {
+                Some(&Ok(ref entry)) => entry.kind().cmp(&kind),
+                Some(&Err(_)) => Equal,
+                None => return Ok(None),
+            };
+            match order {
+                Less => {
+                    let entry = self.next()
+                        .expect("peeked entry").map_err(Error::VerifyIndex)?;
+                    self.problems.push(Problem::Missing {
+                        typ: entry_type(&entry),
+                        path: entry.path().to_path_buf(),
+                    });
+                }
+                Equal => {
+                    return self.next().expect("peeked entry")
+                        .map(Some).map_err(Error::VerifyIndex);
+                }
+                Greater => return Ok(None),
+            }
+        }
+    }
+    fn extra(&mut self, path: PathBuf, typ: EntryType) {
+        self.problems.push(Problem::Extra { path: path, typ: typ });
+    }
+    fn type_changed(&mut self, path: PathBuf, expected: &Entry,
+        found: EntryType)
+    {
+        self.problems.push(Problem::TypeChanged {
+            path: path,
+            expected: entry_type(expected),
+            found: found,
+        });
+    }
+    fn check_xattrs(&mut self, path: &Path, expected: Xattrs, found: Xattrs) {
+        if expected == found {
+            return;
+        }
+        self.problems.push(Problem::XattrsChanged {
+            path: path.to_path_buf(),
+            expected: expected,
+            found: found,
+        });
+    }
+    fn check_file(&mut self, path: PathBuf, dir: &Dir, entry: &DirEntry,
+        expected: Entry)
+        -> Result<(), Error>
+    {
+        let (exe, size, hashes, file_hash, xattrs) = match expected {
+            Entry::File { exe, size, hashes, file_hash, xattrs, .. } => {
+                (exe, size, hashes, file_hash, xattrs)
+            }
+            _ => unreachable!("only files are checked"),
+        };
+        let mut f = dir.open_file(entry).map_err(EFile)?;
+        let meta = f.metadata().map_err(EFile)?;
+        if let Some(expected) = xattrs {
+            let found = xattr::read_fd(&f).map_err(EFile)?;
+            self.check_xattrs(&path, expected, found);
+        }
+        let actual_exe = meta.permissions().mode() & EXE_MASK > 0;
+        if actual_exe != exe {
+            self.problems.push(Problem::ExeChanged {
+                path: path.clone(),
+                expected: exe,
+            });
+        }
+        if meta.len() != size {
+            self.problems.push(Problem::SizeChanged {
+                path: path,
+                expected: size,
+                found: meta.len(),
+            });
+            return Ok(());
+        }
+        let mut file_hasher = file_hash.as_ref().map(|_| self.hash.hasher());
+        let mut blocks = Vec::new();
+        {
+            let mut reader = HashReader(&mut f, file_hasher.as_mut());
+            let lengths = hashes.blocks(size, self.block_size);
+            for (idx, (expected, &(_, len))) in
+                hashes.iter().zip(lengths.iter()).enumerate()
+            {
+                let actual = self.hash.hash_file(&mut reader, len)
+                    .map_err(EFile)?;
+                if actual.as_ref() != expected {
+                    blocks.push(idx);
+                }
+            }
+        }
+        if !blocks.is_empty() {
+            self.problems.push(Problem::HashMismatch {
+                path: path,
+                blocks: blocks,
+            });
+        } else if let (Some(expected), Some(hasher)) = (file_hash, file_hasher)
+        {
+            if hasher.result() != expected {
+                self.problems.push(Problem::FileHashMismatch { path: path });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: BufRead, H: Hash> Writer for Verifier<'a, R, H> {
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>
+    {
+        self.current_dir = path.to_path_buf();
+        match self.find(EntryKind::Dir(path))? {
+            Some(Entry::Dir(_, xattrs)) => {
+                if let Some(expected) = xattrs {
+                    let found = match dir {
+                        Some(dir) => xattr::read_dir(dir).map_err(EFile)?,
+                        None => Xattrs::default(),
+                    };
+                    self.check_xattrs(path, expected, found);
+                }
+            }
+            Some(_) => {}
+            None => self.extra(path.to_path_buf(), EntryType::Dir),
+        }
+        Ok(())
+    }
+    fn add_file(&mut self, dir: &Arc<Dir>, entry: DirEntry)
+        -> Result<(), Error>
+    {
+        let path = self.current_dir.join(entry.file_name());
+        match self.find(EntryKind::File(&path))? {
+            Some(expected @ Entry::File { .. }) => {
+                self.check_file(path, dir, &entry, expected)?;
+            }
+            Some(other) => self.type_changed(path, &other, EntryType::File),
+            None => self.extra(path, EntryType::File),
+        }
+        Ok(())
+    }
+    fn add_hashed_file(&mut self, _name: &OsStr, _file: HashedFile)
+        -> Result<(), Error>
+    {
+        unreachable!("verifier is never used with pre-hashed files");
+    }
+    fn add_symlink(&mut self, dir: &Arc<Dir>, entry: DirEntry)
+        -> Result<(), Error>
+    {
+        let path = self.current_dir.join(entry.file_name());
+        match self.find(EntryKind::File(&path))? {
+            Some(Entry::Link(_, expected, xattrs)) => {
+                if let Some(xattrs) = xattrs {
+                    let found = xattr::read_link(dir, entry.file_name())
+                        .map_err(EFile)?;
+                    self.check_xattrs(&path, xattrs, found);
+                }
+                let found = dir.read_link(&entry).map_err(EFile)?;
+                if found != expected {
+                    self.problems.push(Problem::LinkChanged {
+                        path: path,
+                        expected: expected,
+                        found: found,
+                    });
+                }
+            }
+            Some(other) => {
+                self.type_changed(path, &other, EntryType::Symlink);
+            }
+            None => self.extra(path, EntryType::Symlink),
+        }
+        Ok(())
+    }
+    fn done(&mut self) -> Result<(), Error> {
+        while let Some(entry) = self.next() {
+            let entry = entry.map_err(Error::VerifyIndex)?;
+            self.problems.push(Problem::Missing {
+                typ: entry_type(&entry),
+                path: entry.path().to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn entry_type(entry: &Entry) -> EntryType {
+    match *entry {
+        Entry::Dir(..) => EntryType::Dir,
+        Entry::File { .. } => EntryType::File,
+        Entry::Link(..) => EntryType::Symlink,
+    }
+}
+
+impl fmt::Display for EntryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            EntryType::Dir => "directory",
+            EntryType::File => "file",
+            EntryType::Symlink => "symlink",
+        })
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Problem::*;
+        match *self {
+            Missing { ref path, typ } => {
+                write!(f, "{:?}: missing {}", path, typ)
+            }
+            Extra { ref path, typ } => {
+                write!(f, "{:?}: extra {}", path, typ)
+            }
+            TypeChanged { ref path, expected, found } => {
+                write!(f, "{:?}: expected {} but found {}",
+                    path, expected, found)
+            }
+            ExeChanged { ref path, expected: true } => {
+                write!(f, "{:?}: file is not executable", path)
+            }
+            ExeChanged { ref path, expected: false } => {
+                write!(f, "{:?}: file is executable", path)
+            }
+            SizeChanged { ref path, expected, found } => {
+                write!(f, "{:?}: expected size {} but found {}",
+                    path, expected, found)
+            }
+            HashMismatch { ref path, ref blocks } => {
+                write!(f, "{:?}: blocks {:?} have different hashes",
+                    path, blocks)
+            }
+            FileHashMismatch { ref path } => {
+                write!(f, "{:?}: hash of the whole file differs", path)
+            }
+            LinkChanged { ref path, ref expected, ref found } => {
+                write!(f, "{:?}: symlink points to {:?} instead of {:?}",
+                    path, found, expected)
+            }
+            XattrsChanged { ref path, .. } => {
+                write!(f, "{:?}: extended attributes differ", path)
+            }
+        }
+    }
+}
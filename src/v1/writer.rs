@@ -1,111 +1,471 @@
+use std::env;
 use std::fmt;
-use std::io::{self, Write};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions, remove_file};
+use std::io::{self, Read, Write, Seek, SeekFrom, BufWriter};
+use std::process;
 use std::sync::Arc;
-use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
 
 
-use sha2::Digest;
 use openat::{Dir, Entry};
 
 use error::Error::{self, WriteError as EWrite, ReadFile as EFile};
-use super::hash::Hash;
+use error::Error::BadHeaderKey;
+use {Hasher, Chunking, Xattrs};
+use hash::Hash;
+use scan::Visitor;
+use xattr;
+use super::cdc::Chunker;
+use super::lookup::Lookup;
 
 
-struct Name<'a>(&'a Path);
+pub struct Name<'a>(pub &'a Path);
 
-const EXE_MASK: u32 = 0o100;
+pub struct Hex<'a>(pub &'a [u8]);
+
+/// Formats extended attributes as a single field
+pub struct Attrs<'a>(pub &'a Xattrs);
+
+pub const EXE_MASK: u32 = 0o100;
+const LOWER_CHARS: &'static [u8] = b"0123456789abcdef";
 
 pub const MAGIC: &'static str = "DIRSIGNATURE";
 pub const VERSION: &'static str = "v1";
 
+/// Header keys having special meaning, they can't be used as extra keys
+const RESERVED_KEYS: &'static [&'static str] = &[
+    "block_size", "chunking", "file_hash", "dir_hash", "xattrs"];
+
+
+/// Metadata and block hashes of a single file
+///
+/// It's produced by `hash_file` which may be run in a separate thread
+pub struct HashedFile {
+    pub exe: bool,
+    pub size: u64,
+    pub hashes: Vec<u8>,
+    /// Sizes of the content-defined chunks, empty for fixed blocks
+    pub chunks: Vec<u64>,
+    /// Hash of the whole file if enabled
+    pub file_hash: Option<Vec<u8>>,
+    /// Extended attributes if enabled
+    pub xattrs: Option<Xattrs>,
+}
 
 pub trait Writer {
-    fn start_dir(&mut self, path: &Path) -> Result<(), Error>;
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>;
     fn add_file(&mut self, dir: &Arc<Dir>, entry: Entry) -> Result<(), Error>;
+    fn add_hashed_file(&mut self, name: &OsStr, file: HashedFile)
+        -> Result<(), Error>;
     fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
         -> Result<(), Error>;
     fn done(&mut self) -> Result<(), Error>;
 }
 
-pub struct HashWriter<F, H> {
+impl<W: Writer> Visitor for W {
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>
+    {
+        Writer::start_dir(self, path, dir)
+    }
+    fn add_file(&mut self, dir: &Arc<Dir>, entry: Entry) -> Result<(), Error> {
+        Writer::add_file(self, dir, entry)
+    }
+    fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
+        -> Result<(), Error>
+    {
+        Writer::add_symlink(self, dir, entry)
+    }
+    fn done(&mut self) -> Result<(), Error> {
+        Writer::done(self)
+    }
+}
+
+fn valid_header_key(key: &str, value: &str) -> bool {
+    let valid_char = |c: u8| c > 0x20 && c < 0x7f;
+    !key.is_empty() && !RESERVED_KEYS.contains(&key) &&
+        key.bytes().all(|c| valid_char(c) && c != b'=') &&
+        value.bytes().all(valid_char)
+}
+
+pub struct HashWriter<F> {
     file: F,
-    digest: H,
+    digest: Box<dyn Hasher>,
+    /// Number of bytes written, used as offsets in the lookup file
+    written: u64,
 }
 
 pub struct SyncWriter<F, H: Hash> {
-    file: HashWriter<F, H::Digest>,
+    file: HashWriter<F>,
+    dirs: Option<DirHashes>,
     block_size: u64,
+    chunking: Chunking,
+    file_hash: bool,
+    xattrs: bool,
     hash: H,
+    /// Path of the lookup file and offsets of the directory lines
+    lookup: Option<(PathBuf, Vec<(PathBuf, u64)>)>,
+}
+
+/// Index body spooled to a temporary file until directory hashes are known
+///
+/// Directory hash is only known when the whole subtree of the directory
+/// is scanned. So directory line is written with a placeholder of the same
+/// length as the hash, which is overwritten when the directory is finished,
+/// and the body is copied to the index at the end. Only directories being
+/// scanned are kept in memory.
+struct DirHashes {
+    body: BufWriter<File>,
+    /// Number of bytes written to the body
+    written: u64,
+    /// Length of the hex encoded hash
+    hash_len: usize,
+    /// Directories being scanned, with offset of the hash placeholder,
+    /// the line without the hash and the hasher
+    pending: Vec<(u64, PathBuf, String, Box<dyn Hasher>)>,
 }
 
+/// Feeds everything read from the file to the whole-file hasher
+pub struct HashReader<'a, R>(pub R, pub Option<&'a mut Box<dyn Hasher>>);
+
 impl<F: io::Write, H: Hash> Writer for SyncWriter<F, H> {
-    fn start_dir(&mut self, path: &Path) -> Result<(), Error> {
-        writeln!(&mut self.file, "{}", Name(path)).map_err(EWrite)?;
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>
+    {
+        let mut line = format!("{}", Name(path));
+        if self.xattrs {
+            // intermediate directories of prefixes have no attributes
+            let xattrs = match dir {
+                Some(dir) => xattr::read_dir(dir).map_err(EFile)?,
+                None => Xattrs::default(),
+            };
+            line.push_str(&format!(" {}", Attrs(&xattrs)));
+        }
+        match self.dirs {
+            Some(ref mut dirs) => {
+                // offsets are moved by the header size when body is copied
+                if let Some((_, ref mut offsets)) = self.lookup {
+                    offsets.push((path.to_path_buf(), dirs.written));
+                }
+                dirs.start_dir(path, line, self.hash.hasher())
+                    .map_err(EWrite)?;
+            }
+            None => {
+                if let Some((_, ref mut offsets)) = self.lookup {
+                    offsets.push((path.to_path_buf(), self.file.written));
+                }
+                writeln!(&mut self.file, "{}", line).map_err(EWrite)?;
+            }
+        }
         Ok(())
     }
     fn add_file(&mut self, dir: &Arc<Dir>, entry: Entry)
         -> Result<(), Error>
     {
-        let mut f = dir.open_file(&entry).map_err(EFile)?;
-        let meta = f.metadata().map_err(EFile)?;
-        let mut n = meta.len();
-        write!(&mut self.file, "  {} {} {}",
-            Name(&Path::new(entry.file_name())),
-            if meta.permissions().mode() & EXE_MASK > 0 { "x" } else { "f" },
-            n,
+        let file = hash_file(&self.hash, self.block_size, self.chunking,
+                             self.file_hash, self.xattrs, dir, &entry)?;
+        self.add_hashed_file(entry.file_name(), file)
+    }
+    fn add_hashed_file(&mut self, name: &OsStr, file: HashedFile)
+        -> Result<(), Error>
+    {
+        let hash_size = self.hash.output_bytes();
+        let out = self.out();
+        write!(out, "  {} {}",
+            Name(&Path::new(name)),
+            if file.exe { "x" } else { "f" },
         ).map_err(EWrite)?;
-        while n > 0 {
-            let h = self.hash.hash_file(&mut f, self.block_size)
-                .map_err(EFile)?;
-            write!(&mut self.file, " {:x}", h).map_err(EWrite)?;
-            n = n.saturating_sub(self.block_size);
+        if let Some(ref xattrs) = file.xattrs {
+            write!(out, " {}", Attrs(xattrs)).map_err(EWrite)?;
+        }
+        write!(out, " {}", file.size).map_err(EWrite)?;
+        if let Some(ref h) = file.file_hash {
+            write!(out, " {}", Hex(h)).map_err(EWrite)?;
+        }
+        if file.chunks.is_empty() {
+            for h in file.hashes.chunks(hash_size) {
+                write!(out, " {}", Hex(h)).map_err(EWrite)?;
+            }
+        } else {
+            let hashes = file.hashes.chunks(hash_size);
+            for (len, h) in file.chunks.iter().zip(hashes) {
+                write!(out, " {}:{}", len, Hex(h)).map_err(EWrite)?;
+            }
         }
-        self.file.write_all(b"\n").map_err(EWrite)?;
+        out.write_all(b"\n").map_err(EWrite)?;
         Ok(())
     }
     fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
         -> Result<(), Error>
     {
         let dest = dir.read_link(&entry).map_err(EFile)?;
-        write!(&mut self.file, "  {} s {}\n",
-            Name(&Path::new(entry.file_name())),
-            Name(&dest),
-        ).map_err(EWrite)?;
+        let xattrs = if self.xattrs {
+            Some(xattr::read_link(dir, entry.file_name()).map_err(EFile)?)
+        } else {
+            None
+        };
+        let out = self.out();
+        write!(out, "  {} s", Name(&Path::new(entry.file_name())))
+            .map_err(EWrite)?;
+        if let Some(ref xattrs) = xattrs {
+            write!(out, " {}", Attrs(xattrs)).map_err(EWrite)?;
+        }
+        write!(out, " {}\n", Name(&dest)).map_err(EWrite)?;
         Ok(())
     }
     fn done(&mut self) -> Result<(), Error>
     {
-        write!(&mut self.file.file, "{:x}\n",
-            self.hash.total_hash(&self.file.digest)
-        ).map_err(EFile)
+        if let Some(mut dirs) = self.dirs.take() {
+            let start = self.file.written;
+            dirs.write_to(&mut self.file).map_err(EWrite)?;
+            if let Some((_, ref mut offsets)) = self.lookup {
+                for &mut (_, ref mut offset) in offsets.iter_mut() {
+                    *offset += start;
+                }
+            }
+        }
+        let hash = self.file.digest.result();
+        write!(&mut self.file.file, "{}\n", Hex(&hash)).map_err(EFile)?;
+        if let Some((path, offsets)) = self.lookup.take() {
+            let mut out = BufWriter::new(File::create(&path).map_err(EWrite)?);
+            Lookup::new(hash, offsets).write(&mut out)
+                .and_then(|()| out.flush())
+                .map_err(EWrite)?;
+        }
+        Ok(())
     }
 }
 
 impl<F: io::Write, H: Hash> SyncWriter<F, H> {
-    pub fn new(mut f: F, hash: H, block_size: u64)
+    pub fn new(f: F, hash: H, block_size: u64, chunking: Chunking,
+        file_hash: bool, dir_hash: bool, xattrs: bool,
+        extra: &BTreeMap<String, String>, lookup: Option<&PathBuf>)
         -> Result<SyncWriter<F, H>, Error>
     {
-        writeln!(&mut f,
-            "{}.{} {} block_size={}",
-            MAGIC,
-            VERSION,
-            hash.name(),
-            block_size,
-        ).map_err(EWrite)?;
+        for (key, value) in extra {
+            if !valid_header_key(key, value) {
+                return Err(BadHeaderKey(key.clone(), value.clone()));
+            }
+        }
+        // header is also accounted in the footer hash
+        let mut file = HashWriter {
+            file: f,
+            digest: hash.hasher(),
+            written: 0,
+        };
+        write_header(&mut file, hash.name(), block_size, chunking,
+            file_hash, dir_hash, xattrs, extra).map_err(EWrite)?;
+        let dirs = if dir_hash {
+            Some(DirHashes::new(hash.output_bytes()).map_err(EWrite)?)
+        } else {
+            None
+        };
         Ok(SyncWriter {
-            file: HashWriter { file: f, digest: hash.total_hasher() },
+            file: file,
+            dirs: dirs,
             block_size: block_size,
+            chunking: chunking,
+            file_hash: file_hash,
+            xattrs: xattrs,
             hash: hash,
+            lookup: lookup.map(|path| (path.clone(), Vec::new())),
+        })
+    }
+
+    /// Returns where the entry lines should be written
+    fn out(&mut self) -> &mut dyn io::Write {
+        match self.dirs {
+            Some(ref mut dirs) => dirs,
+            None => &mut self.file,
+        }
+    }
+}
+
+/// Writes the header line, keys are always written in the same order
+pub fn write_header<W: io::Write>(out: &mut W, hash_name: &str,
+    block_size: u64, chunking: Chunking, file_hash: bool, dir_hash: bool,
+    xattrs: bool, extra: &BTreeMap<String, String>)
+    -> io::Result<()>
+{
+    write!(out, "{}.{} {} block_size={}",
+        MAGIC, VERSION, hash_name, block_size)?;
+    if chunking == Chunking::FastCdc {
+        out.write_all(b" chunking=fastcdc")?;
+    }
+    if file_hash {
+        out.write_all(b" file_hash=1")?;
+    }
+    if dir_hash {
+        out.write_all(b" dir_hash=1")?;
+    }
+    if xattrs {
+        out.write_all(b" xattrs=1")?;
+    }
+    for (key, value) in extra {
+        write!(out, " {}={}", key, value)?;
+    }
+    out.write_all(b"\n")
+}
+
+impl DirHashes {
+    fn new(hash_bytes: usize) -> io::Result<DirHashes> {
+        Ok(DirHashes {
+            body: BufWriter::new(temp_file()?),
+            written: 0,
+            hash_len: hash_bytes*2,
+            pending: Vec::new(),
         })
     }
+
+    fn start_dir(&mut self, path: &Path, line: String, hasher: Box<dyn Hasher>)
+        -> io::Result<()>
+    {
+        self.finish(Some(path))?;
+        // directory line isn't accounted in the hash of the parent until
+        // the directory is finished
+        let placeholder = self.written + line.len() as u64 + 1;
+        write!(self.body, "{} {:2$}\n", line, "", self.hash_len)?;
+        self.written = placeholder + self.hash_len as u64 + 1;
+        self.pending.push((placeholder, path.to_path_buf(), line, hasher));
+        Ok(())
+    }
+
+    /// Writes hashes of the directories which are not parents of `next`
+    ///
+    /// Directory hash is a hash of the lines of its files and of the lines
+    /// of its subdirectories (which contain their hashes).
+    fn finish(&mut self, next: Option<&Path>) -> io::Result<()> {
+        loop {
+            match (self.pending.last(), next) {
+                (Some(&(_, ref dir, _, _)), Some(next))
+                    if next.starts_with(dir) => break,
+                (Some(_), _) => {}
+                (None, _) => break,
+            }
+            let (offset, _, line, hasher) = self.pending.pop().unwrap();
+            let hash = format!("{}", Hex(&hasher.result()));
+            self.body.seek(SeekFrom::Start(offset))?;
+            self.body.write_all(hash.as_bytes())?;
+            self.body.seek(SeekFrom::Start(self.written))?;
+            if let Some(&mut (.., ref mut parent)) = self.pending.last_mut() {
+                parent.input(format!("{} {}\n", line, hash).as_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the index body to `out`
+    fn write_to<F: io::Write>(&mut self, out: &mut HashWriter<F>)
+        -> io::Result<()>
+    {
+        self.finish(None)?;
+        self.body.seek(SeekFrom::Start(0))?;
+        io::copy(self.body.get_mut(), out)?;
+        Ok(())
+    }
 }
 
-impl<F: io::Write, H: Digest> io::Write for HashWriter<F, H> {
+impl io::Write for DirHashes {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.body.write(buf)?;
+        self.written += written as u64;
+        if let Some(&mut (_, _, _, ref mut hasher)) = self.pending.last_mut() {
+            hasher.input(&buf[..written]);
+        }
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates a file in the temporary directory which is removed right away
+///
+/// So nothing is left behind if the scan fails.
+fn temp_file() -> io::Result<File> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!("dir-signature-{}-{}.tmp",
+        process::id(), COUNTER.fetch_add(1, Ordering::SeqCst)));
+    let file = OpenOptions::new().read(true).write(true).create_new(true)
+        .open(&path)?;
+    remove_file(&path)?;
+    Ok(file)
+}
+
+/// Reads a file and calculates hashes of its blocks
+///
+/// When `file_hash` is set the hash of the whole file is calculated in the
+/// same pass.
+pub fn hash_file<H: Hash>(hash: &H, block_size: u64, chunking: Chunking,
+    file_hash: bool, xattrs: bool, dir: &Dir, entry: &Entry)
+    -> Result<HashedFile, Error>
+{
+    let mut f = dir.open_file(entry).map_err(EFile)?;
+    let meta = f.metadata().map_err(EFile)?;
+    let xattrs = if xattrs {
+        Some(xattr::read_fd(&f).map_err(EFile)?)
+    } else {
+        None
+    };
+    let size = meta.len();
+    let mut hashes = Vec::with_capacity(
+        ((size + block_size - 1) / block_size) as usize * hash.output_bytes());
+    let mut chunks = Vec::new();
+    let mut file_hasher = if file_hash { Some(hash.hasher()) } else { None };
+    {
+        let mut reader = HashReader(&mut f, file_hasher.as_mut());
+        match chunking {
+            Chunking::Fixed => {
+                let mut n = size;
+                while n > 0 {
+                    let h = hash.hash_file(&mut reader, block_size)
+                        .map_err(EFile)?;
+                    hashes.extend_from_slice(h.as_ref());
+                    n = n.saturating_sub(block_size);
+                }
+            }
+            Chunking::FastCdc => {
+                let chunker = Chunker::new(block_size).map_err(EFile)?;
+                chunker.split(reader.take(size), |chunk| {
+                    let h = hash.hash_file(chunk, chunk.len() as u64)?;
+                    hashes.extend_from_slice(h.as_ref());
+                    chunks.push(chunk.len() as u64);
+                    Ok(())
+                }).map_err(EFile)?;
+            }
+        }
+    }
+    Ok(HashedFile {
+        exe: meta.permissions().mode() & EXE_MASK > 0,
+        size: size,
+        hashes: hashes,
+        chunks: chunks,
+        file_hash: file_hasher.map(|h| h.result()),
+        xattrs: xattrs,
+    })
+}
+
+impl<'a, R: io::Read> io::Read for HashReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        if let Some(ref mut hasher) = self.1 {
+            hasher.input(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<F: io::Write> io::Write for HashWriter<F> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let n = self.file.write(buf)?;
         self.digest.input(&buf[..n]);
+        self.written += n as u64;
         Ok(n)
     }
     fn flush(&mut self) -> io::Result<()> {
@@ -128,6 +488,51 @@ impl<'a> fmt::Display for Name<'a> {
     }
 }
 
+impl<'a> fmt::Display for Attrs<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use std::fmt::Write;
+
+        if self.0.is_empty() {
+            return f.write_char('-');
+        }
+        for (idx, &(ref name, ref value)) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_char(',')?;
+            }
+            escape_attr(f, name.as_bytes())?;
+            f.write_char('=')?;
+            escape_attr(f, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes like `Name` does and also escapes separators of attributes
+fn escape_attr(f: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
+    use std::fmt::Write;
+
+    for &b in data {
+        if b <= 0x20 || b >= 0x7F || b == b'\\' || b == b'=' || b == b',' {
+            write!(f, "\\x{:02x}", b)?;
+        } else {
+            f.write_char(b as char)?;
+        }
+    }
+    Ok(())
+}
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use std::fmt::Write;
+
+        for &b in self.0 {
+            f.write_char(LOWER_CHARS[(b >> 4) as usize] as char)?;
+            f.write_char(LOWER_CHARS[(b & 0xF) as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
 #[test]
 fn test_escapes() {
     assert_eq!(&format!("{}", Name(Path::new("a\x05b"))),
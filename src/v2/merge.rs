@@ -0,0 +1,141 @@
+//! A module for parsing multiple v2 directory signature files
+//!
+//!
+//! Entry points:
+//!
+//! * [`MergedSignatures::new`](struct.MergedSignatures.html#method.new)
+//!   for iterating over entries from multiple signature files
+
+use std::io::BufRead;
+use std::iter::Peekable;
+
+use HashType;
+use super::{Entry, Parser, ParseError, EntryIterator};
+
+quick_error! {
+    /// The error type that can happen when merging signature files
+    #[derive(Debug)]
+    pub enum MergeError {
+        /// Signature files have different hash types
+        HashTypesMismatch(hash_types: Vec<HashType>) {
+            description("Hash types mismatch")
+            display("Hash types mismatch: {:?}", hash_types)
+        }
+        /// Signature files have different block sizes
+        BlockSizesMismatch(block_sizes: Vec<u64>) {
+            description("Block sizes mismatch")
+            display("Block sizes mismatch: {:?}", block_sizes)
+        }
+    }
+}
+
+/// Helper struct to simplify simultaneous iteration over several
+/// directory signature files
+pub struct MergedSignatures<K, R: BufRead> {
+    parsers: Vec<(K, Parser<R>)>,
+}
+
+/// Iterator over the entries from several signature files
+///
+/// Yields entries having the same path and kind from all signature files
+/// containing them, in the order of the signature files.
+pub struct MergedEntriesIterator<'a, K: 'a, R: 'a + BufRead> {
+    iterators: Vec<(&'a K, Peekable<EntryIterator<'a, R>>)>,
+}
+
+impl<K, R: BufRead> MergedSignatures<K, R> {
+    /// Creates merged signatures struct over `parsers`
+    ///
+    /// All hash types and block sizes should be the same
+    pub fn new<I>(parsers: I) -> Result<MergedSignatures<K, R>, MergeError>
+        where I: IntoIterator<Item=(K, Parser<R>)>
+    {
+        let parsers = parsers.into_iter().collect::<Vec<_>>();
+        let hash_types = parsers.iter()
+            .map(|p| p.1.get_header().get_hash_type())
+            .collect::<Vec<_>>();
+        if hash_types.iter().any(|h| *h != hash_types[0]) {
+            return Err(MergeError::HashTypesMismatch(hash_types));
+        }
+        let block_sizes = parsers.iter()
+            .map(|p| p.1.get_header().get_block_size())
+            .collect::<Vec<_>>();
+        if block_sizes.iter().any(|b| *b != block_sizes[0]) {
+            return Err(MergeError::BlockSizesMismatch(block_sizes));
+        }
+        Ok(MergedSignatures {
+            parsers: parsers,
+        })
+    }
+
+    /// Returns hash type of the signature files, `None` if there are no
+    /// signature files
+    pub fn hash_type(&self) -> Option<HashType> {
+        self.parsers.first().map(|p| p.1.get_header().get_hash_type())
+    }
+
+    /// Returns block size of the signature files, `None` if there are no
+    /// signature files
+    pub fn block_size(&self) -> Option<u64> {
+        self.parsers.first().map(|p| p.1.get_header().get_block_size())
+    }
+
+    /// Creates iterator
+    pub fn iter<'a>(&'a mut self) -> MergedEntriesIterator<'a, K, R> {
+        MergedEntriesIterator {
+            iterators: self.parsers.iter_mut()
+                .map(|&mut (ref key, ref mut parser)| {
+                    (key, parser.iter().peekable())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'a, K, R: BufRead> Iterator for MergedEntriesIterator<'a, K, R> {
+    type Item = Vec<(&'a K, Result<Entry, ParseError>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use std::cmp::Ordering::*;
+
+        let mut ixs = Vec::new();
+        {
+            let mut min_entry: Option<&Entry> = None;
+            for (ix, &mut (_, ref mut iterator)) in
+                self.iterators.iter_mut().enumerate()
+            {
+                match iterator.peek() {
+                    Some(&Ok(ref entry)) => {
+                        let order = min_entry
+                            .map(|min| entry.kind().cmp(&min.kind()));
+                        match order {
+                            None | Some(Less) => {
+                                min_entry = Some(entry);
+                                ixs.clear();
+                                ixs.push(ix);
+                            }
+                            Some(Equal) => ixs.push(ix),
+                            Some(Greater) => {}
+                        }
+                    }
+                    // errors are returned as soon as possible
+                    Some(&Err(_)) => {
+                        ixs.clear();
+                        ixs.push(ix);
+                        break;
+                    }
+                    None => {}
+                }
+            }
+        }
+        if ixs.is_empty() {
+            return None;
+        }
+        Some(ixs.into_iter()
+            .map(|ix| {
+                let (key, ref mut iterator) = self.iterators[ix];
+                (key, iterator.next().expect("peeked entry"))
+            })
+            .collect())
+    }
+}
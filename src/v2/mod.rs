@@ -0,0 +1,64 @@
+//! A module for working with version 2 of directory signature
+//!
+//! Unlike v1, the v2 signature file is binary and stores permissions,
+//! ownership and modification times of all entries, so it can be used for
+//! backups of whole filesystems. Entries are written in the same order as
+//! in v1, so the file is reproducible and can be produced and checked
+//! without loading full index into memory. See `FORMAT.v2.rst` for details.
+//!
+//! Entry points:
+//!
+//! * [`scan`](fn.scan.html) function for creating index file
+//! * [`Parser::new`](struct.Parser.html#method.new) for reading index file
+//! * [`merge::MergedSignatures::new`](merge/struct.MergedSignatures.html)
+//!   for iterating over several index files at once
+
+mod writer;
+mod parser;
+pub mod merge;
+
+use std::io;
+
+pub use error::Error;
+pub use self::parser::{Header, Metadata, Hashes, Entry};
+pub use self::parser::{Parser, EntryIterator, ParseError};
+
+use self::writer::Writer;
+use {ScannerConfig, HashType};
+use hash::{self, Hash};
+use scan;
+
+/// Create a v2 index using specified config
+///
+/// Files are hashed in the current thread. Options which only make sense
+/// for v1 (chunking, whole-file and directory hashes, extended attributes,
+/// compression, threads, cache and progress) are not used. Lookup files
+/// are not supported, `Error::BadLookup` is returned if one is configured.
+///
+/// It's better to use some buffered output file here.
+pub fn scan<F: io::Write>(config: &ScannerConfig, out: &mut F)
+    -> Result<(), Error>
+{
+    match config.hash {
+        HashType::Sha512_256 => scan_with(config, out, hash::Sha512_256),
+        HashType::Blake2b_256 => scan_with(config, out, hash::Blake2b_256),
+        HashType::Blake3_256 => scan_with(config, out, hash::Blake3_256),
+        HashType::Sha256 => scan_with(config, out, hash::Sha256),
+        HashType::Custom(alg) => {
+            HashType::custom(alg)?;
+            scan_with(config, out, hash::Custom(alg))
+        }
+    }
+}
+
+fn scan_with<F: io::Write, H: Hash>(config: &ScannerConfig, out: &mut F,
+    hash: H)
+    -> Result<(), Error>
+{
+    if config.lookup.is_some() {
+        return Err(Error::BadLookup("not supported for v2 index"));
+    }
+    let mut writer = Writer::new(out, hash, config.block_size,
+        &config.header_extra)?;
+    scan::scan(config, &mut writer)
+}
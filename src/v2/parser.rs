@@ -0,0 +1,630 @@
+use std;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{self, BufRead, Read};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::slice::Chunks;
+use std::str::FromStr;
+
+use {HashType, Hasher};
+use hash;
+use v1::EntryKind;
+use super::writer::{MAGIC, VERSION, MODE_MASK};
+use super::writer::{DIR, FILE, SYMLINK, FOOTER};
+
+
+quick_error! {
+    /// The error type that can happen when parsing v2 signature file
+    #[derive(Debug)]
+    pub enum ParseError {
+        /// An I/O operation error, including unexpected end of file
+        Io(err: io::Error) {
+            cause(err)
+            description("error reading buffer")
+            display("Error reading buffer: {}", err)
+            from()
+        }
+        /// Invalid header line
+        InvalidHeader(msg: String) {
+            description("Invalid header")
+            display("Invalid header: {}", msg)
+        }
+        /// Hash type is neither built-in nor passed to the parser
+        UnsupportedHashType(hash_type: String) {
+            description("Unsupported hash type")
+            display("Unsupported hash type: {}", hash_type)
+        }
+        /// Invalid record, `offset` is the position of the record in the file
+        InvalidRecord(offset: u64, msg: String) {
+            description("Invalid record")
+            display("Invalid record at offset {}: {}", offset, msg)
+        }
+        /// Footer doesn't match the hash of the preceding data
+        ChecksumMismatch {
+            description("Checksum mismatch")
+            display("Checksum mismatch: footer doesn't match contents \
+                     of the signature file")
+        }
+    }
+}
+
+/// Header of the v2 signature file
+#[derive(Debug, Clone)]
+pub struct Header {
+    hash_type: HashType,
+    block_size: u64,
+    extra: BTreeMap<String, String>,
+}
+
+/// Permissions, ownership and modification time of an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Metadata {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: i64,
+    mtime_nsec: u32,
+}
+
+/// Hashes of the blocks of a file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hashes {
+    data: Vec<u8>,
+    hash_type: HashType,
+}
+
+/// Represents an entry from v2 signature file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Entry {
+    /// Directory
+    Dir {
+        /// Absolute path of the directory
+        path: PathBuf,
+        /// Metadata of the directory
+        meta: Metadata,
+    },
+    /// Regular file
+    File {
+        /// File path (joined with current directory)
+        path: PathBuf,
+        /// Metadata of the file
+        meta: Metadata,
+        /// File size
+        size: u64,
+        /// Blocks hashes
+        hashes: Hashes,
+    },
+    /// Symbolic link
+    Link {
+        /// Link path (joined with current directory)
+        path: PathBuf,
+        /// Metadata of the link itself
+        meta: Metadata,
+        /// Destination of the link
+        dest: PathBuf,
+    },
+}
+
+/// v2 format parser
+pub struct Parser<R: BufRead> {
+    header: Header,
+    reader: R,
+    hasher: Box<dyn Hasher>,
+    offset: u64,
+    footer: Option<Vec<u8>>,
+}
+
+/// Iterator over the entries of the signature file
+pub struct EntryIterator<'a, R: 'a + BufRead> {
+    parser: &'a mut Parser<R>,
+    current_dir: PathBuf,
+    exhausted: bool,
+}
+
+impl Header {
+    fn parse(line: &[u8], custom_hashes: &[HashType])
+        -> Result<Header, ParseError>
+    {
+        let line = std::str::from_utf8(line).map_err(|e|
+            ParseError::InvalidHeader(format!("{}", e)))?;
+        let mut parts = line.split(' ');
+        let signature = format!("{}.{}", MAGIC, VERSION);
+        match parts.next() {
+            Some(s) if s == signature => {}
+            s => {
+                return Err(ParseError::InvalidHeader(
+                    format!("expected {:?} but was {:?}",
+                        signature, s.unwrap_or(""))));
+            }
+        }
+        let hash_type = match parts.next() {
+            Some(name) => HashType::from_str(name).ok()
+                .or_else(|| custom_hashes.iter()
+                    .find(|h| h.name() == name).cloned())
+                .ok_or_else(|| ParseError::UnsupportedHashType(
+                    name.to_string()))?,
+            None => {
+                return Err(ParseError::InvalidHeader(
+                    format!("Missing hash type")));
+            }
+        };
+        let block_size = match parts.next() {
+            Some(attr) if attr.starts_with("block_size=") => {
+                match u64::from_str(&attr["block_size=".len()..]) {
+                    Ok(size) if size > 0 => size,
+                    _ => {
+                        return Err(ParseError::InvalidHeader(
+                            format!("Invalid block size: {:?}", attr)));
+                    }
+                }
+            }
+            _ => {
+                return Err(ParseError::InvalidHeader(
+                    format!("Missing block size")));
+            }
+        };
+        let mut extra = BTreeMap::new();
+        for attr in parts {
+            let mut kv = attr.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                // skipped like in v1, so that future keys can be added
+                (Some(k), Some(v)) if !k.is_empty() => {
+                    if k == "block_size" || extra.contains_key(k) {
+                        warn!("Skipping duplicate header key {:?}", k);
+                        continue;
+                    }
+                    extra.insert(k.to_string(), v.to_string());
+                }
+                _ => {
+                    warn!("Skipping invalid header pair {:?}", attr);
+                }
+            }
+        }
+        Ok(Header {
+            hash_type: hash_type,
+            block_size: block_size,
+            extra: extra,
+        })
+    }
+
+    /// Returns hashing algorithm
+    pub fn get_hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
+    /// Returns block size
+    pub fn get_block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Returns additional key value pairs following the block size
+    ///
+    /// Pairs without a key and repeated keys are skipped, only the first
+    /// value is kept.
+    pub fn get_extra(&self) -> &BTreeMap<String, String> {
+        &self.extra
+    }
+}
+
+impl Metadata {
+    /// Creates metadata, only permission bits of the `mode` are kept
+    pub fn new(mode: u32, uid: u32, gid: u32, mtime: i64, mtime_nsec: u32)
+        -> Metadata
+    {
+        Metadata {
+            mode: mode & MODE_MASK,
+            uid: uid,
+            gid: gid,
+            mtime: mtime,
+            mtime_nsec: mtime_nsec,
+        }
+    }
+
+    /// Returns permission bits including setuid, setgid and sticky bits
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Returns owner user id
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns owner group id
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Returns modification time as seconds and nanoseconds since the epoch
+    pub fn mtime(&self) -> (i64, u32) {
+        (self.mtime, self.mtime_nsec)
+    }
+}
+
+impl Hashes {
+    /// Returns number of hashes
+    pub fn len(&self) -> usize {
+        self.data.len() / self.hash_type.output_bytes()
+    }
+
+    /// Returns iterator over hashes
+    pub fn iter<'a>(&'a self) -> Chunks<'a, u8> {
+        self.data.chunks(self.hash_type.output_bytes())
+    }
+}
+
+impl Entry {
+    /// Get path of the entry
+    pub fn path(&self) -> &Path {
+        match *self {
+            Entry::Dir { ref path, .. } |
+            Entry::File { ref path, .. } |
+            Entry::Link { ref path, .. } => path,
+        }
+    }
+
+    /// Get metadata of the entry
+    pub fn metadata(&self) -> &Metadata {
+        match *self {
+            Entry::Dir { ref meta, .. } |
+            Entry::File { ref meta, .. } |
+            Entry::Link { ref meta, .. } => meta,
+        }
+    }
+
+    /// Returns kind of the entry, entries are sorted by kind in the
+    /// signature file the same way as in v1
+    pub fn kind(&self) -> EntryKind<&Path> {
+        match *self {
+            Entry::Dir { ref path, .. } => EntryKind::Dir(path),
+            Entry::File { ref path, .. } |
+            Entry::Link { ref path, .. } => EntryKind::File(path),
+        }
+    }
+}
+
+impl<R: BufRead> Parser<R> {
+    /// Creates a directory signature parser (format v2)
+    /// Tries to parse header
+    pub fn new(reader: R) -> Result<Parser<R>, ParseError> {
+        Parser::with_hashes(reader, &[])
+    }
+
+    /// Creates a parser which also supports custom hash types
+    ///
+    /// Hash type of the signature file is looked up by name in `hashes`
+    /// if it's not one of the built-in ones.
+    pub fn with_hashes(mut reader: R, hashes: &[HashType])
+        -> Result<Parser<R>, ParseError>
+    {
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        if line.pop() != Some(b'\n') {
+            return Err(ParseError::InvalidHeader(
+                format!("Header must end with a newline")));
+        }
+        let header = Header::parse(&line, hashes)?;
+        let mut hasher = hash::hasher(header.hash_type);
+        hasher.input(&line);
+        hasher.input(b"\n");
+        Ok(Parser {
+            header: header,
+            reader: reader,
+            hasher: hasher,
+            offset: line.len() as u64 + 1,
+            footer: None,
+        })
+    }
+
+    /// Returns parsed `Header`
+    pub fn get_header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns hash of the signature file contents
+    ///
+    /// The footer is available only when iterator has reached the end of
+    /// the signature file, otherwise `None` is returned.
+    pub fn get_footer(&self) -> Option<&[u8]> {
+        self.footer.as_ref().map(|f| &f[..])
+    }
+
+    /// Creates iterator over directory signature entries
+    ///
+    /// Should be called only once, the parser can't be rewound.
+    pub fn iter(&mut self) -> EntryIterator<R> {
+        EntryIterator {
+            parser: self,
+            current_dir: PathBuf::new(),
+            exhausted: false,
+        }
+    }
+
+    /// Consumes the parser returning ownership of the underlying reader
+    pub fn into_reader(self) -> R {
+        self.reader
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), ParseError> {
+        self.reader.read_exact(buf)?;
+        self.hasher.input(buf);
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, ParseError> {
+        let mut buf = [0u8];
+        self.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Reads `len` bytes without allocating them upfront, so corrupted
+    /// lengths fail at the end of file instead of exhausting memory
+    fn read_vec(&mut self, len: u64) -> Result<Vec<u8>, ParseError> {
+        let mut buf = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut buf)?;
+        if (buf.len() as u64) < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                "signature file is truncated").into());
+        }
+        self.hasher.input(&buf);
+        self.offset += len;
+        Ok(buf)
+    }
+
+    fn read_varint(&mut self, start: u64) -> Result<u64, ParseError> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            if shift == 63 && byte > 1 {
+                return Err(invalid(start, "Integer is too large"));
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                if byte == 0 && shift > 0 {
+                    return Err(invalid(start, "Integer is not minimal"));
+                }
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_u32(&mut self, start: u64) -> Result<u32, ParseError> {
+        let value = self.read_varint(start)?;
+        if value > u32::max_value() as u64 {
+            return Err(invalid(start, "Integer is too large"));
+        }
+        Ok(value as u32)
+    }
+
+    fn read_bytes(&mut self, start: u64) -> Result<Vec<u8>, ParseError> {
+        let len = self.read_varint(start)?;
+        self.read_vec(len)
+    }
+
+    fn read_name(&mut self, start: u64) -> Result<OsString, ParseError> {
+        let name = self.read_bytes(start)?;
+        if name.is_empty() || name == b"." || name == b".." ||
+            name.iter().any(|&c| c == b'/' || c == 0)
+        {
+            return Err(invalid(start, "Invalid file name"));
+        }
+        Ok(OsString::from_vec(name))
+    }
+
+    fn read_metadata(&mut self, start: u64) -> Result<Metadata, ParseError> {
+        let mode = self.read_u32(start)?;
+        if mode & !MODE_MASK != 0 {
+            return Err(invalid(start, "Invalid mode"));
+        }
+        let uid = self.read_u32(start)?;
+        let gid = self.read_u32(start)?;
+        let zigzag = self.read_varint(start)?;
+        let mtime = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        let mtime_nsec = self.read_u32(start)?;
+        if mtime_nsec >= 1_000_000_000 {
+            return Err(invalid(start, "Invalid modification time"));
+        }
+        Ok(Metadata::new(mode, uid, gid, mtime, mtime_nsec))
+    }
+
+    fn read_footer(&mut self) -> Result<(), ParseError> {
+        let mut footer = vec![0u8; self.header.hash_type.output_bytes()];
+        self.reader.read_exact(&mut footer)?;
+        if self.hasher.result() != footer {
+            return Err(ParseError::ChecksumMismatch);
+        }
+        if !self.reader.fill_buf()?.is_empty() {
+            return Err(invalid(self.offset + footer.len() as u64,
+                "Data after the footer"));
+        }
+        self.footer = Some(footer);
+        Ok(())
+    }
+}
+
+fn invalid(offset: u64, msg: &str) -> ParseError {
+    ParseError::InvalidRecord(offset, msg.to_string())
+}
+
+impl<'a, R: BufRead> EntryIterator<'a, R> {
+    fn parse_entry(&mut self) -> Result<Option<Entry>, ParseError> {
+        let start = self.parser.offset;
+        let parser = &mut *self.parser;
+        match parser.read_byte()? {
+            DIR => {
+                let path = PathBuf::from(OsString::from_vec(
+                    parser.read_bytes(start)?));
+                if !path.is_absolute() {
+                    return Err(invalid(start, "Directory path is relative"));
+                }
+                let meta = parser.read_metadata(start)?;
+                self.current_dir = path.clone();
+                Ok(Some(Entry::Dir { path: path, meta: meta }))
+            }
+            FILE => {
+                if self.current_dir.as_os_str().is_empty() {
+                    return Err(invalid(start, "File outside of directory"));
+                }
+                let path = self.current_dir.join(parser.read_name(start)?);
+                let meta = parser.read_metadata(start)?;
+                let size = parser.read_varint(start)?;
+                let block_size = parser.header.block_size;
+                let hash_type = parser.header.hash_type;
+                let blocks = size / block_size +
+                    if size % block_size > 0 { 1 } else { 0 };
+                let data = parser.read_vec(
+                    blocks.saturating_mul(hash_type.output_bytes() as u64))?;
+                Ok(Some(Entry::File {
+                    path: path,
+                    meta: meta,
+                    size: size,
+                    hashes: Hashes { data: data, hash_type: hash_type },
+                }))
+            }
+            SYMLINK => {
+                if self.current_dir.as_os_str().is_empty() {
+                    return Err(invalid(start, "Symlink outside of directory"));
+                }
+                let path = self.current_dir.join(parser.read_name(start)?);
+                let meta = parser.read_metadata(start)?;
+                let dest = PathBuf::from(OsString::from_vec(
+                    parser.read_bytes(start)?));
+                Ok(Some(Entry::Link { path: path, meta: meta, dest: dest }))
+            }
+            FOOTER => {
+                parser.read_footer()?;
+                Ok(None)
+            }
+            tag => {
+                Err(invalid(start, &format!("Unknown record type {:?}",
+                    tag as char)))
+            }
+        }
+    }
+}
+
+impl<'a, R: BufRead> Iterator for EntryIterator<'a, R> {
+    type Item = Result<Entry, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+        match self.parse_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.exhausted = true;
+                None
+            }
+            Err(e) => {
+                self.exhausted = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Cursor};
+    use std::path::Path;
+
+    use HashType;
+    use hash;
+    use super::{Entry, Metadata, Parser, ParseError};
+
+    const HEADER: &'static [u8] =
+        b"DIRSIGNATURE.v2 sha512/256 block_size=32768\n";
+
+    fn index(records: &[u8]) -> Vec<u8> {
+        let mut data = HEADER.to_vec();
+        data.extend_from_slice(records);
+        data.push(b'E');
+        let mut hasher = hash::hasher(HashType::Sha512_256);
+        hasher.input(&data);
+        data.extend_from_slice(&hasher.result());
+        data
+    }
+
+    fn parse(data: &[u8]) -> Result<Vec<Entry>, ParseError> {
+        let mut parser = Parser::new(BufReader::new(Cursor::new(data)))?;
+        let entries = parser.iter().collect::<Result<Vec<_>, _>>();
+        entries
+    }
+
+    #[test]
+    fn test_header() {
+        let data = b"DIRSIGNATURE.v2 blake3/256 block_size=4096 x=y\n";
+        let parser = Parser::new(BufReader::new(Cursor::new(&data[..])))
+            .unwrap();
+        let header = parser.get_header();
+        assert_eq!(header.get_hash_type(), HashType::Blake3_256);
+        assert_eq!(header.get_block_size(), 4096);
+        assert_eq!(header.get_extra().get("x").unwrap(), "y");
+
+        // invalid and duplicate pairs are skipped
+        let data = b"DIRSIGNATURE.v2 blake3/256 block_size=4096 x=y x=z w\n";
+        let parser = Parser::new(BufReader::new(Cursor::new(&data[..])))
+            .unwrap();
+        let header = parser.get_header();
+        assert_eq!(header.get_extra().len(), 1);
+        assert_eq!(header.get_extra().get("x").unwrap(), "y");
+
+        let data = b"DIRSIGNATURE.v1 sha512/256 block_size=32768\n";
+        let res = Parser::new(BufReader::new(Cursor::new(&data[..])));
+        assert!(matches!(res, Err(ParseError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_entries() {
+        let data = index(b"\
+            D\x01/\xed\x03\x00\x00\x03\x00\
+            F\x01a\xa4\x03\xe8\x07\xe8\x07\x00\x00\x00\
+            S\x01b\xff\x03\x00\x00\x00\x00\x01a");
+        let entries = parse(&data).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], Entry::Dir {
+            path: "/".into(),
+            meta: Metadata::new(0o755, 0, 0, -2, 0),
+        });
+        assert!(matches!(entries[1],
+            Entry::File { ref path, meta, size: 0, ref hashes }
+            if path == Path::new("/a") && hashes.len() == 0 &&
+               meta == Metadata::new(0o644, 1000, 1000, 0, 0)));
+        assert_eq!(entries[2], Entry::Link {
+            path: "/b".into(),
+            meta: Metadata::new(0o777, 0, 0, 0, 0),
+            dest: "a".into(),
+        });
+        assert_eq!(entries[2].kind().path(), Path::new("/b"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        // file before any directory
+        let res = parse(&index(b"F\x01a\xa4\x03\x00\x00\x00\x00\x00"));
+        assert!(matches!(res, Err(ParseError::InvalidRecord(44, _))));
+        // non-minimal integer
+        let res = parse(&index(b"D\x81\x00/\xed\x03\x00\x00\x00\x00"));
+        assert!(matches!(res, Err(ParseError::InvalidRecord(44, _))));
+        // name with a slash
+        let res = parse(&index(b"\
+            D\x01/\xed\x03\x00\x00\x00\x00\
+            F\x03a/b\xa4\x03\x00\x00\x00\x00\x00"));
+        assert!(matches!(res, Err(ParseError::InvalidRecord(53, _))));
+        // truncated
+        let data = index(b"D\x01/\xed\x03\x00\x00\x00\x00");
+        let res = parse(&data[..data.len()-1]);
+        assert!(matches!(res, Err(ParseError::Io(_))));
+
+        let mut data = index(b"D\x01/\xed\x03\x00\x00\x00\x00");
+        data[49] = 5;
+        let res = parse(&data);
+        assert!(matches!(res, Err(ParseError::ChecksumMismatch)));
+    }
+}
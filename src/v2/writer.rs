@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use libc;
+use openat::{Dir, Entry};
+
+use error::Error::{self, WriteError as EWrite, ReadFile as EFile};
+use error::Error::BadHeaderKey;
+use Hasher;
+use hash::Hash;
+use scan::Visitor;
+use super::parser::Metadata;
+
+
+pub const MAGIC: &'static str = "DIRSIGNATURE";
+pub const VERSION: &'static str = "v2";
+
+pub const DIR: u8 = b'D';
+pub const FILE: u8 = b'F';
+pub const SYMLINK: u8 = b'S';
+pub const FOOTER: u8 = b'E';
+
+/// Permission bits stored in the index, file type is stored separately
+pub const MODE_MASK: u32 = 0o7777;
+
+/// Metadata of the intermediate directories of prefixes
+const PREFIX_MODE: u32 = 0o755;
+
+
+struct HashWriter<F> {
+    file: F,
+    digest: Box<dyn Hasher>,
+}
+
+pub struct Writer<F, H: Hash> {
+    file: HashWriter<F>,
+    hash: H,
+    block_size: u64,
+}
+
+fn valid_header_key(key: &str, value: &str) -> bool {
+    let valid_char = |c: u8| c > 0x20 && c < 0x7f;
+    !key.is_empty() && key != "block_size" &&
+        key.bytes().all(|c| valid_char(c) && c != b'=') &&
+        value.bytes().all(valid_char)
+}
+
+/// Writes unsigned LEB128 integer
+pub fn write_varint<W: io::Write>(out: &mut W, mut value: u64)
+    -> io::Result<()>
+{
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    out.write_all(&buf[..len])
+}
+
+/// Writes length-prefixed byte string
+fn write_bytes<W: io::Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    write_varint(out, data.len() as u64)?;
+    out.write_all(data)
+}
+
+fn write_metadata<W: io::Write>(out: &mut W, meta: &Metadata)
+    -> io::Result<()>
+{
+    let (sec, nsec) = meta.mtime();
+    write_varint(out, meta.mode() as u64)?;
+    write_varint(out, meta.uid() as u64)?;
+    write_varint(out, meta.gid() as u64)?;
+    // zigzag encoding, so that times before the epoch are short too
+    write_varint(out, ((sec << 1) ^ (sec >> 63)) as u64)?;
+    write_varint(out, nsec as u64)
+}
+
+fn stat_metadata(stat: &libc::stat) -> Metadata {
+    Metadata::new(stat.st_mode as u32,
+        stat.st_uid as u32, stat.st_gid as u32,
+        stat.st_mtime as i64, stat.st_mtime_nsec as u32)
+}
+
+impl<F: io::Write, H: Hash> Writer<F, H> {
+    pub fn new(f: F, hash: H, block_size: u64,
+        extra: &BTreeMap<String, String>)
+        -> Result<Writer<F, H>, Error>
+    {
+        for (key, value) in extra {
+            if !valid_header_key(key, value) {
+                return Err(BadHeaderKey(key.clone(), value.clone()));
+            }
+        }
+        let mut file = HashWriter {
+            file: f,
+            digest: hash.hasher(),
+        };
+        write!(&mut file, "{}.{} {} block_size={}",
+            MAGIC, VERSION, hash.name(), block_size,
+        ).map_err(EWrite)?;
+        for (key, value) in extra {
+            write!(&mut file, " {}={}", key, value).map_err(EWrite)?;
+        }
+        file.write_all(b"\n").map_err(EWrite)?;
+        Ok(Writer {
+            file: file,
+            hash: hash,
+            block_size: block_size,
+        })
+    }
+}
+
+impl<F: io::Write, H: Hash> Visitor for Writer<F, H> {
+    fn start_dir(&mut self, path: &Path, dir: Option<&Arc<Dir>>)
+        -> Result<(), Error>
+    {
+        let meta = match dir {
+            Some(dir) => {
+                let meta = dir.metadata(".").map_err(EFile)?;
+                stat_metadata(meta.stat())
+            }
+            None => Metadata::new(PREFIX_MODE, 0, 0, 0, 0),
+        };
+        self.file.write_all(&[DIR]).map_err(EWrite)?;
+        write_bytes(&mut self.file, path.as_os_str().as_bytes())
+            .map_err(EWrite)?;
+        write_metadata(&mut self.file, &meta).map_err(EWrite)?;
+        Ok(())
+    }
+    fn add_file(&mut self, dir: &Arc<Dir>, entry: Entry)
+        -> Result<(), Error>
+    {
+        let mut f = dir.open_file(&entry).map_err(EFile)?;
+        let stat = f.metadata().map_err(EFile)?;
+        let meta = Metadata::new(stat.mode(),
+            stat.uid(), stat.gid(), stat.mtime(), stat.mtime_nsec() as u32);
+        let size = stat.len();
+        self.file.write_all(&[FILE]).map_err(EWrite)?;
+        write_bytes(&mut self.file, entry.file_name().as_bytes())
+            .map_err(EWrite)?;
+        write_metadata(&mut self.file, &meta).map_err(EWrite)?;
+        write_varint(&mut self.file, size).map_err(EWrite)?;
+        let mut n = size;
+        while n > 0 {
+            let h = self.hash.hash_file(&mut f, self.block_size)
+                .map_err(EFile)?;
+            self.file.write_all(h.as_ref()).map_err(EWrite)?;
+            n = n.saturating_sub(self.block_size);
+        }
+        Ok(())
+    }
+    fn add_symlink(&mut self, dir: &Arc<Dir>, entry: Entry)
+        -> Result<(), Error>
+    {
+        let meta = dir.metadata(&entry).map_err(EFile)?;
+        let dest = dir.read_link(&entry).map_err(EFile)?;
+        self.file.write_all(&[SYMLINK]).map_err(EWrite)?;
+        write_bytes(&mut self.file, entry.file_name().as_bytes())
+            .map_err(EWrite)?;
+        write_metadata(&mut self.file, &stat_metadata(meta.stat()))
+            .map_err(EWrite)?;
+        write_bytes(&mut self.file, dest.as_os_str().as_bytes())
+            .map_err(EWrite)?;
+        Ok(())
+    }
+    fn done(&mut self) -> Result<(), Error> {
+        self.file.write_all(&[FOOTER]).map_err(EWrite)?;
+        let digest = self.file.digest.result();
+        self.file.file.write_all(&digest).map_err(EWrite)
+    }
+}
+
+impl<F: io::Write> io::Write for HashWriter<F> {
+    fn write(&mut self, chunk: &[u8]) -> io::Result<usize> {
+        let bytes = self.file.write(chunk)?;
+        self.digest.input(&chunk[..bytes]);
+        Ok(bytes)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::write_varint;
+
+    fn varint(value: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, value).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_varint() {
+        assert_eq!(varint(0), b"\x00");
+        assert_eq!(varint(0x7f), b"\x7f");
+        assert_eq!(varint(0x80), b"\x80\x01");
+        assert_eq!(varint(300), b"\xac\x02");
+        assert_eq!(varint(!0), b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01");
+    }
+}
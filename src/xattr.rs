@@ -0,0 +1,146 @@
+//! Reading extended attributes
+//!
+//! Attributes are read through file descriptors, so no paths are resolved
+//! except the name of the symlink inside the directory. Directories are
+//! opened with `O_PATH` which doesn't allow `flistxattr`, so they are
+//! accessed through `/proc/self/fd` too.
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::slice::Iter;
+
+use libc::{self, c_char, c_void, size_t, ssize_t};
+use openat::Dir;
+
+
+/// Extended attributes of an entry (including POSIX ACLs and file
+/// capabilities which are stored as extended attributes)
+///
+/// Attributes are sorted by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Xattrs {
+    items: Vec<(OsString, Vec<u8>)>,
+}
+
+impl Xattrs {
+    /// Creates attributes from name value pairs, sorting them by name
+    pub fn new<I>(items: I) -> Xattrs
+        where I: IntoIterator<Item=(OsString, Vec<u8>)>
+    {
+        let mut items = items.into_iter().collect::<Vec<_>>();
+        items.sort();
+        Xattrs { items: items }
+    }
+
+    /// Returns number of attributes
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no attributes
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns value of the attribute
+    pub fn get<S: AsRef<OsStr>>(&self, name: S) -> Option<&[u8]> {
+        let name = name.as_ref();
+        self.items.iter()
+            .find(|&&(ref n, _)| &n[..] == name)
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// Returns iterator over attribute names and values sorted by name
+    pub fn iter(&self) -> Iter<(OsString, Vec<u8>)> {
+        self.items.iter()
+    }
+}
+
+/// Calls `f` with growing buffer until the data fits
+fn read_sized<F>(mut f: F) -> io::Result<Vec<u8>>
+    where F: FnMut(*mut c_void, size_t) -> ssize_t
+{
+    loop {
+        let size = f(0 as *mut c_void, 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let len = f(buf.as_mut_ptr() as *mut c_void, buf.len());
+        if len >= 0 {
+            buf.truncate(len as usize);
+            return Ok(buf);
+        }
+        let err = io::Error::last_os_error();
+        // attribute has grown since the size was read
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+fn read<L, G>(mut list: L, mut get: G) -> io::Result<Xattrs>
+    where L: FnMut(*mut c_char, size_t) -> ssize_t,
+          G: FnMut(*const c_char, *mut c_void, size_t) -> ssize_t,
+{
+    let names = match read_sized(|buf, size| list(buf as *mut c_char, size)) {
+        Ok(names) => names,
+        Err(ref e) if e.raw_os_error() == Some(libc::ENOTSUP) => {
+            return Ok(Xattrs::default());
+        }
+        Err(e) => return Err(e),
+    };
+    let mut items = Vec::new();
+    for name in names.split(|&c| c == 0).filter(|n| !n.is_empty()) {
+        let cname = CString::new(name).expect("no zero bytes in name");
+        match read_sized(|buf, size| get(cname.as_ptr(), buf, size)) {
+            Ok(value) => {
+                items.push((OsString::from_vec(name.to_vec()), value));
+            }
+            // attribute was removed since the list was read
+            Err(ref e) if e.raw_os_error() == Some(libc::ENODATA) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Xattrs::new(items))
+}
+
+/// Reads extended attributes of the open file
+pub fn read_fd<F: AsRawFd>(file: &F) -> io::Result<Xattrs> {
+    let fd: RawFd = file.as_raw_fd();
+    read(
+        |buf, size| unsafe { libc::flistxattr(fd, buf, size) },
+        |name, buf, size| unsafe { libc::fgetxattr(fd, name, buf, size) })
+}
+
+/// Reads extended attributes of the directory
+///
+/// Directory descriptors are opened with `O_PATH`, so `f*xattr` calls fail
+/// with `EBADF` on them. The magic link in `/proc/self/fd` is followed
+/// instead.
+pub fn read_dir(dir: &Dir) -> io::Result<Xattrs> {
+    let path = CString::new(format!("/proc/self/fd/{}", dir.as_raw_fd()))
+        .expect("no zero bytes in path");
+    read(
+        |buf, size| unsafe { libc::listxattr(path.as_ptr(), buf, size) },
+        |name, buf, size| unsafe {
+            libc::getxattr(path.as_ptr(), name, buf, size)
+        })
+}
+
+/// Reads extended attributes of the symlink itself
+///
+/// Symlinks can't be opened, so the link is accessed by name relative to
+/// the directory descriptor using `/proc/self/fd`.
+pub fn read_link(dir: &Dir, name: &OsStr) -> io::Result<Xattrs> {
+    let mut path = format!("/proc/self/fd/{}/", dir.as_raw_fd()).into_bytes();
+    path.extend_from_slice(name.as_bytes());
+    let path = CString::new(path)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    read(
+        |buf, size| unsafe { libc::llistxattr(path.as_ptr(), buf, size) },
+        |name, buf, size| unsafe {
+            libc::lgetxattr(path.as_ptr(), name, buf, size)
+        })
+}
@@ -0,0 +1,147 @@
+extern crate dir_signature;
+extern crate difference;
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use dir_signature::{ScannerConfig, v1};
+use difference::assert_diff;
+
+const DIR1_INDEX: &'static str = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+/subdir
+  .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+8459d4b1c12835153c2aa695cfd33395ab3793e1e1e7d015dbdc466d56146d92
+";
+
+const HELLO_HASH: &'static str =
+    "8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc";
+const FAKE_HASH: &'static str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = Path::new("target/test-cache").join(name);
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn scan_cached(index: &Path, stats: &Path) -> String {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.cache(index, stats);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+fn write_file(path: &Path, data: &str) {
+    File::create(path).unwrap().write_all(data.as_bytes()).unwrap();
+}
+
+fn read_file(path: &Path) -> String {
+    let mut buf = String::new();
+    File::open(path).unwrap().read_to_string(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn test_no_previous_index() {
+    let dir = work_dir("no_index");
+    let stats = dir.join("stats");
+    assert_diff(&scan_cached(&dir.join("index"), &stats), DIR1_INDEX, "\n", 0);
+    let stats = read_file(&stats);
+    assert!(stats.starts_with("DIRSIGNATURE-STATS.v1\n/hello.txt 6 "));
+    assert_eq!(stats.lines().count(), 5);
+    assert_eq!(stats.lines().nth(1).unwrap().split(' ').count(), 7);
+}
+
+#[test]
+fn test_reuse_hashes() {
+    let dir = work_dir("reuse");
+    let index = dir.join("index");
+    let stats = dir.join("stats");
+    scan_cached(&index, &stats);
+    // hashes are taken from the index so we can check that they are reused
+    write_file(&index, &DIR1_INDEX.replace(HELLO_HASH, FAKE_HASH));
+    let result = scan_cached(&index, &stats);
+    assert!(result.contains(
+        &format!("  hello.txt f 6 {}\n", FAKE_HASH)));
+    assert_eq!(result.lines().count(), DIR1_INDEX.lines().count());
+    // stat cache is rewritten and can be used again
+    let result = scan_cached(&index, &stats);
+    assert!(result.contains(
+        &format!("  hello.txt f 6 {}\n", FAKE_HASH)));
+}
+
+/// Pretends that `/hello.txt` was changed since previous scan by replacing
+/// a field of its stat cache entry
+fn change_stat(stats: &Path, field: usize) {
+    let data = read_file(stats).lines()
+        .map(|line| if line.starts_with("/hello.txt ") {
+            let mut fields = line.split(' ').collect::<Vec<_>>();
+            fields[field] = "12345";
+            fields.join(" ")
+        } else {
+            line.to_string()
+        })
+        .collect::<Vec<_>>().join("\n") + "\n";
+    write_file(stats, &data);
+}
+
+#[test]
+fn test_rehash_changed() {
+    let dir = work_dir("changed");
+    let index = dir.join("index");
+    let stats = dir.join("stats");
+    scan_cached(&index, &stats);
+    write_file(&index, &DIR1_INDEX.replace(HELLO_HASH, FAKE_HASH));
+    // mtime
+    change_stat(&stats, 2);
+    assert_diff(&scan_cached(&index, &stats), DIR1_INDEX, "\n", 0);
+}
+
+#[test]
+fn test_rehash_ctime_changed() {
+    let dir = work_dir("ctime_changed");
+    let index = dir.join("index");
+    let stats = dir.join("stats");
+    scan_cached(&index, &stats);
+    write_file(&index, &DIR1_INDEX.replace(HELLO_HASH, FAKE_HASH));
+    // mtime is kept, e.g. by `touch -r`, but ctime is changed anyway
+    change_stat(&stats, 4);
+    assert_diff(&scan_cached(&index, &stats), DIR1_INDEX, "\n", 0);
+}
+
+#[test]
+fn test_broken_index() {
+    let dir = work_dir("broken");
+    let index = dir.join("index");
+    let stats = dir.join("stats");
+    scan_cached(&index, &stats);
+    write_file(&index, &DIR1_INDEX.replace(HELLO_HASH, "xyz"));
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.cache(&index, &stats);
+    assert!(v1::scan(&cfg, &mut Vec::new()).is_err());
+    // previous stat cache is kept and the temporary file is removed
+    assert!(read_file(&stats).starts_with("DIRSIGNATURE-STATS.v1\n"));
+    assert!(!dir.join("stats.tmp").exists());
+}
+
+#[test]
+fn test_incompatible_index() {
+    let dir = work_dir("incompatible");
+    let index = dir.join("index");
+    let stats = dir.join("stats");
+    scan_cached(&index, &stats);
+    write_file(&index, &DIR1_INDEX
+        .replace(HELLO_HASH, FAKE_HASH)
+        .replace("block_size=32768", "block_size=65536"));
+    assert_diff(&scan_cached(&index, &stats), DIR1_INDEX, "\n", 0);
+}
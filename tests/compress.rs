@@ -0,0 +1,89 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Write};
+use std::path::{Path, PathBuf};
+
+extern crate rustc_serialize;
+use rustc_serialize::hex::FromHex;
+
+#[macro_use] extern crate matches;
+
+extern crate dir_signature;
+use dir_signature::{ScannerConfig, Compression, get_hash, v1};
+use dir_signature::v1::{Parser, ParseError, ParseRowError};
+use dir_signature::v1::merge::FileMergeBuilder;
+
+const DIR2_FOOTER: &'static str =
+    "f506b32f8769870afcfcc0c0880c36a8ef7c9c29959f4bd08f5b67cb720d4c81";
+
+fn scan(dir: &str, compression: Compression) -> Vec<u8> {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    cfg.compression(compression);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    buf
+}
+
+fn entries(data: &[u8]) -> Vec<v1::Entry> {
+    let mut parser = Parser::new(BufReader::new(Cursor::new(data))).unwrap();
+    let entries = parser.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(parser.get_footer().unwrap().get_hash(),
+               &DIR2_FOOTER.from_hex().unwrap()[..]);
+    entries
+}
+
+#[test]
+fn test_gzip() {
+    let plain = scan("tests/dir2", Compression::None);
+    let gzip = scan("tests/dir2", Compression::Gzip);
+    assert!(gzip.starts_with(b"\x1f\x8b"));
+    assert!(gzip.len() < plain.len());
+    assert_eq!(entries(&gzip), entries(&plain));
+    assert_eq!(get_hash(&mut Cursor::new(&gzip[..])).unwrap(),
+               DIR2_FOOTER.from_hex().unwrap());
+}
+
+#[test]
+fn test_zstd() {
+    let plain = scan("tests/dir2", Compression::None);
+    let zstd = scan("tests/dir2", Compression::Zstd);
+    assert!(zstd.starts_with(b"\x28\xb5\x2f\xfd"));
+    assert!(zstd.len() < plain.len());
+    assert_eq!(entries(&zstd), entries(&plain));
+    assert_eq!(get_hash(&mut Cursor::new(&zstd[..])).unwrap(),
+               DIR2_FOOTER.from_hex().unwrap());
+}
+
+#[test]
+fn test_truncated() {
+    let zstd = scan("tests/dir2", Compression::Zstd);
+    let truncated = &zstd[..zstd.len()-20];
+    assert!(get_hash(&mut Cursor::new(truncated)).is_err());
+    // the whole index fits into a single compressed block, so even the
+    // header can't be read
+    let result = Parser::new(BufReader::new(Cursor::new(truncated)))
+        .and_then(|mut p| p.iter().collect::<Result<Vec<_>, _>>());
+    assert!(matches!(result,
+        Err(ParseError::Parse(ParseRowError::Read(_), 1))),
+        "Was: {:?}", result);
+}
+
+#[test]
+fn test_merge_builder() {
+    let dir = Path::new("target/test-compress");
+    fs::create_dir_all(dir).unwrap();
+    File::create(dir.join("dir1.gz")).unwrap()
+        .write_all(&scan("tests/dir1", Compression::Gzip)).unwrap();
+    File::create(dir.join("dir2.zst")).unwrap()
+        .write_all(&scan("tests/dir2", Compression::Zstd)).unwrap();
+    let mut builder = FileMergeBuilder::new();
+    builder.add("/1", dir.join("dir1.gz"));
+    builder.add("/2", dir.join("dir2.zst"));
+    let mut merged = builder.finalize().unwrap();
+    let paths = merged.iter()
+        .map(|row| row.iter().map(|&(k, _)| k.clone()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let both = vec![PathBuf::from("/1"), PathBuf::from("/2")];
+    assert_eq!(paths.len(), 11);
+    assert_eq!(paths[0], both);
+}
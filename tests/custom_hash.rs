@@ -0,0 +1,128 @@
+extern crate dir_signature;
+extern crate difference;
+#[macro_use] extern crate matches;
+
+use std::io::{BufReader, Cursor};
+
+use dir_signature::{ScannerConfig, HashAlgorithm, Hasher, HashType, Error, v1};
+use dir_signature::v1::{Parser, ParseError, ParseRowError};
+use difference::assert_diff;
+
+/// 64-bit FNV-1a, never use it for anything real
+struct Fnv;
+struct FnvHasher(u64);
+
+impl HashAlgorithm for Fnv {
+    fn name(&self) -> &str {
+        "fnv1a/64"
+    }
+    fn output_bytes(&self) -> usize {
+        8
+    }
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(FnvHasher(0xcbf29ce484222325))
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn input(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    fn result(&self) -> Vec<u8> {
+        (0..8).rev().map(|i| (self.0 >> (i * 8)) as u8).collect()
+    }
+}
+
+/// FNV with an arbitrary name
+struct Named(&'static str);
+
+impl HashAlgorithm for Named {
+    fn name(&self) -> &str {
+        self.0
+    }
+    fn output_bytes(&self) -> usize {
+        Fnv.output_bytes()
+    }
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Fnv.hasher()
+    }
+}
+
+static FNV: Fnv = Fnv;
+static SPACE: Named = Named("fnv 64");
+static EQUALS: Named = Named("fnv=64");
+static UPPER: Named = Named("FNV");
+static SHA256: Named = Named("sha256");
+static SHA512_256: Named = Named("sha512/256");
+static BLAKE2B_256: Named = Named("blake2b/256");
+static BLAKE3_256: Named = Named("blake3/256");
+
+const DIR1_INDEX: &'static str = "\
+DIRSIGNATURE.v1 fnv1a/64 block_size=32768
+/
+  hello.txt f 6 7da032d518a2ba53
+  test.txt f 0
+/subdir
+  .hidden f 7 1895c4e46cff1ac9
+  file.txt f 10 cd35faa4c7fd9a05
+b78b81bf79afa438
+";
+
+#[test]
+fn test_scan_custom_hash() {
+    let mut cfg = ScannerConfig::new();
+    cfg.hash(HashType::custom(&FNV).unwrap());
+    cfg.add_dir("tests/dir1", "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), DIR1_INDEX, "\n", 0);
+}
+
+#[test]
+fn test_parse_custom_hash() {
+    let reader = BufReader::new(Cursor::new(DIR1_INDEX.as_bytes()));
+    let res = Parser::new(reader);
+    assert!(matches!(res,
+        Err(ParseError::Parse(ParseRowError::UnsupportedHashType(ref h), 1))
+        if h == "fnv1a/64"));
+
+    let reader = BufReader::new(Cursor::new(DIR1_INDEX.as_bytes()));
+    let mut parser = Parser::with_hashes(reader, &[HashType::Custom(&FNV)])
+        .unwrap();
+    assert_eq!(parser.get_header().get_hash_type(), HashType::Custom(&FNV));
+    let entries = parser.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len(), 6);
+
+    let report = v1::verify(&mut Parser::with_hashes(
+        BufReader::new(Cursor::new(DIR1_INDEX.as_bytes())),
+        &[HashType::Custom(&FNV)]).unwrap(),
+        "tests/dir1").unwrap();
+    assert!(report.is_clean());
+}
+
+#[test]
+fn test_bad_custom_name() {
+    for alg in &[&SPACE, &EQUALS, &UPPER,
+                 &SHA256, &SHA512_256, &BLAKE2B_256, &BLAKE3_256]
+    {
+        assert!(matches!(HashType::custom(*alg),
+            Err(Error::BadHashName(ref name)) if name == alg.0));
+    }
+    let mut cfg = ScannerConfig::new();
+    cfg.hash(HashType::Custom(&SPACE));
+    cfg.add_dir("tests/dir1", "/");
+    assert!(matches!(v1::scan(&cfg, &mut Vec::new()),
+        Err(Error::BadHashName(_))));
+    // would be read back as the built-in sha256
+    cfg.hash(HashType::Custom(&SHA256));
+    assert!(matches!(v1::scan(&cfg, &mut Vec::new()),
+        Err(Error::BadHashName(_))));
+}
+
+#[test]
+fn test_compare_by_name() {
+    assert_eq!(HashType::Sha256, HashType::Custom(&SHA256));
+    assert!(HashType::Custom(&FNV) != HashType::Sha256);
+}
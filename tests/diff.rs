@@ -0,0 +1,216 @@
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+
+#[macro_use] extern crate matches;
+
+extern crate dir_signature;
+use dir_signature::{ScannerConfig, v1};
+use dir_signature::v1::{Entry, Parser};
+use dir_signature::v1::diff::{Change, Diff};
+use dir_signature::v1::merge::MergeError;
+
+fn parser(content: &[u8]) -> Parser<BufReader<Cursor<&[u8]>>> {
+    Parser::new(BufReader::new(Cursor::new(content))).unwrap()
+}
+
+#[test]
+fn test_diff() {
+    let old = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  empty.txt f 0
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  script.sh f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+/subdir
+  big.bin f 65536 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433
+  link s ../hello.txt
+c2c3ba48b0ca774b4e5b967b08efa68011f7220b7484ae6aef5f1ebae513a609
+";
+    let new = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  empty.txt f 0
+  hello.txt f 6 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  new.txt f 0
+  script.sh x 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+/subdir
+  big.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+27009b877c5c5c83e802f0151ca034a9bd5dfd8279fb6e7144b8ac4297f93b74
+";
+    let mut diff = Diff::new(parser(&old[..]), parser(&new[..])).unwrap();
+    let changes = diff.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(changes.iter().map(|c| c.path()).collect::<Vec<_>>(), vec![
+        Path::new("/hello.txt"),
+        Path::new("/new.txt"),
+        Path::new("/script.sh"),
+        Path::new("/subdir/big.bin"),
+        Path::new("/subdir/link"),
+    ]);
+    assert!(matches!(changes[0],
+        Change::Modified { ref blocks, .. } if blocks == &[0]));
+    assert!(matches!(changes[1],
+        Change::Added(Entry::File { size: 0, .. })));
+    assert!(matches!(changes[2],
+        Change::Modified {
+            old: Entry::File { exe: false, .. },
+            new: Entry::File { exe: true, .. },
+            ref blocks,
+        } if blocks.is_empty()));
+    assert!(matches!(changes[3],
+        Change::Modified { ref blocks, .. } if blocks == &[1, 2]));
+    assert_eq!(changes[4], Change::Removed(
+        Entry::Link(PathBuf::from("/subdir/link"),
+                    PathBuf::from("../hello.txt"), None)));
+}
+
+#[test]
+fn test_diff_same() {
+    let content = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+/subdir
+32d2c5ad7f90acc907353abfe94d32021bc8760ef1f9bf8d99a970982365fcab
+";
+    let mut diff = Diff::new(parser(&content[..]), parser(&content[..]))
+        .unwrap();
+    assert_eq!(diff.iter().count(), 0);
+}
+
+#[test]
+fn test_diff_dir_replaced() {
+    let old = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  sub f 0
+e20e45e67a4b91b113e142e02a6d3b9cec7d4eb374f747ff7ea642dd1d6198f4
+";
+    let new = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+/sub
+88d86c28191751101ad464e5e7c1d3ad164ba5f4fb50462da26ebcb568b0a48a
+";
+    let mut diff = Diff::new(parser(&old[..]), parser(&new[..])).unwrap();
+    let changes = diff.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert!(matches!(changes[0], Change::Removed(Entry::File { .. })));
+    assert_eq!(changes[1],
+        Change::Added(Entry::Dir(PathBuf::from("/sub"), None)));
+    assert_eq!(changes.len(), 2);
+}
+
+#[test]
+fn test_diff_different_block_sizes() {
+    let old = b"DIRSIGNATURE.v1 sha512/256 block_size=32768\n";
+    let new = b"DIRSIGNATURE.v1 sha512/256 block_size=65536\n";
+    assert!(matches!(Diff::new(parser(&old[..]), parser(&new[..])),
+        Err(MergeError::BlockSizesMismatch(_))));
+}
+
+#[test]
+fn test_diff_file_hash() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    let mut plain = Vec::new();
+    v1::scan(&cfg, &mut plain).unwrap();
+    cfg.file_hash();
+    let mut with_hash = Vec::new();
+    v1::scan(&cfg, &mut with_hash).unwrap();
+
+    let mut diff = Diff::new(parser(&plain), parser(&with_hash)).unwrap();
+    assert_eq!(diff.iter().count(), 0);
+
+    // blocks aren't compared when whole-file hashes match
+    let changed = String::from_utf8(with_hash.clone()).unwrap()
+        .replacen(" 768007e0", " 068007e0", 1);
+    let mut new = parser(changed.as_bytes());
+    new.skip_checksum();
+    let mut diff = Diff::new(parser(&with_hash), new).unwrap();
+    assert_eq!(diff.iter().count(), 0);
+
+    let changed = String::from_utf8(with_hash.clone()).unwrap()
+        .replace(" 9b844a86", " 0b844a86");
+    let mut new = parser(changed.as_bytes());
+    new.skip_checksum();
+    let mut diff = Diff::new(parser(&with_hash), new).unwrap();
+    let changes = diff.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].path(), Path::new("/subdir/bigdata.bin"));
+    // only the whole-file hash differs
+    assert!(matches!(changes[0],
+        Change::Modified { ref blocks, .. } if blocks.is_empty()));
+}
+
+#[test]
+fn test_diff_dir_hash() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    let mut plain = Vec::new();
+    v1::scan(&cfg, &mut plain).unwrap();
+    cfg.dir_hash();
+    let mut with_hash = Vec::new();
+    v1::scan(&cfg, &mut with_hash).unwrap();
+
+    let mut diff = Diff::new(parser(&plain), parser(&with_hash)).unwrap();
+    assert_eq!(diff.iter().count(), 0);
+
+    // subtree with equal hashes isn't even parsed, so garbage is not found
+    let changed = String::from_utf8(with_hash.clone()).unwrap()
+        .replace("  file3.txt f 12", "  file3.txt x 12");
+    let mut new = parser(changed.as_bytes());
+    new.skip_checksum();
+    let mut diff = Diff::new(parser(&with_hash), new).unwrap();
+    assert_eq!(diff.iter().count(), 0);
+
+    let mut new = parser(changed.as_bytes());
+    new.skip_checksum();
+    let mut diff = Diff::new(parser(&plain), new).unwrap();
+    let changes = diff.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].path(), Path::new("/subdir/file3.txt"));
+}
+
+#[test]
+fn test_diff_xattrs() {
+    let old = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 xattrs=1
+/ -
+  hello.txt f user.a=1 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  ping x - 0
+/sub -
+  link s - ../hello.txt
+24349874142b236c77f8d92184145826994e2dc92e90545aaf736bc9edaa26d7
+";
+    let new = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 xattrs=1
+/ -
+  hello.txt f user.a=2 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  ping x security.capability=\\x01 0
+/sub user.b=1
+  link s - ../hello.txt
+a1cf34934cbbfb77ef07b102e3b5f77766a5783a8e46af84bcfe55f1edec665c
+";
+    let plain = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  ping x 0
+/sub
+  link s ../hello.txt
+d03003203f81e6f6e07500df592c1114cbf98b244449574934c404e496c21f7f
+";
+    let mut diff = Diff::new(parser(old), parser(new)).unwrap();
+    let changes = diff.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    let paths = changes.iter().map(|c| c.path()).collect::<Vec<_>>();
+    assert_eq!(paths, vec![
+        Path::new("/hello.txt"),
+        Path::new("/ping"),
+        Path::new("/sub"),
+    ]);
+    assert!(changes.iter().all(|c| matches!(*c,
+        Change::Modified { ref blocks, .. } if blocks.is_empty())));
+
+    // attributes are only compared when both indexes have them
+    let mut diff = Diff::new(parser(plain), parser(new)).unwrap();
+    assert_eq!(diff.iter().count(), 0);
+}
@@ -0,0 +1,127 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+#[macro_use] extern crate matches;
+extern crate difference;
+
+extern crate dir_signature;
+use difference::assert_diff;
+use dir_signature::{ScannerConfig, Chunking, v1};
+use dir_signature::v1::Parser;
+use dir_signature::v1::json::{export, import, JsonError};
+
+fn scan(cfg: &ScannerConfig) -> Vec<u8> {
+    let mut buf = Vec::new();
+    v1::scan(cfg, &mut buf).unwrap();
+    buf
+}
+
+fn to_json(index: &[u8]) -> String {
+    let mut parser = Parser::new(BufReader::new(Cursor::new(index))).unwrap();
+    let mut buf = Vec::new();
+    export(&mut parser, &mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+fn from_json(json: &str) -> Result<Vec<u8>, JsonError> {
+    let mut buf = Vec::new();
+    import(Cursor::new(json.as_bytes()), &mut buf).map(|()| buf)
+}
+
+fn check_round_trip(cfg: &ScannerConfig) {
+    let index = scan(cfg);
+    let json = to_json(&index);
+    assert_diff(&String::from_utf8_lossy(&from_json(&json).unwrap()),
+                &String::from_utf8_lossy(&index), "\n", 0);
+}
+
+#[test]
+fn test_export() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    assert_diff(&to_json(&scan(&cfg)), r#"{"block_size":32768,"chunking":"fixed","dir_hash":false,"extra":{},"file_hash":false,"hash":"sha512/256","type":"header","version":"v1","xattrs":false}
+{"path":"/","type":"dir"}
+{"exe":false,"hashes":["8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc"],"path":"/hello.txt","size":6,"type":"file"}
+{"exe":false,"hashes":[],"path":"/test.txt","size":0,"type":"file"}
+{"path":"/subdir","type":"dir"}
+{"exe":false,"hashes":["24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819"],"path":"/subdir/.hidden","size":7,"type":"file"}
+{"exe":false,"hashes":["9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9"],"path":"/subdir/file.txt","size":10,"type":"file"}
+{"hash":"8459d4b1c12835153c2aa695cfd33395ab3793e1e1e7d015dbdc466d56146d92","type":"footer"}
+"#, "\n", 0);
+}
+
+#[test]
+fn test_round_trip() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    check_round_trip(&cfg);
+    cfg.add_dir("tests/dir2", "/dir2");
+    cfg.header_key("origin", "test");
+    check_round_trip(&cfg);
+    cfg.file_hash();
+    cfg.dir_hash();
+    check_round_trip(&cfg);
+    cfg.chunking(Chunking::FastCdc);
+    cfg.block_size(4096).unwrap();
+    check_round_trip(&cfg);
+}
+
+#[test]
+fn test_non_utf8() {
+    let dir = Path::new("target/test-json/non-utf8");
+    fs::remove_dir_all(dir).ok();
+    fs::create_dir_all(dir).unwrap();
+    let name = OsStr::from_bytes(b"caf\xe9 menu.txt");
+    File::create(dir.join(name)).unwrap().write_all(b"hello").unwrap();
+    symlink(name, dir.join("link")).unwrap();
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    let index = scan(&cfg);
+    let json = to_json(&index);
+    assert!(json.contains(r#""path":"/caf\\xe9\\x20menu.txt""#),
+        "Was: {}", json);
+    assert!(json.contains(r#""target":"caf\\xe9\\x20menu.txt""#),
+        "Was: {}", json);
+    assert_eq!(from_json(&json).unwrap(), index);
+}
+
+#[test]
+fn test_xattrs() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.xattrs();
+    check_round_trip(&cfg);
+}
+
+#[test]
+fn test_errors() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    let json = to_json(&scan(&cfg));
+
+    let changed = json.replace(r#""size":6"#, r#""size":7"#);
+    let mut out = Vec::new();
+    assert!(matches!(import(Cursor::new(changed.as_bytes()), &mut out),
+        Err(JsonError::ChecksumMismatch)));
+    // the footer is only written when it matches
+    assert!(!out.is_empty());
+    let mut parser = Parser::new(BufReader::new(Cursor::new(&out))).unwrap();
+    assert!(parser.iter().collect::<Result<Vec<_>, _>>().is_err());
+
+    let lines = json.lines().collect::<Vec<_>>();
+    let truncated = lines[..lines.len()-1].join("\n");
+    assert!(matches!(from_json(&truncated), Err(JsonError::MissingFooter)));
+
+    let broken = json.replace(r#""type":"file""#, r#""type":"fifo""#);
+    let result = from_json(&broken);
+    assert!(matches!(result, Err(JsonError::InvalidEntry(ref msg, 3))
+        if msg == "Unknown type \"fifo\""), "Was: {:?}", result);
+
+    let result = from_json("{\"type\":\"header\"\n");
+    assert!(matches!(result, Err(JsonError::Json(_, 1))),
+        "Was: {:?}", result);
+}
@@ -0,0 +1,155 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+#[macro_use] extern crate matches;
+extern crate difference;
+
+extern crate dir_signature;
+use difference::assert_diff;
+use dir_signature::{ScannerConfig, Compression, Error, v1, v2};
+use dir_signature::v1::{Entry, Parser};
+use dir_signature::v1::lookup::{Lookup, LookupError};
+
+const DIR2_LOOKUP: &'static str = "\
+DIRSIGNATURE-LOOKUP.v1 f506b32f8769870afcfcc0c0880c36a8ef7c9c29959f4bd08f5b67cb720d4c81
+/ 44
+/sub2 128
+/subdir 215
+";
+
+fn scan(cfg: &mut ScannerConfig, name: &str) -> (Vec<u8>, Vec<u8>) {
+    let dir = Path::new("target/test-lookup");
+    fs::create_dir_all(dir).unwrap();
+    let lookup_path = dir.join(name);
+    cfg.lookup_file(&lookup_path);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    let mut lookup = Vec::new();
+    File::open(&lookup_path).unwrap().read_to_end(&mut lookup).unwrap();
+    (buf, lookup)
+}
+
+fn parser(data: &[u8]) -> Parser<BufReader<Cursor<&[u8]>>> {
+    Parser::new(BufReader::new(Cursor::new(data))).unwrap()
+}
+
+fn read_lookup(data: &[u8]) -> Lookup {
+    Lookup::read(Cursor::new(data)).unwrap()
+}
+
+#[test]
+fn test_scan() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    let (index, lookup) = scan(&mut cfg, "dir2.lookup");
+    assert_diff(&String::from_utf8_lossy(&lookup), DIR2_LOOKUP, "\n", 0);
+    assert_eq!(parser(&index).build_lookup().unwrap(),
+               read_lookup(&lookup));
+}
+
+#[test]
+fn test_scan_dir_hash() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.dir_hash();
+    let (index, lookup) = scan(&mut cfg, "dir2-dir-hash.lookup");
+    let lookup = read_lookup(&lookup);
+    assert_eq!(parser(&index).build_lookup().unwrap(), lookup);
+    let mut parser = parser(&index);
+    parser.set_lookup(lookup).unwrap();
+    assert!(matches!(parser.lookup("/subdir/file3.txt").unwrap(),
+        Some(Entry::File { ref path, size: 12, .. })
+        if path == Path::new("/subdir/file3.txt")));
+}
+
+#[test]
+fn test_lookup() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    let (index, lookup) = scan(&mut cfg, "dir2-find.lookup");
+    let mut parser = parser(&index);
+    parser.set_lookup(read_lookup(&lookup)).unwrap();
+    assert!(matches!(parser.lookup("/sub2/hello.txt").unwrap(),
+        Some(Entry::File { ref path, size: 6, .. })
+        if path == Path::new("/sub2/hello.txt")));
+    assert!(matches!(parser.lookup("/subdir").unwrap(),
+        Some(Entry::Dir(ref path, _)) if path == Path::new("/subdir")));
+    assert!(matches!(parser.lookup("/file2.txt").unwrap(),
+        Some(Entry::File { size: 18, .. })));
+    assert!(matches!(parser.lookup("/").unwrap(),
+        Some(Entry::Dir(ref path, _)) if path == Path::new("/")));
+    assert!(parser.lookup("/sub2/missing.txt").unwrap().is_none());
+    assert!(parser.lookup("/missing/hello.txt").unwrap().is_none());
+    assert!(parser.lookup("/subdir/file3.txt/x").unwrap().is_none());
+}
+
+#[test]
+fn test_iter_from() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    let (index, lookup) = scan(&mut cfg, "dir2-iter.lookup");
+    let mut parser = parser(&index);
+    parser.set_lookup(read_lookup(&lookup)).unwrap();
+    let paths = parser.iter_from("/sub2").unwrap().unwrap()
+        .map(|e| match e.unwrap() {
+            Entry::Dir(path, _) => path,
+            Entry::File { path, .. } => path,
+            Entry::Link(path, _, _) => path,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(paths, vec![
+        PathBuf::from("/sub2"),
+        PathBuf::from("/sub2/hello.txt"),
+        PathBuf::from("/subdir"),
+        PathBuf::from("/subdir/bigdata.bin"),
+        PathBuf::from("/subdir/file3.txt"),
+    ]);
+    assert!(parser.iter_from("/sub2/hello.txt").unwrap().is_none());
+}
+
+#[test]
+fn test_errors() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    let (index1, _) = scan(&mut cfg, "dir1.lookup");
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    let (index2, lookup2) = scan(&mut cfg, "dir2-errors.lookup");
+
+    let mut parser1 = parser(&index1);
+    assert!(matches!(parser1.lookup("/"), Err(LookupError::NoLookup)));
+    assert!(matches!(parser1.set_lookup(read_lookup(&lookup2)),
+        Err(LookupError::HashMismatch)));
+
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.compression(Compression::Gzip);
+    let mut gzip = Vec::new();
+    v1::scan(&cfg, &mut gzip).unwrap();
+    assert!(matches!(parser(&gzip).set_lookup(read_lookup(&lookup2)),
+        Err(LookupError::Compressed)));
+
+    // lookup can be set after some entries are read
+    let mut parser2 = parser(&index2);
+    assert!(parser2.iter().next().is_some());
+    parser2.set_lookup(read_lookup(&lookup2)).unwrap();
+    assert!(matches!(parser2.lookup("/sub2/hello.txt").unwrap(),
+        Some(Entry::File { size: 6, .. })));
+
+    let bad = b"DIRSIGNATURE-LOOKUP.v1 f506\n/sub2 x\n";
+    assert!(Lookup::read(Cursor::new(&bad[..])).is_err());
+}
+
+#[test]
+fn test_lookup_unsupported() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.lookup_file("target/test-lookup/unsupported");
+    cfg.compression(Compression::Zstd);
+    assert!(matches!(v1::scan(&cfg, &mut Vec::new()),
+        Err(Error::BadLookup(_))));
+    cfg.compression(Compression::None);
+    assert!(matches!(v2::scan(&cfg, &mut Vec::new()),
+        Err(Error::BadLookup(_))));
+}
@@ -4,7 +4,7 @@ use std::path::{Path, PathBuf};
 #[macro_use] extern crate matches;
 
 extern crate dir_signature;
-use dir_signature::HashType;
+use dir_signature::{HashType, ScannerConfig, v1};
 use dir_signature::v1::{Entry, EntryKind, Parser};
 use dir_signature::v1::merge::{MergeError, MergedSignatures};
 
@@ -18,7 +18,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /subdir
   .hidden f 28394 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
   link s ../hello.txt
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+ecce53be62b5800b7f9bcf9e606541a62ddbd287c2bc4111707dad9f20b47a73
 ";
     let content2 = b"\
 DIRSIGNATURE.v1 sha512/256 block_size=32768
@@ -28,7 +28,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /subdir
   .hidden f 28394 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
   link2 s ../hello.txt
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+c9a5e8448b142bd44242d1c951b19de70cf91f873b0d24a23321f874e1f1005f
 ";
     let parsers = vec!(
         (
@@ -105,6 +105,28 @@ fn test_merge_different_block_sizes() {
             if sizes == &vec!(32768, 65536)));
 }
 
+#[test]
+fn test_merge_scanned_with_different_block_sizes() {
+    let mut indexes = vec!();
+    for &block_size in &[4096, 65536] {
+        let mut cfg = ScannerConfig::new();
+        cfg.add_dir("tests/dir1", "/");
+        cfg.block_size(block_size).unwrap();
+        let mut buf = Vec::new();
+        v1::scan(&cfg, &mut buf).unwrap();
+        indexes.push(buf);
+    }
+    let parsers = indexes.iter().map(|idx| {
+        (Path::new("tests/dir1"),
+         Parser::new(BufReader::new(Cursor::new(&idx[..]))).unwrap())
+    });
+
+    let merger = MergedSignatures::new(parsers);
+    assert!(matches!(merger,
+            Err(MergeError::BlockSizesMismatch(ref sizes))
+            if sizes == &vec!(4096, 65536)));
+}
+
 #[test]
 fn test_merge_iter() {
     let content1 = b"\
@@ -114,7 +136,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
   .hidden f 28394 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
 /b
   empty.txt f 0
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+65e191be92c00eaf5b1f7380930ad7d67ff46449938aff39e41f48cd7eabbf1e
 ";
     let content2 = b"\
 DIRSIGNATURE.v1 sha512/256 block_size=32768
@@ -125,7 +147,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
   .hidden f 28394 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
 /c
   empty.txt f 0
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+6d36605453db3720d791462a22699a476fbb767b54d63781ff624dd7947b6e89
 ";
     let parsers = vec!(
         (
@@ -144,12 +166,12 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     let entries = merged_iter.next().unwrap();
     assert_eq!(entries.len(), 2);
     let ref entry = entries[0];
-    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path)))
+    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path, _)))
                      if base_path == Path::new("/111") &&
                      path == Path::new("/")),
             "Was: {:?}", entry);
     let ref entry = entries[1];
-    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path)))
+    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path, _)))
                      if base_path == Path::new("/222") &&
                      path == Path::new("/")),
             "Was: {:?}", entry);
@@ -173,12 +195,12 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     let entries = merged_iter.next().unwrap();
     assert_eq!(entries.len(), 2);
     let ref entry = entries[0];
-    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path)))
+    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path, _)))
                      if base_path == Path::new("/111") &&
                      path == Path::new("/a")),
             "Was: {:?}", entry);
     let ref entry = entries[1];
-    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path)))
+    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path, _)))
                      if base_path == Path::new("/222") &&
                      path == Path::new("/a")),
             "Was: {:?}", entry);
@@ -199,7 +221,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     let entries = merged_iter.next().unwrap();
     assert_eq!(entries.len(), 1);
     let ref entry = entries[0];
-    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path)))
+    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path, _)))
                      if base_path == Path::new("/111") &&
                      path == Path::new("/b")),
             "Was: {:?}", entry);
@@ -208,7 +230,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     let entries = merged_iter.advance(&EntryKind::Dir("/c"));
     assert_eq!(entries.len(), 1);
     let ref entry = entries[0];
-    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path)))
+    assert!(matches!(entry, &(base_path, Ok(Entry::Dir(ref path, _)))
                      if base_path == Path::new("/222") &&
                      path == Path::new("/c")),
             "Was: {:?}", entry);
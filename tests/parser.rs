@@ -1,5 +1,6 @@
+use std::borrow::Cow;
 use std::io::{BufReader, Cursor, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 extern crate rustc_serialize;
 use rustc_serialize::hex::FromHex;
@@ -7,8 +8,9 @@ use rustc_serialize::hex::FromHex;
 #[macro_use] extern crate matches;
 
 extern crate dir_signature;
-use dir_signature::HashType;
+use dir_signature::{HashType, get_hash};
 use dir_signature::v1::{Entry, EntryKind, Parser, ParseError, ParseRowError};
+use dir_signature::v1::EntryRef;
 
 #[test]
 fn test_parser() {
@@ -20,7 +22,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /subdir
   .hidden f 58394 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
   just\\x20link s ../hello.txt
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+0d77299eff4d4f530bd615f255db281718ed9636ba4616b22865972d6f58d275
 ";
     let reader = BufReader::new(Cursor::new(&content[..]));
     let mut signature_parser = Parser::new(reader).unwrap();
@@ -34,7 +36,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
 
     let entry = entry_iter.next().unwrap().unwrap();
     match entry {
-        Entry::Dir(dir) => {
+        Entry::Dir(dir, _) => {
             assert_eq!(dir, Path::new("/"));
         },
         _ => {
@@ -44,7 +46,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
 
     let entry = entry_iter.next().unwrap().unwrap();
     match entry {
-        Entry::File {path, exe, size, hashes} => {
+        Entry::File {path, exe, size, hashes, ..} => {
             assert_eq!(path, Path::new("/empty.txt"));
             assert_eq!(exe, false);
             assert_eq!(size, 0);
@@ -57,7 +59,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
 
     let entry = entry_iter.next().unwrap().unwrap();
     match entry {
-        Entry::File {path, exe, size, hashes} => {
+        Entry::File {path, exe, size, hashes, ..} => {
             let mut hashes_iter = hashes.iter();
             assert_eq!(path, Path::new("/hello.txt"));
             assert_eq!(exe, false);
@@ -76,7 +78,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     let _ = entry_iter.next().unwrap().unwrap();
     let entry = entry_iter.next().unwrap().unwrap();
     match entry {
-        Entry::Link(path, dest) => {
+        Entry::Link(path, dest, _) => {
             assert_eq!(path, Path::new("/subdir/just link"));
             assert_eq!(dest, Path::new("../hello.txt"));
         },
@@ -91,6 +93,89 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     assert!(matches!(entry, None), "Was: {:?}", entry);
 }
 
+#[test]
+fn test_parser_next_ref() {
+    let content = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  empty.txt f 0
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+/subdir
+  .hidden f 58394 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+  just\\x20link s ../hello.txt
+0d77299eff4d4f530bd615f255db281718ed9636ba4616b22865972d6f58d275
+";
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    let mut iter = parser.iter();
+
+    assert!(matches!(iter.next_ref(), Some(Ok(EntryRef::Dir(ref d, None)))
+        if d == Path::new("/")));
+    assert!(matches!(iter.next_ref(),
+        Some(Ok(EntryRef::File { ref name, size: 0, ref hashes, .. }))
+        if name == Path::new("empty.txt") && hashes.len() == 0));
+    match iter.next_ref().unwrap().unwrap() {
+        EntryRef::File { dir, name, exe, size, hashes, file_hash, .. } => {
+            assert!(matches!(name, Cow::Borrowed(_)));
+            assert_eq!(dir.join(&name), Path::new("/hello.txt"));
+            assert_eq!(exe, false);
+            assert_eq!(size, 6);
+            assert_eq!(file_hash, None);
+            assert_eq!(hashes.iter().collect::<Vec<_>>(), vec![
+                &b"8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc"[..]]);
+        }
+        entry => panic!("Expected file, found {:?}", entry),
+    }
+    // both kinds of entries can be mixed
+    assert!(matches!(iter.next(), Some(Ok(Entry::Dir(ref d, _)))
+        if d == Path::new("/subdir")));
+    match iter.next_ref().unwrap().unwrap() {
+        entry @ EntryRef::File { .. } => {
+            assert_eq!(entry.path(), Path::new("/subdir/.hidden"));
+            let entry = entry.into_owned();
+            assert!(matches!(entry, Entry::File { ref hashes, .. }
+                if hashes.len() == 2));
+        }
+        entry => panic!("Expected file, found {:?}", entry),
+    }
+    assert!(matches!(iter.next_ref(),
+        Some(Ok(EntryRef::Link { ref name, ref dest, .. }))
+        if name == Path::new("just link") &&
+           dest == Path::new("../hello.txt")));
+    assert!(iter.next_ref().is_none());
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_parser_next_ref_skip_dir() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 dir_hash=1
+/ 00a158475a4f6d8639195bdb3e4820b2c86a85b3c8af19aaf4415c72efc57f34
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/sub2 c11c7a4d82689e1cc45380021435f7a8f37557737599b0675d2d84d2c3c8a732
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir e7ad29f77475b09fd59cb106dfa474fd756f7d17c9fe6fde47f329d4e0841959
+  bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+e84fa307e89c34268350758af7befbd8b62c47766e91b599da39c7d437152d06
+";
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    let mut iter = parser.iter();
+    assert!(matches!(iter.next_ref(), Some(Ok(EntryRef::Dir(..)))));
+    assert!(iter.dir_hash().is_some());
+    assert!(matches!(iter.next_ref(), Some(Ok(EntryRef::File { .. }))));
+    assert!(matches!(iter.next_ref(), Some(Ok(EntryRef::Dir(ref d, None)))
+        if d == Path::new("/sub2")));
+    iter.skip_dir().unwrap();
+    let entry = iter.advance(&EntryKind::File(
+        Path::new("/subdir/file3.txt")));
+    assert!(matches!(entry, Some(Ok(Entry::File { size: 12, .. }))),
+        "Entry result was: {:?}", entry);
+    // checksum is still verified
+    assert!(iter.next_ref().is_none());
+}
+
 #[test]
 fn test_parser_advance_file() {
     let content = b"\
@@ -104,7 +189,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /subdir
   .hidden f 28394 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
   just\\x20link s ../hello.txt
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+c3614581dd267945ea17341eb0ca7077b0cad63c19705be98d1d0109f3e389c2
 ";
     let reader = BufReader::new(Cursor::new(&content[..]));
     let mut signature_parser = Parser::new(reader).unwrap();
@@ -121,7 +206,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     assert!(matches!(entry, None), "Entry was: {:?}", entry);
     let entry = entry_iter.advance(&EntryKind::Dir("/empty"));
     assert!(matches!(entry,
-            Some(Ok(Entry::Dir(ref path))) if path == Path::new("/empty")),
+            Some(Ok(Entry::Dir(ref path, _))) if path == Path::new("/empty")),
         "Entry was: {:?}", entry);
     let entry = entry_iter.advance(&EntryKind::File("/subdir/.hidden"));
     assert!(matches!(entry,
@@ -132,7 +217,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
     assert!(matches!(entry, None), "Entry was: {:?}", entry);
     let entry = entry_iter.advance(&EntryKind::File("/subdir/just link"));
     assert!(matches!(entry,
-            Some(Ok(Entry::Link(ref path, ref dest)))
+            Some(Ok(Entry::Link(ref path, ref dest, _)))
             if path == Path::new("/subdir/just link") &&
                 dest == Path::new("../hello.txt")),
         "Entry was: {:?}", entry);
@@ -149,7 +234,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
   zzz f 0
 /etc/z
   a f 0
-0000000000000000000000000000000000000000000000000000000000000000
+c904f32c7c1a8f7eae69698a1b186e8c9dd55d177b0189e5af1c92e0e53c5e83
 ";
     let reader = BufReader::new(Cursor::new(&content[..]));
     let mut signature_parser = Parser::new(reader).unwrap();
@@ -172,7 +257,7 @@ fn test_parser_advance_current_path_is_less_than_wanted_file_dir() {
 /etc/z
   a f 0
   b f 0
-0000000000000000000000000000000000000000000000000000000000000000
+b1490151e539b9613d14ead122de86ffedb231e9d8df0476a053c43d59c72bac
 ";
     let reader = BufReader::new(Cursor::new(&content[..]));
     let mut signature_parser = Parser::new(reader).unwrap();
@@ -207,7 +292,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /usr/share
   test f 0
 /var
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+fce61ccea99f9f22093d8770a8d27aa34ac809c6aa0ebed3f9cdb9a8a858d92b
 ";
     let reader = BufReader::new(Cursor::new(&content[..]));
     let mut signature_parser = Parser::new(reader).unwrap();
@@ -215,7 +300,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
 
     let entry = entry_iter.advance(&EntryKind::Dir(Path::new("/etc")));
     assert!(matches!(entry,
-            Some(Ok(Entry::Dir(ref path)))
+            Some(Ok(Entry::Dir(ref path, _)))
             if path == Path::new("/etc")),
         "Entry was: {:?}", entry);
     let entry = entry_iter.advance(&EntryKind::Dir(Path::new("/etc")));
@@ -227,7 +312,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
         "Entry was: {:?}", entry);
     let entry = entry_iter.advance(&EntryKind::Dir(Path::new("/usr/share")));
     assert!(matches!(entry,
-            Some(Ok(Entry::Dir(ref path)))
+            Some(Ok(Entry::Dir(ref path, _)))
             if path == Path::new("/usr/share")),
         "Entry was: {:?}", entry);
     let entry = entry_iter.advance(&EntryKind::Dir(Path::new("/usr/bin")));
@@ -239,7 +324,7 @@ c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
         "Entry was: {:?}", entry);
     let entry = entry_iter.advance(&EntryKind::Dir(Path::new("/var")));
     assert!(matches!(entry,
-            Some(Ok(Entry::Dir(ref path)))
+            Some(Ok(Entry::Dir(ref path, _)))
             if path == Path::new("/var")),
         "Entry was: {:?}", entry);
 
@@ -285,7 +370,7 @@ fn test_parser_reset() {
     let content = "\
 DIRSIGNATURE.v1 sha512/256 block_size=32768
 /
-8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+bd7736c631d9e5622a8a3d754d4f3d1e1ddcf418a48afc8a0a6ab2b50ea7f872
 ";
     let reader = BufReader::new(Cursor::new(&content[..]));
     let mut parser = Parser::new(reader).unwrap();
@@ -294,7 +379,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
         let mut entry_iter = parser.iter();
         let entry = entry_iter.next();
         assert!(matches!(entry,
-                Some(Ok(Entry::Dir(ref path))) if path == Path::new("/")),
+                Some(Ok(Entry::Dir(ref path, _))) if path == Path::new("/")),
             "Entry result was: {:?}", entry);
         let entry = entry_iter.next();
         assert!(matches!(entry, None), "Entry result was: {:?}", entry);
@@ -307,6 +392,378 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
     let mut entry_iter = parser.iter();
     let entry = entry_iter.next();
     assert!(matches!(entry,
-            Some(Ok(Entry::Dir(ref path))) if path == Path::new("/")),
+            Some(Ok(Entry::Dir(ref path, _))) if path == Path::new("/")),
         "Entry result was: {:?}", entry);
 }
+
+#[test]
+fn test_parser_checksum_mismatch() {
+    // hash of the hello.txt is changed
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  hello.txt f 6 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  test.txt f 0
+8459d4b1c12835153c2aa695cfd33395ab3793e1e1e7d015dbdc466d56146d92
+";
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    let entries = parser.iter().collect::<Vec<_>>();
+    assert_eq!(entries.len(), 4);
+    assert!(matches!(entries[3],
+            Err(ParseError::Parse(ParseRowError::ChecksumMismatch, 5))),
+        "Entry result was: {:?}", entries[3]);
+
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    parser.skip_checksum();
+    let entries = parser.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len(), 3);
+}
+
+#[test]
+fn test_parser_truncated() {
+    // the /subdir directory is cut out
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+8459d4b1c12835153c2aa695cfd33395ab3793e1e1e7d015dbdc466d56146d92
+";
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    let entry = parser.iter().advance(&EntryKind::File(
+        Path::new("/subdir/file.txt")));
+    assert!(matches!(entry,
+            Some(Err(ParseError::Parse(ParseRowError::ChecksumMismatch, _)))),
+        "Entry result was: {:?}", entry);
+}
+
+#[test]
+fn test_parser_header_extra_and_footer() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 build_id=42 origin=ci
+/
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+/subdir
+  .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+5385922324a39b384838edc850eaa8dc62b7177bfa2fad4ebeaea9f2c49bb2d8
+";
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    let header = parser.get_header();
+    assert_eq!(header.get_block_size(), 32768);
+    assert_eq!(header.get_extra().iter()
+               .map(|(k, v)| (&k[..], &v[..]))
+               .collect::<Vec<_>>(),
+               vec![("build_id", "42"), ("origin", "ci")]);
+    assert!(parser.get_footer().is_none());
+    assert_eq!(parser.iter().count(), 6);
+    assert_eq!(parser.get_footer().unwrap().get_hash(),
+        &"5385922324a39b384838edc850eaa8dc62b7177bfa2fad4ebeaea9f2c49bb2d8"
+        .from_hex().unwrap()[..]);
+    // same entries without the pairs in the header have another footer
+    assert_ne!(parser.get_footer().unwrap().get_hash(),
+        &"8459d4b1c12835153c2aa695cfd33395ab3793e1e1e7d015dbdc466d56146d92"
+        .from_hex().unwrap()[..]);
+}
+
+#[test]
+fn test_parser_invalid_header_extra() {
+    let headers = [
+        ("DIRSIGNATURE.v1 sha512/256 block_size=32768 build_id\n", None),
+        ("DIRSIGNATURE.v1 sha512/256 block_size=32768 a=1 a=2\n", Some("1")),
+        ("DIRSIGNATURE.v1 sha512/256 block_size=32768 =1 a=1\n", Some("1")),
+    ];
+    for &(header, value) in &headers {
+        let reader = BufReader::new(Cursor::new(&header[..]));
+        let parser = Parser::new(reader).unwrap();
+        let header = parser.get_header();
+        assert_eq!(header.get_extra().get("a").map(|v| &v[..]), value);
+        assert_eq!(header.get_extra().len(), value.iter().count());
+    }
+}
+
+#[test]
+fn test_parser_invalid_fastcdc_block_size() {
+    let headers = [
+        "DIRSIGNATURE.v1 sha512/256 block_size=100 chunking=fastcdc\n",
+        "DIRSIGNATURE.v1 sha512/256 block_size=32 chunking=fastcdc\n",
+    ];
+    for header in &headers {
+        let reader = BufReader::new(Cursor::new(&header[..]));
+        let res = Parser::new(reader);
+        assert!(matches!(res,
+                Err(ParseError::Parse(ParseRowError::InvalidBlockSize(_), 1))),
+            "Header {:?}", header);
+    }
+}
+
+#[test]
+fn test_parser_dir_hash() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 dir_hash=1
+/ 00a158475a4f6d8639195bdb3e4820b2c86a85b3c8af19aaf4415c72efc57f34
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/sub2 c11c7a4d82689e1cc45380021435f7a8f37557737599b0675d2d84d2c3c8a732
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir e7ad29f77475b09fd59cb106dfa474fd756f7d17c9fe6fde47f329d4e0841959
+  bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+e84fa307e89c34268350758af7befbd8b62c47766e91b599da39c7d437152d06
+";
+    let root_hash = "00a158475a4f6d8639195bdb3e4820b2c86a85b3c8af19aaf4415c72efc57f34"
+        .from_hex().unwrap();
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    assert!(parser.get_header().has_dir_hash());
+    let mut iter = parser.iter();
+    assert!(matches!(iter.next(), Some(Ok(Entry::Dir(ref d, _)))
+        if d == Path::new("/")));
+    assert_eq!(iter.dir_hash().unwrap(), &root_hash[..]);
+    assert!(matches!(iter.next(), Some(Ok(Entry::File { .. }))));
+    assert_eq!(iter.dir_hash(), None);
+    assert!(matches!(iter.next(), Some(Ok(Entry::Dir(ref d, _)))
+        if d == Path::new("/sub2")));
+    iter.skip_dir().unwrap();
+    assert!(matches!(iter.next(), Some(Ok(Entry::Dir(ref d, _)))
+        if d == Path::new("/subdir")));
+    iter.skip_dir().unwrap();
+    // checksum is still verified for the skipped lines
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_parser_file_hash() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 file_hash=1
+/
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+6c943004d223865792950b31fe56313c7c61a24fb105d6f39ba8cccdcd70097b
+";
+    let mut parser = Parser::new(BufReader::new(Cursor::new(&content[..])))
+        .unwrap();
+    let hash = "8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc"
+        .from_hex().unwrap();
+    let mut iter = parser.iter();
+    assert!(matches!(iter.next(), Some(Ok(Entry::Dir(..)))));
+    let entry = iter.next();
+    assert!(matches!(entry,
+        Some(Ok(Entry::File { size: 6, ref file_hash, .. }))
+        if file_hash.as_ref() == Some(&hash)),
+        "Entry was: {:?}", entry);
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_parser_xattrs() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 dir_hash=1 xattrs=1
+/ user.comment=root\\x20dir 3e38ec820ca094cda591252ee1eb8dd0b4b446156a854c623c9d14e9e915eb24
+  link s - ping
+  ping x security.capability=\\x01\\x00\\x00\\x02\\x00\\x20,user.empty= 0
+/sub - cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce
+3eba7023dc475bc6cbe3d31f1620e8538c7cfe07cbf3c21226ddb794cdfb0f7d
+";
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    assert!(parser.get_header().has_dir_hash());
+    assert!(parser.get_header().has_xattrs());
+    let mut iter = parser.iter();
+    let root = iter.next().unwrap().unwrap();
+    assert!(matches!(root, Entry::Dir(..)));
+    {
+        let root = root.xattrs().unwrap();
+        assert_eq!(root.len(), 1);
+        assert_eq!(root.get("user.comment"), Some(&b"root dir"[..]));
+    }
+    let link = iter.next().unwrap().unwrap();
+    assert!(matches!(link,
+        Entry::Link(_, ref dest, Some(ref xattrs))
+        if dest == Path::new("ping") && xattrs.is_empty()));
+    let ping = iter.next().unwrap().unwrap();
+    assert!(matches!(ping, Entry::File { .. }));
+    {
+        let ping = ping.xattrs().unwrap();
+        assert_eq!(ping.len(), 2);
+        assert_eq!(ping.get("security.capability"),
+            Some(&b"\x01\x00\x00\x02\x00 "[..]));
+        assert_eq!(ping.get("user.empty"), Some(&b""[..]));
+    }
+    match iter.next_ref() {
+        Some(Ok(ref entry @ EntryRef::Dir(..))) => {
+            assert!(entry.xattrs().unwrap().is_empty());
+        }
+        res => panic!("Unexpected result: {:?}", res),
+    }
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_parser_xattrs_unsorted() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 xattrs=1
+/ user.b=1,user.a=2
+";
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    assert!(matches!(parser.iter().next(),
+        Some(Err(ParseError::Parse(ParseRowError::InvalidXattrs(_), 2)))));
+}
+
+#[test]
+fn test_parser_sha256() {
+    let content = "\
+DIRSIGNATURE.v1 sha256 block_size=32768
+/
+  file2.txt f 18 46d655dc52a164dab259931e480f2b46296d8bb363e9f7aa03cd484269b9ba68
+/sub2
+  hello.txt f 6 e258d248fda94c63753607f7c4494ee0fcbe92f1a76bfdac795c9d84101eb317
+/subdir
+  bigdata.bin f 81920 c35020473aed1b4642cd726cad727b63fff2824ad68cedd7ffb73c7cbd890479 c35020473aed1b4642cd726cad727b63fff2824ad68cedd7ffb73c7cbd890479 4fe7b59af6de3b665b67788cc2f99892ab827efae3a467342b3bb4e3bc8e5bfe
+  file3.txt f 12 c78666ac8ba7cc55521f99db0d85396e72857908d400ddbae0cab210358717d3
+125e5d2e7c58fe546f9034582fd98b22c9968895548b35af5799a0359882d186
+";
+    let footer = "125e5d2e7c58fe546f9034582fd98b22c9968895548b35af5799a0359882d186"
+        .from_hex().unwrap();
+    let reader = BufReader::new(Cursor::new(&content[..]));
+    let mut parser = Parser::new(reader).unwrap();
+    assert_eq!(parser.get_header().get_hash_type(), HashType::Sha256);
+    let entries = parser.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len(), 7);
+    assert_eq!(parser.get_footer().unwrap().get_hash(), &footer[..]);
+    assert_eq!(get_hash(&mut Cursor::new(&content[..])).unwrap(), footer);
+}
+
+fn strict_errors(content: &str) -> Vec<(usize, ParseRowError)> {
+    let reader = BufReader::new(Cursor::new(content.as_bytes()));
+    let mut parser = Parser::new(reader).unwrap();
+    parser.skip_checksum();
+    parser.strict();
+    parser.iter()
+        .filter_map(|e| match e {
+            Ok(_) => None,
+            Err(ParseError::Parse(err, row_num)) => Some((row_num, err)),
+            Err(e) => panic!("Unexpected error: {:?}", e),
+        })
+        .collect()
+}
+
+#[test]
+fn test_parser_strict() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  b\\x20c.txt f 0
+  d s b\\x20c.txt
+/sub
+/sub/x
+  file.txt f 0
+/zzz
+3fcbd8c1d92f106072025cab2a81c5630c59f4ca3b2ef87449fe296227b8913d
+";
+    assert!(strict_errors(content).is_empty());
+}
+
+#[test]
+fn test_parser_strict_unsorted() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  b.txt f 0
+  a.txt f 0
+  c.txt f 0
+/sub
+/abc
+2287085d8561823793357ad0fc75f15cbf0588b008efc8e963848f72f5413c57
+";
+    let errors = strict_errors(content);
+    assert_eq!(errors.len(), 2, "Errors: {:?}", errors);
+    assert!(matches!(errors[0], (4, ParseRowError::Unsorted(ref p))
+        if p == Path::new("/a.txt")));
+    assert!(matches!(errors[1], (7, ParseRowError::Unsorted(ref p))
+        if p == Path::new("/abc")));
+
+    // entries are returned after their errors
+    let reader = BufReader::new(Cursor::new(content.as_bytes()));
+    let mut parser = Parser::new(reader).unwrap();
+    parser.skip_checksum();
+    parser.strict();
+    let paths = parser.iter().filter_map(|e| e.ok())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, vec![
+        PathBuf::from("/"),
+        PathBuf::from("/b.txt"),
+        PathBuf::from("/a.txt"),
+        PathBuf::from("/c.txt"),
+        PathBuf::from("/sub"),
+        PathBuf::from("/abc"),
+    ]);
+
+    // the same file is fine without strict mode
+    let reader = BufReader::new(Cursor::new(content.as_bytes()));
+    let mut parser = Parser::new(reader).unwrap();
+    parser.skip_checksum();
+    assert_eq!(parser.iter().collect::<Result<Vec<_>, _>>().unwrap().len(),
+               6);
+}
+
+#[test]
+fn test_parser_strict_outside_dir() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+  a.txt f 0
+/
+  b.txt f 0
+04f1e516a558ed2c74695b557fd24e095faa85413142130700eb3fd636710e92
+";
+    let errors = strict_errors(content);
+    assert_eq!(errors.len(), 1, "Errors: {:?}", errors);
+    assert!(matches!(errors[0], (2, ParseRowError::OutsideDir(ref p))
+        if p == Path::new("a.txt")));
+    assert!(errors[0].1.is_violation());
+}
+
+#[test]
+fn test_parser_strict_duplicate() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  a f 0
+  a s b
+/a
+/a
+369169bf6f6a101b475d9dda6ca4c511777144e5c22ab72f2b74226996dba5a8
+";
+    let errors = strict_errors(content);
+    assert_eq!(errors.len(), 3, "Errors: {:?}", errors);
+    assert!(matches!(errors[0], (4, ParseRowError::Duplicate(ref p))
+        if p == Path::new("/a")));
+    assert!(matches!(errors[1], (5, ParseRowError::Duplicate(ref p))
+        if p == Path::new("/a")));
+    assert!(matches!(errors[2], (6, ParseRowError::Duplicate(ref p))
+        if p == Path::new("/a")));
+}
+
+#[test]
+fn test_parser_strict_escaping() {
+    let content = "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  \\x61.txt f 0
+  d s a\\x2ftxt
+/sub/../x
+f424d24b9e849246070f5ee94cef6821379fb1a76c38440a7900edc34a44c55e
+";
+    let errors = strict_errors(content);
+    assert_eq!(errors.len(), 3, "Errors: {:?}", errors);
+    assert!(matches!(errors[0], (3, ParseRowError::NonCanonicalName(ref n))
+        if n == "\\x61.txt"));
+    assert!(matches!(errors[1], (4, ParseRowError::NonCanonicalName(ref n))
+        if n == "a\\x2ftxt"));
+    assert!(matches!(errors[2], (5, ParseRowError::NonCanonicalName(_))));
+}
@@ -0,0 +1,150 @@
+use std::io::{BufReader, Cursor};
+
+#[macro_use] extern crate matches;
+extern crate difference;
+
+extern crate dir_signature;
+use difference::assert_diff;
+use dir_signature::{ScannerConfig, Compression, v1, v2};
+use dir_signature::v1::Parser;
+use dir_signature::sign::{SecretKey, PublicKey, Signature, TrustedKeys};
+use dir_signature::sign::SignError;
+
+fn scan(dir: &str, compression: Compression) -> Vec<u8> {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    cfg.compression(compression);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    buf
+}
+
+fn key(seed: u8) -> SecretKey {
+    SecretKey::from_bytes(&[seed; 32]).unwrap()
+}
+
+fn trusted(keys: &[&SecretKey]) -> TrustedKeys {
+    let mut trusted = TrustedKeys::new();
+    for key in keys {
+        trusted.add(key.public_key());
+    }
+    trusted
+}
+
+#[test]
+fn test_sign() {
+    let index = scan("tests/dir1", Compression::None);
+    let signature = key(1).sign(&mut Cursor::new(&index)).unwrap();
+    let mut buf = Vec::new();
+    signature.write(&mut buf).unwrap();
+    assert_diff(&String::from_utf8(buf.clone()).unwrap(), "\
+DIRSIGNATURE-SIGNATURE.v1 ed25519 8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c 20717d2baa8143b89d849d45556a217118ff9bd04d7ad35cb094bee6ada9771cb3b19428c978b2f18773cf4c0fb3783be213d81d9d84af730ef0e945a6fa7803
+", "\n", 0);
+    assert_eq!(Signature::read(&buf[..]).unwrap(), signature);
+    trusted(&[&key(2), &key(1)])
+        .verify(&mut Cursor::new(&index), &signature).unwrap();
+}
+
+#[test]
+fn test_bad_signature() {
+    let index = scan("tests/dir1", Compression::None);
+    let other = scan("tests/dir2", Compression::None);
+    let signature = key(1).sign(&mut Cursor::new(&index)).unwrap();
+    assert!(matches!(
+        trusted(&[&key(1)]).verify(&mut Cursor::new(&other), &signature),
+        Err(SignError::Mismatch)));
+    assert!(matches!(
+        trusted(&[&key(2)]).verify(&mut Cursor::new(&index), &signature),
+        Err(SignError::UntrustedKey(_))));
+    assert!(matches!(
+        TrustedKeys::new().verify(&mut Cursor::new(&index), &signature),
+        Err(SignError::UntrustedKey(_))));
+}
+
+#[test]
+fn test_parser() {
+    for &compression in &[Compression::None, Compression::Gzip] {
+        let index = scan("tests/dir1", compression);
+        let signature = key(1).sign(&mut Cursor::new(&index)).unwrap();
+        let mut parser = Parser::new(BufReader::new(Cursor::new(&index)))
+            .unwrap();
+        parser.check_signature(&signature, &trusted(&[&key(1)])).unwrap();
+        assert_eq!(parser.iter().collect::<Result<Vec<_>, _>>()
+            .unwrap().len(), 6);
+        let mut parser = Parser::new(BufReader::new(Cursor::new(&index)))
+            .unwrap();
+        assert!(matches!(
+            parser.check_signature(&signature, &trusted(&[&key(2)])),
+            Err(SignError::UntrustedKey(_))));
+    }
+}
+
+#[test]
+fn test_tampered_body() {
+    let index = scan("tests/dir1", Compression::None);
+    let signature = key(1).sign(&mut Cursor::new(&index)).unwrap();
+    // the footer is kept, so only the full check detects the change
+    let tampered = String::from_utf8(index).unwrap()
+        .replace("hello.txt", "evil.txt").into_bytes();
+    assert!(matches!(
+        trusted(&[&key(1)]).verify(&mut Cursor::new(&tampered), &signature),
+        Err(SignError::InvalidIndex(_))));
+    assert!(matches!(key(1).sign(&mut Cursor::new(&tampered)),
+        Err(SignError::InvalidIndex(_))));
+
+    let mut parser = Parser::new(BufReader::new(Cursor::new(&tampered)))
+        .unwrap();
+    parser.skip_checksum();
+    assert!(matches!(
+        parser.check_signature(&signature, &trusted(&[&key(1)])),
+        Err(SignError::InvalidIndex(_))));
+
+    // header is covered by the footer too
+    let index = scan("tests/dir1", Compression::None);
+    let tampered = String::from_utf8(index).unwrap()
+        .replace("block_size=32768", "block_size=32768 origin=evil")
+        .into_bytes();
+    assert!(matches!(
+        trusted(&[&key(1)]).verify(&mut Cursor::new(&tampered), &signature),
+        Err(SignError::InvalidIndex(_))));
+}
+
+#[test]
+fn test_v2() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    let mut index = Vec::new();
+    v2::scan(&cfg, &mut index).unwrap();
+    let signature = key(1).sign(&mut Cursor::new(&index)).unwrap();
+    trusted(&[&key(1)]).verify(&mut Cursor::new(&index), &signature)
+        .unwrap();
+    let other = scan("tests/dir1", Compression::None);
+    assert!(matches!(
+        trusted(&[&key(1)]).verify(&mut Cursor::new(&other), &signature),
+        Err(SignError::Mismatch)));
+}
+
+#[test]
+fn test_keys() {
+    let secret = key(3);
+    let mut buf = Vec::new();
+    secret.write(&mut buf).unwrap();
+    assert_eq!(buf.len(), 65);
+    assert_eq!(SecretKey::read(&buf[..]).unwrap().public_key(),
+               secret.public_key());
+
+    let keys = format!("# build server\n{}\n\n{}\n",
+        key(1).public_key(), key(2).public_key());
+    let mut trusted = TrustedKeys::new();
+    trusted.read(keys.as_bytes()).unwrap();
+    let index = scan("tests/dir2", Compression::None);
+    let signature = key(2).sign(&mut Cursor::new(&index)).unwrap();
+    trusted.verify(&mut Cursor::new(&index), &signature).unwrap();
+
+    assert!(matches!("abcd".parse::<PublicKey>(),
+        Err(SignError::InvalidKey(_))));
+    assert!(matches!(Signature::read(&b"DIRSIGNATURE-SIGNATURE.v1 rsa\n"[..]),
+        Err(SignError::InvalidSignature(_))));
+    assert_ne!(SecretKey::generate().unwrap().public_key(),
+               SecretKey::generate().unwrap().public_key());
+}
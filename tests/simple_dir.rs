@@ -1,7 +1,11 @@
 extern crate dir_signature;
 extern crate difference;
 
-use dir_signature::{ScannerConfig, v1};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use dir_signature::{ScannerConfig, Overlay, Error, HashType, Chunking, v1};
 use difference::assert_diff;
 
 
@@ -19,7 +23,7 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /subdir
   .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
   file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
-11928917e3e44838af46bad1c7a43a8c16eb26052997f70328d7b07ae4dd6eac
+8459d4b1c12835153c2aa695cfd33395ab3793e1e1e7d015dbdc466d56146d92
 ", "\n", 0);
 
 }
@@ -39,7 +43,87 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
 /subdir
   bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
   file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
-c23f2579827456818fc855c458d1ad7339d144b57ee247a6628e4fc8e39958bb
+f506b32f8769870afcfcc0c0880c36a8ef7c9c29959f4bd08f5b67cb720d4c81
+", "\n", 0);
+}
+
+#[test]
+fn test_dir2_file_hash() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.file_hash();
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 file_hash=1
+/
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/sub2
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir
+  bigdata.bin f 81920 9b844a86c0995a64a9cf163bcb58b8b1f2302e65b03cf5d90445078b0dba11c6 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+2d7bf6849c597db23a09ce27e401be304f1d3c8b958613b294be71066aec1572
+", "\n", 0);
+}
+
+#[test]
+fn test_dir2_dir_hash() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.dir_hash();
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 dir_hash=1
+/ 00a158475a4f6d8639195bdb3e4820b2c86a85b3c8af19aaf4415c72efc57f34
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/sub2 c11c7a4d82689e1cc45380021435f7a8f37557737599b0675d2d84d2c3c8a732
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir e7ad29f77475b09fd59cb106dfa474fd756f7d17c9fe6fde47f329d4e0841959
+  bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+e84fa307e89c34268350758af7befbd8b62c47766e91b599da39c7d437152d06
+", "\n", 0);
+}
+
+#[test]
+fn test_dir1_xattrs() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.xattrs();
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 xattrs=1
+/ -
+  hello.txt f - 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f - 0
+/subdir -
+  .hidden f - 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f - 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+ea15ee23d8d7110e1b087acfe3ad9a417cfd7e75109bee580e134c024f4b1e25
+", "\n", 0);
+}
+
+#[test]
+fn test_dir2_xattrs_dir_hash() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.dir_hash();
+    cfg.xattrs();
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 dir_hash=1 xattrs=1
+/ - 50d5ee456feae35aaad71efa9759c94a1866b69ce7c3c813fefbdf4692dfc523
+  file2.txt f - 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/sub2 - e211f79074d85b4fb41397c2d09280dd65ff9b372dc7879ee95787258571d586
+  hello.txt f - 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir - d93e4459c12f2fa31d6092eb95723146a01a2de60d31fce2831efda40dc59fc4
+  bigdata.bin f - 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f - 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+8ba9545da4e4d6156b8851dd25b4066933355f1673fe2a6e671aa89a3c9a0731
 ", "\n", 0);
 }
 
@@ -63,6 +147,432 @@ DIRSIGNATURE.v1 sha512/256 block_size=32768
   bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
   file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
   file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
-0a8f363c76c92aa58c67cae711b83b3f44d47c370cce42fae114c8d8541237cb
+4c975d0141eb617c5b2f10e746222389ce62a803965793c25e031f126698a8cf
+", "\n", 0);
+}
+
+#[test]
+fn test_dir1_dir2_threads() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.add_dir("tests/dir2", "/");
+    let mut expected = Vec::new();
+    v1::scan(&cfg, &mut expected).unwrap();
+
+    cfg.threads(4);
+    cfg.queue_size(2);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf),
+                &String::from_utf8_lossy(&expected), "\n", 0);
+}
+
+#[test]
+fn test_prefix_only() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/etc/app");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+/etc
+/etc/app
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+/etc/app/subdir
+  .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+4a6b40dc4071a4649055bb106bca8d56744aa38f4e0299d1f150bcf92279ea16
+", "\n", 0);
+}
+
+#[test]
+fn test_dir2_with_prefixed_dir1() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/etc/app");
+    cfg.add_dir("tests/dir2", "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/etc
+/etc/app
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+/etc/app/subdir
+  .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+/sub2
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir
+  bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+9f433765d8c47fc859b00f62b132b60a35abf188f71a9f181e111c79ac491433
+", "\n", 0);
+}
+
+#[test]
+fn test_overlay_last_wins() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.add_dir("tests/dir2", "/");
+    cfg.add_dir("tests/dir3", "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+  hello.txt f 8 e61ebacae17e4e6b9ec39c0673d46c7764cba7f1378208341688f201c396a159
+  sub2 s subdir
+  subdir f 10 8b2418fcbad49c1b9be3c1383fbaf405f2b8223bbe29298490227b823b004001
+  test.txt f 0
+8d0dc5604ba280add0f4956a3875a1231de20583b3045044c729c84b69f4e8f4
+", "\n", 0);
+}
+
+#[test]
+fn test_overlay_first_wins() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.add_dir("tests/dir2", "/");
+    cfg.add_dir("tests/dir3", "/");
+    cfg.overlay(Overlay::FirstWins);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+/sub2
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir
+  .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+4c975d0141eb617c5b2f10e746222389ce62a803965793c25e031f126698a8cf
+", "\n", 0);
+}
+
+#[test]
+fn test_overlay_error() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.add_dir("tests/dir3", "/");
+    cfg.overlay(Overlay::Error);
+    match v1::scan(&cfg, &mut Vec::new()) {
+        Err(Error::Conflict(ref path)) if path == Path::new("/hello.txt") => {}
+        res => panic!("Expected conflict, got {:?}", res),
+    }
+}
+
+#[test]
+fn test_overlay_shadowed_dir() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.add_dir("tests/dir3", "/");
+    cfg.add_dir("tests/dir2", "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+  hello.txt f 8 e61ebacae17e4e6b9ec39c0673d46c7764cba7f1378208341688f201c396a159
+  test.txt f 0
+/sub2
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir
+  bigdata.bin f 81920 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 768007e06b0cd9e62d50f458b9435c6dda0a6d272f0b15550f97c478394b7433 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+93d9f7aa750a15536e0969f99c750592cdcb25c9d868512603f3e24789e66aab
+", "\n", 0);
+}
+
+#[test]
+fn test_overlay_prefix_conflict() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.add_dir("tests/dir2", "/hello.txt/sub");
+    match v1::scan(&cfg, &mut Vec::new()) {
+        Err(Error::Conflict(ref path)) if path == Path::new("/hello.txt") => {}
+        res => panic!("Expected conflict, got {:?}", res),
+    }
+}
+
+#[test]
+fn test_exclude() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.exclude("/subdir");
+    cfg.exclude("hello.txt");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/sub2
+92abb239a8b94a0620f32d23d28a9273fca4642b21c22571134a94e2f55a4c5a
+", "\n", 0);
+}
+
+#[test]
+fn test_exclude_include() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.exclude("*.txt");
+    cfg.include("file.txt");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+/subdir
+  .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+d0e7825f1a77fdb9acf32d7a6a3639ab3f3e2ace07ea856c8dcb6093b2ebac94
+", "\n", 0);
+}
+
+#[test]
+fn test_ignore_file() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir4", "/");
+    cfg.ignore_file(".dirsignore");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  .dirsignore f 6 47a9fdbb368a930c1cadc69416a6c7bdfb7b6d9d9ede97083ad8e2bcac1a3d5c
+  keep.txt f 5 84cc484cdb268e90ba68f298af5eda8d0bb18e8925e489fc3a38b7ec7b8c8bb9
+/sub
+  .dirsignore f 15 7346bbffd05cb4b55829732ded1c9a06a8ded6d2e7a9c070f357d630156a5e20
+  important.log f 10 ff7682dc6a807e3f3fe7fdddf990b4a3fee76b0e8639dd463248280ea4877ca5
+0670ad4008fc601ba3e5e92a4a4d5b567c6d5a3604994834c9ba8a09d0a3060d
+", "\n", 0);
+}
+
+#[test]
+fn test_unreadable_ignore_file() {
+    let dir = Path::new("target/test-ignore-file");
+    fs::remove_dir_all(dir).ok();
+    fs::create_dir_all(dir.join("sub/.dirsignore")).unwrap();
+    File::create(dir.join(".dirsignore")).unwrap()
+        .write_all(b"*.log\n\xff\n").unwrap();
+    File::create(dir.join("debug.log")).unwrap();
+    File::create(dir.join("sub/debug.log")).unwrap();
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    cfg.ignore_file(".dirsignore");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+  .dirsignore f 8 fc92269cbd5b3c3caaa9cb2517b4e7e60b00f4afa206488a4fa9aab4d8357753
+  debug.log f 0
+/sub
+  debug.log f 0
+/sub/.dirsignore
+55d80b29d374ec82bb622694677bb44958362e98d5aa7d3de6a5769be2a0d193
+", "\n", 0);
+}
+
+#[test]
+fn test_bad_pattern() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.exclude("[z-a].txt");
+    let mut buf = Vec::new();
+    match v1::scan(&cfg, &mut buf) {
+        Err(Error::BadPattern(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_header_keys() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.header_key("origin", "ci");
+    cfg.header_key("build_id", "42");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=32768 build_id=42 origin=ci
+/
+  hello.txt f 6 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+/subdir
+  .hidden f 7 24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f 10 9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+5385922324a39b384838edc850eaa8dc62b7177bfa2fad4ebeaea9f2c49bb2d8
+", "\n", 0);
+
+    // the pairs are accounted in the footer
+    let mut plain_cfg = ScannerConfig::new();
+    plain_cfg.add_dir("tests/dir1", "/");
+    let mut plain = Vec::new();
+    v1::scan(&plain_cfg, &mut plain).unwrap();
+    let footer = |buf: &[u8]| String::from_utf8_lossy(buf)
+        .lines().last().unwrap().to_string();
+    assert_ne!(footer(&buf), footer(&plain));
+}
+
+#[test]
+fn test_bad_header_key() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.header_key("build id", "42");
+    let mut buf = Vec::new();
+    match v1::scan(&cfg, &mut buf) {
+        Err(Error::BadHeaderKey(ref key, _)) if key == "build id" => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_block_size() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.block_size(16384).unwrap();
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=16384
+/
+  file2.txt f 18 c4cadd1e2e2aded1cdb2ba48fdfe8a831d9236042aec16472725d45b001c1ad5
+/sub2
+  hello.txt f 6 e0494295cc1dfdd443d09f81913881a112745174778cc0c224ccc7137024fe41
+/subdir
+  bigdata.bin f 81920 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133 6eb7f16cf7afcabe9bdea88bdab0469a7937eb715ada9dfd8f428d9d38d86133
+  file3.txt f 12 b130fa20a2ba5a3d9976e6c15e8a59ad9e5cbbc52536a4458952872cda5c218d
+d55b329e4b0b0d11fc1fb3dfa4ec7a0ddf0e71f6cfb18ce44ad398059d7c5cc2
+", "\n", 0);
+}
+
+#[test]
+fn test_bad_block_size() {
+    for &size in &[0, 1000, 256, 128 << 20] {
+        let mut cfg = ScannerConfig::new();
+        match cfg.block_size(size) {
+            Err(Error::BadBlockSize(s)) if s == size => {}
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("block size {} is accepted", size),
+        }
+    }
+}
+
+#[test]
+fn test_dir1_fastcdc() {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir1", "/");
+    cfg.block_size(512).unwrap();
+    cfg.chunking(Chunking::FastCdc);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=512 chunking=fastcdc
+/
+  hello.txt f 6 6:8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc
+  test.txt f 0
+/subdir
+  .hidden f 7 7:24f72d3a930b5f7933ddd91a5c7cb7ba09a093f936a04bf6486c8b1763c59819
+  file.txt f 10 10:9ce28248299290fe84340d7821adf01b3b6a579ef827e1e58bc3949de4b7e5d9
+1918fa941391066447d5968f3fd4e3138d07c2a19595826232e963c5fa6b66c4
+", "\n", 0);
+}
+
+#[test]
+fn test_fastcdc_multiple_chunks() {
+    let dir = Path::new("target/test-fastcdc");
+    fs::remove_dir_all(dir).ok();
+    fs::create_dir_all(dir).unwrap();
+    let mut state = 1u32;
+    let data = (0..10000).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect::<Vec<u8>>();
+    File::create(dir.join("data.bin")).unwrap().write_all(&data).unwrap();
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    cfg.block_size(512).unwrap();
+    cfg.chunking(Chunking::FastCdc);
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha512/256 block_size=512 chunking=fastcdc
+/
+  data.bin f 10000 535:302dc477594f9c2ddeb1fd553bdb456834c2fe599d8607bddfe114a1b65392d0 1009:19cd5b13623065c933d1318c3b162e0a3bfd9b34b1d0a9d8c967c72ac11a7fe8 620:c18df14643235e6f457ed5c6300417ea46666efda9639e25c86f6a7ed51edf43 142:cfe8e11b074c901f85f37c7e2814559227b0db9cb0726ab6067fed83aab75c15 474:45ccc21dfb9e32185d76d6cfde0134ed2297d51299596607f1cf2cb16c1c7161 1837:01882fe3bcaed2510d5b735422e70a14c8538ccb6234e72dcb56c06ff8dfa954 793:103463183f55d5f64465875aba84ad2114840a3ad24f3e681f70dcb54240627f 1416:afc4f34d7f418f37b44d162c2e1d72ca452115d3db17e0a4fba64c0f0e1a5a74 916:9a2c1e2cc34cb1e60ff1ed641359b28ed219183db1a9d0b2528bd3ef12c18e66 600:044dad26507fa2c3c4d5d5b52f8d2bc8a3cc25155fd610d8bb36aacf4fff974d 1143:3a57cdcfcc26c62804341f08f6c27159630808589733b1d9a4e86b813d3c0b34 515:ce1af7ea7a9179e9e423403fcd3269c49856194be0162221c903f09bcd12511a
+2cab60c6bb526b240f3a84bb447ca7ccf0b6fb6f0106d5d4e8b6b26743f1c560
+", "\n", 0);
+}
+
+#[test]
+fn test_dir1_blake3() {
+    let mut cfg = ScannerConfig::new();
+    cfg.hash(HashType::Blake3_256);
+    cfg.add_dir("tests/dir1", "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 blake3/256 block_size=32768
+/
+  hello.txt f 6 cd48250d675584e374285667d9c1233b5aa4129afeea215dd47c169aa848d567
+  test.txt f 0
+/subdir
+  .hidden f 7 7c35189aed1a1fc6211843259bb0a5e584d4cbf490f23ddd22e19d23699eb0b5
+  file.txt f 10 9f974bb87d97e1a7b4dcb5ccfdc9e8afa13ab3548f459a828f77e2a9ea7383b2
+83ff6ef7b4d458026c3a629b973cef4e16f7e3443c4752c8f378d4293ed414d2
+", "\n", 0);
+}
+
+#[test]
+fn test_dir2_blake3() {
+    let mut cfg = ScannerConfig::new();
+    cfg.hash(HashType::Blake3_256);
+    cfg.add_dir("tests/dir2", "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 blake3/256 block_size=32768
+/
+  file2.txt f 18 99b6111fa45d4c059be2ca5b1b385386aeb1e97ea5c904a52129fb83540fc83b
+/sub2
+  hello.txt f 6 26e70f0a438787ee143979a9b519a4a330ea21e0a23d31fcb47051e70b8fe5ad
+/subdir
+  bigdata.bin f 81920 ac169ead597dac88b2d7223edd85c9895392532cfc7a3c5c29a3fbe3ccba37f2 ac169ead597dac88b2d7223edd85c9895392532cfc7a3c5c29a3fbe3ccba37f2 111f6c2f2ac0fc43154414a6e3e4c104cb04907e9453d3ac85cc5f55cc015b48
+  file3.txt f 12 1bf82b88aa13e45ffa7bf078480cbb0dd01d3f28305b95f1cd95687f99bcef5b
+a8a60325c589b4194c6ecd5ef0064a0dee73b1af9a733638aad66a1dea624cc6
+", "\n", 0);
+}
+
+#[test]
+fn test_dir2_sha256() {
+    let mut cfg = ScannerConfig::new();
+    cfg.hash(HashType::Sha256);
+    cfg.add_dir("tests/dir2", "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    assert_diff(&String::from_utf8_lossy(&buf), "\
+DIRSIGNATURE.v1 sha256 block_size=32768
+/
+  file2.txt f 18 46d655dc52a164dab259931e480f2b46296d8bb363e9f7aa03cd484269b9ba68
+/sub2
+  hello.txt f 6 e258d248fda94c63753607f7c4494ee0fcbe92f1a76bfdac795c9d84101eb317
+/subdir
+  bigdata.bin f 81920 c35020473aed1b4642cd726cad727b63fff2824ad68cedd7ffb73c7cbd890479 c35020473aed1b4642cd726cad727b63fff2824ad68cedd7ffb73c7cbd890479 4fe7b59af6de3b665b67788cc2f99892ab827efae3a467342b3bb4e3bc8e5bfe
+  file3.txt f 12 c78666ac8ba7cc55521f99db0d85396e72857908d400ddbae0cab210358717d3
+125e5d2e7c58fe546f9034582fd98b22c9968895548b35af5799a0359882d186
 ", "\n", 0);
 }
@@ -0,0 +1,169 @@
+extern crate dir_signature;
+#[macro_use] extern crate matches;
+
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Write};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use dir_signature::{ScannerConfig, Chunking, v1};
+use dir_signature::v1::{Parser, SyncError};
+use dir_signature::v1::merge::MergedSignatures;
+
+type Reader<'a> = BufReader<Cursor<&'a [u8]>>;
+
+fn copy_dir(src: &Path, dst: &Path) {
+    fs::create_dir_all(dst).unwrap();
+    for entry in fs::read_dir(src).unwrap() {
+        let entry = entry.unwrap();
+        let dst = dst.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &dst);
+        } else {
+            fs::copy(entry.path(), dst).unwrap();
+        }
+    }
+}
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = Path::new("target/test-sync").join(name);
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn index(dirs: &[&Path]) -> Vec<u8> {
+    let mut cfg = ScannerConfig::new();
+    for dir in dirs {
+        cfg.add_dir(dir, "/");
+    }
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    buf
+}
+
+fn parser(data: &[u8]) -> Parser<Reader> {
+    Parser::new(BufReader::new(Cursor::new(data))).unwrap()
+}
+
+fn sync<'a>(target: &'a [u8], sources: Vec<(&'a Path, &'a [u8])>,
+    dest: &Path)
+    -> Result<(), SyncError>
+{
+    let mut sources = MergedSignatures::new(sources.into_iter()
+        .map(|(path, idx)| (path, parser(idx)))).unwrap();
+    v1::sync(&mut parser(target), &mut sources, dest)
+}
+
+fn assert_clean(target: &[u8], dest: &Path) {
+    let report = v1::verify(&mut parser(target), dest).unwrap();
+    assert_eq!(report.problems(), &[]);
+}
+
+#[test]
+fn test_sync_to_empty_dir() {
+    let dest = work_dir("empty");
+    let src = Path::new("tests/dir2");
+    let idx = index(&[src]);
+    sync(&idx, vec![(src, &idx)], &dest).unwrap();
+    assert_clean(&idx, &dest);
+}
+
+#[test]
+fn test_sync_update() {
+    let work = work_dir("update");
+    let src = work.join("src");
+    let dest = work.join("dest");
+    copy_dir(Path::new("tests/dir2"), &src);
+    File::create(src.join("run.sh")).unwrap()
+        .write_all(b"#!/bin/sh\n").unwrap();
+    fs::set_permissions(src.join("run.sh"), PermissionsExt::from_mode(0o755))
+        .unwrap();
+    symlink("subdir/file3.txt", src.join("link")).unwrap();
+
+    copy_dir(Path::new("tests/dir1"), &dest);
+    fs::create_dir(dest.join("sub2")).unwrap();
+    File::create(dest.join("sub2/hello.txt")).unwrap()
+        .write_all(b"hello\nworld\n").unwrap();
+    fs::create_dir(dest.join("file2.txt")).unwrap();
+    symlink("nowhere", dest.join("link")).unwrap();
+
+    let dir1 = Path::new("tests/dir1");
+    let dir1_idx = index(&[dir1]);
+    let src_idx = index(&[&src]);
+    let target = index(&[dir1, &src]);
+    sync(&target, vec![(dir1, &dir1_idx), (&src, &src_idx)], &dest).unwrap();
+    assert_clean(&target, &dest);
+    assert_eq!(fs::read_link(dest.join("link")).unwrap(),
+               Path::new("subdir/file3.txt"));
+
+    // all blocks are found in the destination directory
+    let target = index(&[&src]);
+    sync(&target, vec![], &dest).unwrap();
+    assert_clean(&target, &dest);
+    assert!(!dest.join("hello.txt").exists());
+}
+
+/// Pseudo-random data which chunks differently from zeroes
+fn random_data(len: usize) -> Vec<u8> {
+    let mut state = 1u32;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect()
+}
+
+#[test]
+fn test_sync_fastcdc_insertion() {
+    let work = work_dir("fastcdc");
+    let src = work.join("src");
+    let dest = work.join("dest");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&dest).unwrap();
+    let data = random_data(65536);
+    File::create(dest.join("data.bin")).unwrap().write_all(&data).unwrap();
+    let mut changed = b"0123456789".to_vec();
+    changed.extend_from_slice(&data);
+    File::create(src.join("data.bin")).unwrap().write_all(&changed).unwrap();
+
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(&src, "/");
+    cfg.block_size(1024).unwrap();
+    cfg.chunking(Chunking::FastCdc);
+    let mut target = Vec::new();
+    v1::scan(&cfg, &mut target).unwrap();
+    // only the first chunk differs, so the rest of the source isn't needed
+    File::create(src.join("data.bin")).unwrap()
+        .write_all(&changed[..4096]).unwrap();
+
+    sync(&target, vec![(&src, &target)], &dest).unwrap();
+    assert_clean(&target, &dest);
+}
+
+#[test]
+fn test_sync_missing_block() {
+    let dest = work_dir("missing");
+    let idx = index(&[Path::new("tests/dir1")]);
+    let res = sync(&idx, vec![], &dest);
+    assert!(matches!(res, Err(SyncError::MissingBlock(ref p, 0))
+                          if p == Path::new("/hello.txt")),
+            "Was: {:?}", res);
+    // nothing is left in place of the unfinished file
+    assert_eq!(fs::read_dir(&dest).unwrap().count(), 0);
+}
+
+#[test]
+fn test_sync_invalid_path() {
+    let dest = work_dir("invalid");
+    let target = b"\
+DIRSIGNATURE.v1 sha512/256 block_size=32768
+/
+/../escape
+04f12b523b71c5721c6083926e5423bf2fe28f7b84a19642c7a3ac953da81b3a
+";
+    let res = sync(target, vec![], &dest);
+    assert!(matches!(res, Err(SyncError::InvalidPath(_))),
+            "Was: {:?}", res);
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::io::{BufReader, Cursor};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+#[macro_use] extern crate matches;
+
+extern crate dir_signature;
+use dir_signature::{ScannerConfig, HashType, get_hash, v1, v2};
+use dir_signature::v2::{Entry, Parser, ParseError};
+use dir_signature::v2::merge::{MergedSignatures, MergeError};
+
+
+fn scan_v2(dir: &str) -> Vec<u8> {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    let mut buf = Vec::new();
+    v2::scan(&cfg, &mut buf).unwrap();
+    buf
+}
+
+fn scan_v1(dir: &str) -> Vec<u8> {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    buf
+}
+
+fn parse_v2(data: &[u8]) -> Vec<Entry> {
+    let mut parser = Parser::new(BufReader::new(data)).unwrap();
+    parser.iter().collect::<Result<_, _>>().unwrap()
+}
+
+fn check_metadata(root: &str, entry: &Entry) {
+    let path = Path::new(root).join(entry.path().strip_prefix("/").unwrap());
+    let stat = fs::symlink_metadata(&path).unwrap();
+    let meta = entry.metadata();
+    assert_eq!(meta.mode(), stat.mode() & 0o7777, "{:?}", path);
+    assert_eq!(meta.uid(), stat.uid(), "{:?}", path);
+    assert_eq!(meta.gid(), stat.gid(), "{:?}", path);
+    assert_eq!(meta.mtime(), (stat.mtime(), stat.mtime_nsec() as u32),
+        "{:?}", path);
+}
+
+#[test]
+fn test_dir1() {
+    let buf = scan_v2("tests/dir1");
+    assert!(buf.starts_with(b"DIRSIGNATURE.v2 sha512/256 block_size=32768\n"));
+    // the index doesn't depend on the scan
+    assert_eq!(buf, scan_v2("tests/dir1"));
+
+    let entries = parse_v2(&buf);
+    let paths = entries.iter().map(|e| e.path()).collect::<Vec<_>>();
+    assert_eq!(paths, vec![
+        Path::new("/"),
+        Path::new("/hello.txt"),
+        Path::new("/test.txt"),
+        Path::new("/subdir"),
+        Path::new("/subdir/.hidden"),
+        Path::new("/subdir/file.txt"),
+    ]);
+    for entry in &entries {
+        check_metadata("tests/dir1", entry);
+    }
+    match entries[1] {
+        Entry::File { size, ref hashes, .. } => {
+            assert_eq!(size, 6);
+            assert_eq!(hashes.len(), 1);
+        }
+        ref entry => panic!("Expected file, found {:?}", entry),
+    }
+    assert!(matches!(entries[2],
+        Entry::File { size: 0, ref hashes, .. } if hashes.len() == 0));
+}
+
+#[test]
+fn test_same_as_v1() {
+    let buf = scan_v2("tests/dir2");
+    assert!(buf.len() < scan_v1("tests/dir2").len());
+
+    let v1_data = scan_v1("tests/dir2");
+    let mut v1_parser = v1::Parser::new(
+        BufReader::new(Cursor::new(&v1_data[..]))).unwrap();
+    let v1_entries = v1_parser.iter()
+        .collect::<Result<Vec<_>, _>>().unwrap();
+    let entries = parse_v2(&buf);
+    assert_eq!(entries.len(), v1_entries.len());
+    for (entry, v1_entry) in entries.iter().zip(&v1_entries) {
+        check_metadata("tests/dir2", entry);
+        match (entry, v1_entry) {
+            (&Entry::Dir { ref path, .. },
+             &v1::Entry::Dir(ref v1_path, _)) => {
+                assert_eq!(path, v1_path);
+            }
+            (&Entry::File { ref path, size, ref hashes, .. },
+             &v1::Entry::File { path: ref v1_path, size: v1_size,
+                                hashes: ref v1_hashes, .. }) => {
+                assert_eq!(path, v1_path);
+                assert_eq!(size, v1_size);
+                assert!(hashes.iter().eq(v1_hashes.iter()));
+            }
+            _ => panic!("Entries differ: {:?} {:?}", entry, v1_entry),
+        }
+    }
+}
+
+#[test]
+fn test_footer() {
+    let buf = scan_v2("tests/dir2");
+    let mut parser = Parser::new(BufReader::new(&buf[..])).unwrap();
+    assert!(parser.get_footer().is_none());
+    assert_eq!(parser.iter().count(), 7);
+    let footer = parser.get_footer().unwrap().to_vec();
+    assert_eq!(footer.len(), 32);
+    assert_eq!(get_hash(&mut Cursor::new(&buf[..])).unwrap(), footer);
+}
+
+#[test]
+fn test_truncated() {
+    let buf = scan_v2("tests/dir2");
+    let truncated = &buf[..buf.len()-1];
+    let mut parser = Parser::new(BufReader::new(truncated)).unwrap();
+    let result = parser.iter().collect::<Result<Vec<_>, _>>();
+    assert!(matches!(result, Err(ParseError::Io(_))));
+
+    let mut changed = buf.clone();
+    let len = changed.len();
+    changed[len-1] ^= 1;
+    let mut parser = Parser::new(BufReader::new(&changed[..])).unwrap();
+    let result = parser.iter().collect::<Result<Vec<_>, _>>();
+    assert!(matches!(result, Err(ParseError::ChecksumMismatch)));
+}
+
+#[test]
+fn test_merge() {
+    let dir1 = scan_v2("tests/dir1");
+    let dir2 = scan_v2("tests/dir2");
+    let parsers = vec![
+        (PathBuf::from("/1"), Parser::new(BufReader::new(&dir1[..])).unwrap()),
+        (PathBuf::from("/2"), Parser::new(BufReader::new(&dir2[..])).unwrap()),
+    ];
+    let mut merged = MergedSignatures::new(parsers).unwrap();
+    assert_eq!(merged.hash_type(), Some(HashType::Sha512_256));
+    assert_eq!(merged.block_size(), Some(32768));
+    let rows = merged.iter()
+        .map(|row| {
+            let keys = row.iter().map(|&(k, _)| k.clone()).collect::<Vec<_>>();
+            let path = row[0].1.as_ref().unwrap().path().to_path_buf();
+            (path, keys)
+        })
+        .collect::<Vec<_>>();
+    let one = vec![PathBuf::from("/1")];
+    let two = vec![PathBuf::from("/2")];
+    let both = vec![PathBuf::from("/1"), PathBuf::from("/2")];
+    assert_eq!(rows, vec![
+        (PathBuf::from("/"), both.clone()),
+        (PathBuf::from("/file2.txt"), two.clone()),
+        (PathBuf::from("/hello.txt"), one.clone()),
+        (PathBuf::from("/test.txt"), one.clone()),
+        (PathBuf::from("/sub2"), two.clone()),
+        (PathBuf::from("/sub2/hello.txt"), two.clone()),
+        (PathBuf::from("/subdir"), both.clone()),
+        (PathBuf::from("/subdir/.hidden"), one.clone()),
+        (PathBuf::from("/subdir/bigdata.bin"), two.clone()),
+        (PathBuf::from("/subdir/file.txt"), one.clone()),
+        (PathBuf::from("/subdir/file3.txt"), two.clone()),
+    ]);
+}
+
+#[test]
+fn test_merge_block_sizes() {
+    let dir1 = scan_v2("tests/dir1");
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir("tests/dir2", "/");
+    cfg.block_size(65536).unwrap();
+    let mut dir2 = Vec::new();
+    v2::scan(&cfg, &mut dir2).unwrap();
+    let parsers = vec![
+        (1, Parser::new(BufReader::new(&dir1[..])).unwrap()),
+        (2, Parser::new(BufReader::new(&dir2[..])).unwrap()),
+    ];
+    assert!(matches!(MergedSignatures::new(parsers),
+        Err(MergeError::BlockSizesMismatch(ref sizes))
+        if sizes == &[32768, 65536]));
+}
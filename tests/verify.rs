@@ -0,0 +1,216 @@
+extern crate dir_signature;
+extern crate libc;
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use dir_signature::{Error, ScannerConfig, Xattrs, v1};
+use dir_signature::v1::{Problem, EntryType};
+
+fn copy_dir(src: &Path, dst: &Path) {
+    fs::create_dir_all(dst).unwrap();
+    for entry in fs::read_dir(src).unwrap() {
+        let entry = entry.unwrap();
+        let dst = dst.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &dst);
+        } else {
+            fs::copy(entry.path(), dst).unwrap();
+        }
+    }
+}
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = Path::new("target/test-verify").join(name);
+    fs::remove_dir_all(&dir).ok();
+    copy_dir(Path::new("tests/dir1"), &dir);
+    dir
+}
+
+fn index(dir: &Path) -> Vec<u8> {
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(dir, "/");
+    let mut buf = Vec::new();
+    v1::scan(&cfg, &mut buf).unwrap();
+    buf
+}
+
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let name = CString::new(name).unwrap();
+    let res = unsafe {
+        libc::setxattr(path.as_ptr(), name.as_ptr(),
+            value.as_ptr() as *const libc::c_void, value.len(), 0)
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn verify(index: &[u8], dir: &Path) -> v1::Report {
+    let mut parser = v1::Parser::new(BufReader::new(Cursor::new(index)))
+        .unwrap();
+    v1::verify(&mut parser, dir).unwrap()
+}
+
+#[test]
+fn test_clean() {
+    let dir = work_dir("clean");
+    let report = verify(&index(&dir), &dir);
+    assert!(report.is_clean());
+    assert_eq!(report.problems(), &[]);
+}
+
+#[test]
+fn test_problems() {
+    let dir = work_dir("problems");
+    let idx = index(&dir);
+
+    File::create(dir.join("hello.txt")).unwrap()
+        .write_all(b"HELLO\n").unwrap();
+    fs::remove_file(dir.join("test.txt")).unwrap();
+    fs::create_dir(dir.join("test.txt")).unwrap();
+    File::create(dir.join("test.txt/x")).unwrap();
+    symlink("hello.txt", dir.join("link")).unwrap();
+    fs::remove_file(dir.join("subdir/.hidden")).unwrap();
+    let file = dir.join("subdir/file.txt");
+    File::create(&file).unwrap().write_all(b"changed it\n").unwrap();
+    fs::set_permissions(&file, PermissionsExt::from_mode(0o755)).unwrap();
+
+    let report = verify(&idx, &dir);
+    assert!(!report.is_clean());
+    assert_eq!(report.problems(), &[
+        Problem::HashMismatch {
+            path: PathBuf::from("/hello.txt"),
+            blocks: vec![0],
+        },
+        Problem::Extra {
+            path: PathBuf::from("/link"),
+            typ: EntryType::Symlink,
+        },
+        Problem::TypeChanged {
+            path: PathBuf::from("/test.txt"),
+            expected: EntryType::File,
+            found: EntryType::Dir,
+        },
+        Problem::Missing {
+            path: PathBuf::from("/subdir/.hidden"),
+            typ: EntryType::File,
+        },
+        Problem::ExeChanged {
+            path: PathBuf::from("/subdir/file.txt"),
+            expected: false,
+        },
+        Problem::SizeChanged {
+            path: PathBuf::from("/subdir/file.txt"),
+            expected: 10,
+            found: 11,
+        },
+        Problem::Extra {
+            path: PathBuf::from("/test.txt/x"),
+            typ: EntryType::File,
+        },
+    ][..]);
+}
+
+#[test]
+fn test_missing_dir() {
+    let dir = work_dir("missing_dir");
+    let idx = index(&dir);
+    fs::remove_dir_all(dir.join("subdir")).unwrap();
+    let report = verify(&idx, &dir);
+    assert_eq!(report.problems(), &[
+        Problem::Missing {
+            path: PathBuf::from("/subdir"),
+            typ: EntryType::Dir,
+        },
+        Problem::Missing {
+            path: PathBuf::from("/subdir/.hidden"),
+            typ: EntryType::File,
+        },
+        Problem::Missing {
+            path: PathBuf::from("/subdir/file.txt"),
+            typ: EntryType::File,
+        },
+    ][..]);
+}
+
+#[test]
+fn test_xattrs() {
+    let dir = work_dir("xattrs");
+    let file = dir.join("hello.txt");
+    match set_xattr(&file, "user.test", b"1") {
+        Ok(()) => {}
+        // filesystem has no support for user attributes
+        Err(ref e) if e.raw_os_error() == Some(libc::ENOTSUP) => return,
+        Err(e) => panic!("Can't set attribute: {}", e),
+    }
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(&dir, "/");
+    cfg.xattrs();
+    let mut idx = Vec::new();
+    v1::scan(&cfg, &mut idx).unwrap();
+    assert!(verify(&idx, &dir).is_clean());
+
+    set_xattr(&file, "user.test", b"2").unwrap();
+    set_xattr(&dir.join("subdir"), "user.test", b"3").unwrap();
+    let value = |v: &[u8]| Xattrs::new(vec![("user.test".into(), v.to_vec())]);
+    let report = verify(&idx, &dir);
+    assert_eq!(report.problems(), &[
+        Problem::XattrsChanged {
+            path: PathBuf::from("/hello.txt"),
+            expected: value(b"1"),
+            found: value(b"2"),
+        },
+        Problem::XattrsChanged {
+            path: PathBuf::from("/subdir"),
+            expected: Xattrs::default(),
+            found: value(b"3"),
+        },
+    ][..]);
+}
+
+#[test]
+fn test_file_hash() {
+    let dir = work_dir("file_hash");
+    let mut cfg = ScannerConfig::new();
+    cfg.add_dir(&dir, "/");
+    cfg.file_hash();
+    let mut idx = Vec::new();
+    v1::scan(&cfg, &mut idx).unwrap();
+    assert!(verify(&idx, &dir).is_clean());
+
+    // block hash is the same as the whole-file one, only the latter changed
+    let changed = String::from_utf8(idx).unwrap().replacen(
+        " 8dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc",
+        " 0dd499a36d950b8732f85a3bffbc8d8bee4a0af391e8ee2bb0aa0c4553b6c0fc",
+        1);
+    let mut parser = v1::Parser::new(
+        BufReader::new(Cursor::new(changed.as_bytes()))).unwrap();
+    parser.skip_checksum();
+    let report = v1::verify(&mut parser, &dir).unwrap();
+    assert_eq!(report.problems(), &[
+        Problem::FileHashMismatch {
+            path: PathBuf::from("/hello.txt"),
+        },
+    ][..]);
+}
+
+#[test]
+fn test_broken_index() {
+    let dir = work_dir("broken_index");
+    let broken = String::from_utf8(index(&dir)).unwrap()
+        .replace("hello.txt f 6", "hello.txt f six");
+    let mut parser = v1::Parser::new(
+        BufReader::new(Cursor::new(broken.as_bytes()))).unwrap();
+    match v1::verify(&mut parser, &dir) {
+        Err(Error::VerifyIndex(_)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(report) => panic!("unexpected report {:?}", report.problems()),
+    }
+}